@@ -0,0 +1,98 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+use std::hint::black_box;
+
+use ancs_protocol::control_point::NotificationAttributeID;
+use ancs_protocol::data_source::{Expected, Progress};
+use ancs_protocol::data_source::{NotificationAttributes, NotificationAttributesView};
+
+// A notification attributes response for notification 7 with a message of `message_length` bytes,
+// cut in the middle of a character like a maximum length does
+fn response(message_length: usize) -> Vec<u8> {
+    let message: Vec<u8> = "Grüße aus Köln! "
+        .bytes()
+        .cycle()
+        .take(message_length)
+        .collect();
+    let message_size = (message_length + 1).to_string();
+    let attributes = [
+        (
+            NotificationAttributeID::AppIdentifier,
+            b"com.apple.MobileSMS".as_slice(),
+        ),
+        (NotificationAttributeID::Title, b"Alice"),
+        (NotificationAttributeID::Subtitle, b"Family"),
+        (NotificationAttributeID::Message, &message),
+        (
+            NotificationAttributeID::MessageSize,
+            message_size.as_bytes(),
+        ),
+        (NotificationAttributeID::Date, b"20240131T093005"),
+    ];
+    let mut buffer = vec![0, 7, 0, 0, 0];
+    for (attribute, value) in attributes {
+        buffer.push(attribute as u8);
+        buffer.extend((value.len() as u16).to_le_bytes());
+        buffer.extend(value);
+    }
+    buffer
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for message_length in [64, 1024, 16 * 1024, 60 * 1024] {
+        let buffer = response(message_length);
+        group.throughput(Throughput::Bytes(buffer.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("view", message_length),
+            &buffer,
+            |b, buffer| b.iter(|| NotificationAttributesView::parse(black_box(buffer))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("owned", message_length),
+            &buffer,
+            |b, buffer| {
+                b.iter_batched(
+                    || buffer.clone(),
+                    NotificationAttributes::from_buffer,
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+// Data Source notifications of a 23 byte MTU put together the way the client does, then parsed
+fn fragmented(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fragmented");
+    let expected = Expected::Notification {
+        notification_id: 7,
+        attributes: 6,
+    };
+    for message_length in [1024, 16 * 1024] {
+        let buffer = response(message_length);
+        let fragments: Vec<Vec<u8>> = buffer.chunks(20).map(<[u8]>::to_vec).collect();
+        group.throughput(Throughput::Bytes(buffer.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(message_length),
+            &fragments,
+            |b, fragments| {
+                b.iter(|| {
+                    let mut response = Vec::new();
+                    for fragment in fragments {
+                        response.extend_from_slice(fragment);
+                        if expected.progress(&response) == Progress::Complete {
+                            break;
+                        }
+                    }
+                    NotificationAttributesView::parse(&response).into_owned()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, fragmented);
+criterion_main!(benches);
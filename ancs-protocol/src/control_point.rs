@@ -0,0 +1,631 @@
+#![allow(unused)]
+
+use uuid::Uuid;
+
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// UUID for characteristic
+pub const CONTROL_POINT_UUID: Uuid = Uuid::from_u128(0x69D1D8F345E149A898219BBDFDAAD9D9);
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CategoryID {
+    Other,
+    IncomingCall,
+    MissedCall,
+    Voicemail,
+    Social,
+    Schedule,
+    Email,
+    News,
+    HealthAndFitness,
+    BusinessAndFinance,
+    Location,
+    Entertainment,
+}
+
+impl TryFrom<u8> for CategoryID {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(CategoryID::Other),
+            1 => Ok(CategoryID::IncomingCall),
+            2 => Ok(CategoryID::MissedCall),
+            3 => Ok(CategoryID::Voicemail),
+            4 => Ok(CategoryID::Social),
+            5 => Ok(CategoryID::Schedule),
+            6 => Ok(CategoryID::Email),
+            7 => Ok(CategoryID::News),
+            8 => Ok(CategoryID::HealthAndFitness),
+            9 => Ok(CategoryID::BusinessAndFinance),
+            10 => Ok(CategoryID::Location),
+            11 => Ok(CategoryID::Entertainment),
+            _ => Err(value),
+        }
+    }
+}
+
+// Parses the variant name as used in the config file, e.g. "IncomingCall"
+impl FromStr for CategoryID {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..=CategoryID::Entertainment as u8)
+            .filter_map(|id| CategoryID::try_from(id).ok())
+            .find(|category| format!("{:?}", category).eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown category: {}", s))
+    }
+}
+
+// Name of a category id for display, ids newer than this implementation are kept as numbers
+pub fn category_name(category_id: u8) -> String {
+    match CategoryID::try_from(category_id) {
+        Ok(category) => format!("{:?}", category),
+        Err(id) => format!("Unknown({})", id),
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Debug)]
+#[allow(clippy::enum_variant_names)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EventID {
+    NotificationAdded,
+    NotificationModified,
+    NotificationRemoved,
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EventFlag {
+    Silent = 1 << 0,
+    Important = 1 << 1,
+    PreExisting = 1 << 2,
+    PositiveAction = 1 << 3,
+    NegativeAction = 1 << 4,
+}
+
+impl EventFlag {
+    pub const ALL: [EventFlag; 5] = [
+        EventFlag::Silent,
+        EventFlag::Important,
+        EventFlag::PreExisting,
+        EventFlag::PositiveAction,
+        EventFlag::NegativeAction,
+    ];
+
+    pub fn is_set(self, event_flags: u8) -> bool {
+        event_flags & self as u8 != 0
+    }
+}
+
+// Names of the flags set in `event_flags`, reserved bits are left out
+pub fn event_flag_names(event_flags: u8) -> Vec<String> {
+    EventFlag::ALL
+        .into_iter()
+        .filter(|flag| flag.is_set(event_flags))
+        .map(|flag| format!("{:?}", flag))
+        .collect()
+}
+
+#[repr(u8)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CommandID {
+    GetNotificationAttributes,
+    GetAppAttributes,
+    PerformNotificationAction,
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NotificationAttributeID {
+    AppIdentifier,
+    Title,
+    Subtitle,
+    Message,
+    MessageSize,
+    Date,
+    PositiveActionLabel,
+    NegativeActionLabel,
+}
+
+impl NotificationAttributeID {
+    pub const ALL: [NotificationAttributeID; 8] = [
+        NotificationAttributeID::AppIdentifier,
+        NotificationAttributeID::Title,
+        NotificationAttributeID::Subtitle,
+        NotificationAttributeID::Message,
+        NotificationAttributeID::MessageSize,
+        NotificationAttributeID::Date,
+        NotificationAttributeID::PositiveActionLabel,
+        NotificationAttributeID::NegativeActionLabel,
+    ];
+
+    // Title, Subtitle and Message are requested with a maximum length
+    pub fn has_max_length(self) -> bool {
+        self == NotificationAttributeID::Title
+            || self == NotificationAttributeID::Subtitle
+            || self == NotificationAttributeID::Message
+    }
+}
+
+// Parses the variant name as used in the config file, e.g. "Message"
+impl FromStr for NotificationAttributeID {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NotificationAttributeID::ALL
+            .into_iter()
+            .find(|attribute| format!("{:?}", attribute).eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown notification attribute: {}", s))
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ActionID {
+    Positive,
+    Negative,
+}
+
+impl TryFrom<u8> for ActionID {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ActionID::Positive),
+            1 => Ok(ActionID::Negative),
+            _ => Err(value),
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AppAttributeID {
+    Displayname,
+}
+
+impl TryFrom<u8> for AppAttributeID {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(AppAttributeID::Displayname),
+            _ => Err(value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NotificationAttributeCmd {
+    pub notification_id: u32,
+    pub attributes: Vec<NotificationAttributeID>,
+    // maximum lengths for attributes that take one, u16::MAX unless set
+    pub max_lengths: Vec<(NotificationAttributeID, u16)>,
+}
+
+impl NotificationAttributeCmd {
+    pub fn new(notification_id: u32, attributes: Vec<NotificationAttributeID>) -> Self {
+        Self {
+            notification_id,
+            attributes,
+            max_lengths: Vec::new(),
+        }
+    }
+
+    pub fn with_max_length(mut self, attribute: NotificationAttributeID, max_length: u16) -> Self {
+        self.max_lengths.retain(|(id, _)| *id != attribute);
+        self.max_lengths.push((attribute, max_length));
+        self
+    }
+
+    pub fn max_length(&self, attribute: NotificationAttributeID) -> u16 {
+        self.max_lengths
+            .iter()
+            .find(|(id, _)| *id == attribute)
+            .map_or(u16::MAX, |(_, max_length)| *max_length)
+    }
+
+    pub fn to_buffer(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let bytes = self.notification_id.to_le_bytes();
+        for byte in bytes {
+            buffer.push(byte);
+        }
+        for attribute in self.attributes.iter().copied() {
+            buffer.push(attribute as u8);
+            if attribute.has_max_length() {
+                buffer.extend(self.max_length(attribute).to_le_bytes());
+            }
+        }
+        buffer
+    }
+
+    // Parses what `to_buffer` writes, `None` for unknown attributes or a missing maximum length
+    pub fn from_buffer(buffer: &[u8]) -> Option<Self> {
+        let notification_id = u32::from_le_bytes(buffer.get(..4)?.try_into().ok()?);
+        let mut cmd = Self::new(notification_id, Vec::new());
+        let mut rest = &buffer[4..];
+        while let Some((&attribute_id, tail)) = rest.split_first() {
+            let attribute = *NotificationAttributeID::ALL.get(attribute_id as usize)?;
+            rest = tail;
+            if attribute.has_max_length() {
+                let max_length = u16::from_le_bytes(rest.get(..2)?.try_into().ok()?);
+                cmd = cmd.with_max_length(attribute, max_length);
+                rest = &rest[2..];
+            }
+            cmd.attributes.push(attribute);
+        }
+        Some(cmd)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AppAttributeCmd {
+    pub app_identifier: String,
+    pub attributes: Vec<AppAttributeID>,
+}
+
+impl AppAttributeCmd {
+    pub fn new(app_identifier: String, attributes: Vec<AppAttributeID>) -> Self {
+        Self {
+            app_identifier,
+            attributes,
+        }
+    }
+
+    pub fn to_buffer(&self) -> Vec<u8> {
+        // the app identifier is NULL-terminated
+        let mut buffer: Vec<u8> = self.app_identifier.bytes().collect();
+        buffer.push(0);
+        for attribute in &self.attributes {
+            buffer.push(attribute.clone() as u8);
+        }
+        buffer
+    }
+
+    // Parses what `to_buffer` writes, `None` without the NULL terminator or for unknown attributes
+    pub fn from_buffer(buffer: &[u8]) -> Option<Self> {
+        let null_terminator = buffer.iter().position(|&b| b == 0)?;
+        let app_identifier = String::from_utf8(buffer[..null_terminator].to_vec()).ok()?;
+        let attributes = buffer[null_terminator + 1..]
+            .iter()
+            .map(|&attribute_id| AppAttributeID::try_from(attribute_id).ok())
+            .collect::<Option<_>>()?;
+        Some(Self::new(app_identifier, attributes))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NotificationActionCmd {
+    pub notification_id: u32,
+    pub action_id: ActionID,
+}
+
+impl NotificationActionCmd {
+    pub fn new(notification_id: u32, action_id: ActionID) -> Self {
+        Self {
+            notification_id,
+            action_id,
+        }
+    }
+
+    pub fn to_buffer(&self) -> Vec<u8> {
+        let mut buffer = self.notification_id.to_le_bytes().to_vec();
+        buffer.push(self.action_id as u8);
+        buffer
+    }
+
+    // Parses what `to_buffer` writes, `None` if cut short or for an unknown action
+    pub fn from_buffer(buffer: &[u8]) -> Option<Self> {
+        let notification_id = u32::from_le_bytes(buffer.get(..4)?.try_into().ok()?);
+        let action_id = ActionID::try_from(*buffer.get(4)?).ok()?;
+        Some(Self::new(notification_id, action_id))
+    }
+}
+
+// A whole Control Point write as a Notification Provider receives it
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Command {
+    NotificationAttributes(NotificationAttributeCmd),
+    AppAttributes(AppAttributeCmd),
+    PerformNotificationAction(NotificationActionCmd),
+}
+
+impl Command {
+    // The command id followed by the command's payload
+    pub fn to_buffer(&self) -> Vec<u8> {
+        let (command_id, payload) = match self {
+            Command::NotificationAttributes(cmd) => {
+                (CommandID::GetNotificationAttributes, cmd.to_buffer())
+            }
+            Command::AppAttributes(cmd) => (CommandID::GetAppAttributes, cmd.to_buffer()),
+            Command::PerformNotificationAction(cmd) => {
+                (CommandID::PerformNotificationAction, cmd.to_buffer())
+            }
+        };
+        let mut buffer = vec![command_id as u8];
+        buffer.extend(payload);
+        buffer
+    }
+
+    // Fails with the error a phone answers the write with, an unknown action is a bad parameter
+    // rather than a malformed command
+    pub fn from_buffer(buffer: &[u8]) -> Result<Self, AncsCommandError> {
+        let (&command_id, payload) = buffer
+            .split_first()
+            .ok_or(AncsCommandError::InvalidCommand)?;
+        if command_id == CommandID::GetNotificationAttributes as u8 {
+            NotificationAttributeCmd::from_buffer(payload)
+                .map(Command::NotificationAttributes)
+                .ok_or(AncsCommandError::InvalidCommand)
+        } else if command_id == CommandID::GetAppAttributes as u8 {
+            AppAttributeCmd::from_buffer(payload)
+                .map(Command::AppAttributes)
+                .ok_or(AncsCommandError::InvalidCommand)
+        } else if command_id == CommandID::PerformNotificationAction as u8 {
+            if payload.len() < 5 {
+                return Err(AncsCommandError::InvalidCommand);
+            }
+            NotificationActionCmd::from_buffer(payload)
+                .map(Command::PerformNotificationAction)
+                .ok_or(AncsCommandError::InvalidParameter)
+        } else {
+            Err(AncsCommandError::UnknownCommand)
+        }
+    }
+}
+
+// ANCS specific ATT errors a control point write can fail with
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AncsCommandError {
+    UnknownCommand = 0xA0,
+    InvalidCommand = 0xA1,
+    // e.g. the notification was removed before its attributes were asked for
+    InvalidParameter = 0xA2,
+    ActionFailed = 0xA3,
+}
+
+impl AncsCommandError {
+    pub fn from_att_code(code: u8) -> Option<Self> {
+        match code {
+            0xA0 => Some(AncsCommandError::UnknownCommand),
+            0xA1 => Some(AncsCommandError::InvalidCommand),
+            0xA2 => Some(AncsCommandError::InvalidParameter),
+            0xA3 => Some(AncsCommandError::ActionFailed),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AncsCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            AncsCommandError::UnknownCommand => "unknown command",
+            AncsCommandError::InvalidCommand => "invalid command",
+            AncsCommandError::InvalidParameter => "invalid parameter",
+            AncsCommandError::ActionFailed => "action failed",
+        };
+        write!(f, "{} (0x{:02X})", description, *self as u8)
+    }
+}
+
+impl std::error::Error for AncsCommandError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::select;
+    use rand::Rng;
+
+    #[test]
+    fn little_endian() {
+        for _ in 0..1000 {
+            let mut rng = rand::thread_rng();
+            let i: u32 = rng.gen();
+            let buffer = NotificationAttributeCmd::new(i, Vec::new()).to_buffer();
+            assert_eq!(i.to_le_bytes(), buffer.as_slice());
+        }
+    }
+
+    #[test]
+    fn notification_attributes() {
+        let buffer =
+            NotificationAttributeCmd::new(2, vec![NotificationAttributeID::Title]).to_buffer();
+        assert_eq!(buffer, vec![2, 0, 0, 0, 1, 255, 255]);
+    }
+
+    #[test]
+    fn app_attributes() {
+        let buffer = AppAttributeCmd::new("com.a".to_string(), vec![AppAttributeID::Displayname])
+            .to_buffer();
+        assert_eq!(buffer, vec![b'c', b'o', b'm', b'.', b'a', 0, 0]);
+    }
+
+    #[test]
+    fn command_errors() {
+        assert_eq!(
+            AncsCommandError::from_att_code(0xA2),
+            Some(AncsCommandError::InvalidParameter)
+        );
+        assert_eq!(
+            AncsCommandError::from_att_code(0xA0),
+            Some(AncsCommandError::UnknownCommand)
+        );
+        // a generic ATT error is not ANCS specific
+        assert_eq!(AncsCommandError::from_att_code(0x0E), None);
+        assert_eq!(
+            AncsCommandError::ActionFailed.to_string(),
+            "action failed (0xA3)"
+        );
+    }
+
+    #[test]
+    fn max_lengths() {
+        let buffer = NotificationAttributeCmd::new(
+            2,
+            vec![
+                NotificationAttributeID::AppIdentifier,
+                NotificationAttributeID::Title,
+                NotificationAttributeID::Message,
+                NotificationAttributeID::MessageSize,
+            ],
+        )
+        .with_max_length(NotificationAttributeID::Title, 64)
+        .with_max_length(NotificationAttributeID::Message, 300)
+        .to_buffer();
+        assert_eq!(buffer, vec![2, 0, 0, 0, 0, 1, 64, 0, 3, 44, 1, 4]);
+    }
+
+    #[test]
+    fn attribute_from_str() {
+        assert_eq!(
+            NotificationAttributeID::from_str("MessageSize"),
+            Ok(NotificationAttributeID::MessageSize)
+        );
+        assert!(NotificationAttributeID::from_str("Body").is_err());
+    }
+
+    #[test]
+    fn category_from_str() {
+        assert_eq!(
+            CategoryID::from_str("IncomingCall"),
+            Ok(CategoryID::IncomingCall)
+        );
+        assert_eq!(CategoryID::from_str("social"), Ok(CategoryID::Social));
+        assert!(CategoryID::from_str("Unknown").is_err());
+    }
+
+    #[test]
+    fn flag_names() {
+        let event_flags = EventFlag::Silent as u8 | EventFlag::NegativeAction as u8 | 1 << 7;
+        assert_eq!(
+            event_flag_names(event_flags),
+            vec!["Silent", "NegativeAction"]
+        );
+        assert!(event_flag_names(0).is_empty());
+    }
+
+    #[test]
+    fn commands() {
+        let action =
+            Command::PerformNotificationAction(NotificationActionCmd::new(9, ActionID::Negative));
+        assert_eq!(action.to_buffer(), vec![2, 9, 0, 0, 0, 1]);
+        assert_eq!(Command::from_buffer(&action.to_buffer()), Ok(action));
+        assert_eq!(
+            Command::from_buffer(&[2, 9, 0, 0, 0, 2]),
+            Err(AncsCommandError::InvalidParameter)
+        );
+        assert_eq!(
+            Command::from_buffer(&[2, 9, 0]),
+            Err(AncsCommandError::InvalidCommand)
+        );
+        assert_eq!(
+            Command::from_buffer(&[1, b'a']),
+            Err(AncsCommandError::InvalidCommand)
+        );
+        assert_eq!(
+            Command::from_buffer(&[7]),
+            Err(AncsCommandError::UnknownCommand)
+        );
+        assert_eq!(
+            Command::from_buffer(&[]),
+            Err(AncsCommandError::InvalidCommand)
+        );
+    }
+
+    fn notification_attribute() -> impl Strategy<Value = NotificationAttributeID> {
+        select(NotificationAttributeID::ALL.to_vec())
+    }
+
+    proptest! {
+        #[test]
+        fn notification_attribute_cmd_round_trip(
+            notification_id: u32,
+            attributes in vec(notification_attribute(), 0..10),
+            max_lengths in vec((notification_attribute(), any::<u16>()), 0..4),
+        ) {
+            let cmd = max_lengths.into_iter().fold(
+                NotificationAttributeCmd::new(notification_id, attributes),
+                |cmd, (attribute, max_length)| cmd.with_max_length(attribute, max_length),
+            );
+            let decoded = NotificationAttributeCmd::from_buffer(&cmd.to_buffer()).unwrap();
+            prop_assert_eq!(decoded.notification_id, cmd.notification_id);
+            prop_assert_eq!(&decoded.attributes, &cmd.attributes);
+            // maximum lengths only go over the air for the attributes that take one
+            for attribute in cmd.attributes.iter().copied() {
+                if attribute.has_max_length() {
+                    prop_assert_eq!(decoded.max_length(attribute), cmd.max_length(attribute));
+                }
+            }
+            prop_assert_eq!(decoded.to_buffer(), cmd.to_buffer());
+        }
+
+        #[test]
+        fn app_attribute_cmd_round_trip(
+            app_identifier in "[^\\x00]{0,40}",
+            attributes in vec(Just(AppAttributeID::Displayname), 0..3),
+        ) {
+            let cmd = AppAttributeCmd::new(app_identifier, attributes);
+            prop_assert_eq!(AppAttributeCmd::from_buffer(&cmd.to_buffer()), Some(cmd));
+        }
+
+        #[test]
+        fn command_round_trip(
+            notification_id: u32,
+            attributes in vec(notification_attribute(), 0..10),
+            app_identifier in "[^\\x00]{0,40}",
+            action_id in select(vec![ActionID::Positive, ActionID::Negative]),
+        ) {
+            // every maximum length is written, so these decode to themselves
+            let cmd = attributes
+                .iter()
+                .filter(|attribute| attribute.has_max_length())
+                .fold(
+                    NotificationAttributeCmd::new(notification_id, attributes.clone()),
+                    |cmd, &attribute| cmd.with_max_length(attribute, u16::MAX),
+                );
+            let commands = [
+                Command::NotificationAttributes(cmd),
+                Command::AppAttributes(AppAttributeCmd::new(
+                    app_identifier,
+                    vec![AppAttributeID::Displayname],
+                )),
+                Command::PerformNotificationAction(NotificationActionCmd::new(
+                    notification_id,
+                    action_id,
+                )),
+            ];
+            for command in commands {
+                prop_assert_eq!(Command::from_buffer(&command.to_buffer()), Ok(command));
+            }
+        }
+
+        #[test]
+        fn decoding_never_panics(buffer in vec(any::<u8>(), 0..64)) {
+            let _ = NotificationAttributeCmd::from_buffer(&buffer);
+            let _ = AppAttributeCmd::from_buffer(&buffer);
+            let _ = Command::from_buffer(&buffer);
+        }
+    }
+}
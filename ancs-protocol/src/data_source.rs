@@ -0,0 +1,684 @@
+use chrono::NaiveDateTime;
+use log::warn;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use std::borrow::Cow;
+use std::str;
+
+use crate::control_point::{
+    AppAttributeCmd, AppAttributeID, CommandID, NotificationAttributeCmd, NotificationAttributeID,
+};
+use crate::date;
+
+// UUID for characteristic
+pub const DATA_SOURCE_UUID: Uuid = Uuid::from_u128(0x22EAC6E924D64BB5BE44B36ACE7C7BFB);
+
+// Values are cut at the requested maximum length, which can split a multi-byte character at the
+// end. The partial character is dropped, anything else that is not UTF-8 is replaced. Only the
+// replacement allocates.
+fn decode(value: &[u8]) -> Cow<'_, str> {
+    match str::from_utf8(value) {
+        Ok(value) => Cow::Borrowed(value),
+        Err(e) if e.error_len().is_none() => String::from_utf8_lossy(&value[..e.valid_up_to()]),
+        Err(_) => String::from_utf8_lossy(value),
+    }
+}
+
+// Reads a response front to back without copying it
+struct Cursor<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer }
+    }
+
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.buffer.len() < length {
+            return None;
+        }
+        let (taken, rest) = self.buffer.split_at(length);
+        self.buffer = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    // Up to the NULL terminator, which is skipped
+    fn null_terminated(&mut self) -> Option<&'a [u8]> {
+        let null_terminator = self.buffer.iter().position(|&b| b == 0)?;
+        let value = &self.buffer[..null_terminator];
+        self.buffer = &self.buffer[null_terminator + 1..];
+        Some(value)
+    }
+
+    // The next attribute id and its value, which is `None` if it is cut short
+    fn attribute(&mut self) -> Option<(u8, Option<&'a [u8]>)> {
+        if self.buffer.len() < 3 {
+            return None;
+        }
+        let attribute_id = self.u8()?;
+        let length = self.u16()? as usize;
+        let value = self.take(length);
+        if value.is_none() {
+            self.buffer = &[];
+        }
+        Some((attribute_id, value))
+    }
+}
+
+// Notification attributes borrowed from the response they were parsed from. Values are only
+// copied where they are not UTF-8.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NotificationAttributesView<'a> {
+    pub notification_id: u32,
+    pub app_identifier: Option<Cow<'a, str>>,
+    pub title: Option<Cow<'a, str>>,
+    pub subtitle: Option<Cow<'a, str>>,
+    pub message: Option<Cow<'a, str>>,
+    pub message_size: Option<u16>,
+    pub date: Option<NaiveDateTime>,
+    pub positive_action_label: Option<Cow<'a, str>>,
+    pub negative_action_label: Option<Cow<'a, str>>,
+}
+
+impl<'a> NotificationAttributesView<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Self {
+        let mut cursor = Cursor::new(buffer);
+        // the first byte is the message type which specifies whether the message is a notification
+        // attribute or a app attribute
+        let notification_id = match cursor.u8().and_then(|_| cursor.u32()) {
+            Some(notification_id) => notification_id,
+            None => {
+                warn!("Notification attributes cut short: {:?}", buffer);
+                return Self::default();
+            }
+        };
+        let mut view = Self {
+            notification_id,
+            ..Self::default()
+        };
+        while let Some((attribute_id, value)) = cursor.attribute() {
+            let Some(value) = value else {
+                warn!(
+                    "Attribute {} of notification {} is cut short",
+                    attribute_id, notification_id
+                );
+                break;
+            };
+            if attribute_id == NotificationAttributeID::AppIdentifier as u8 {
+                view.app_identifier = Some(decode(value));
+            } else if attribute_id == NotificationAttributeID::Title as u8 {
+                view.title = Some(decode(value));
+            } else if attribute_id == NotificationAttributeID::Subtitle as u8 {
+                view.subtitle = Some(decode(value));
+            } else if attribute_id == NotificationAttributeID::Message as u8 {
+                view.message = Some(decode(value));
+            } else if attribute_id == NotificationAttributeID::MessageSize as u8 {
+                // the size is sent as a decimal string
+                view.message_size = decode(value).trim_end_matches('\0').parse().ok();
+            } else if attribute_id == NotificationAttributeID::Date as u8 {
+                let raw = decode(value);
+                view.date = date::parse(&raw);
+                if view.date.is_none() {
+                    warn!(
+                        "Malformed date {:?} for notification {}",
+                        raw, notification_id
+                    );
+                }
+            } else if attribute_id == NotificationAttributeID::PositiveActionLabel as u8 {
+                view.positive_action_label = Some(decode(value));
+            } else if attribute_id == NotificationAttributeID::NegativeActionLabel as u8 {
+                view.negative_action_label = Some(decode(value));
+            }
+        }
+        view
+    }
+
+    pub fn into_owned(self) -> NotificationAttributes {
+        NotificationAttributes {
+            notification_id: self.notification_id,
+            app_identifier: self.app_identifier.map(Cow::into_owned),
+            title: self.title.map(Cow::into_owned),
+            subtitle: self.subtitle.map(Cow::into_owned),
+            message: self.message.map(Cow::into_owned),
+            message_size: self.message_size,
+            date: self.date,
+            positive_action_label: self.positive_action_label.map(Cow::into_owned),
+            negative_action_label: self.negative_action_label.map(Cow::into_owned),
+        }
+    }
+}
+
+// Notification Attributes
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NotificationAttributes {
+    pub notification_id: u32,
+    pub app_identifier: Option<String>,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub message: Option<String>,
+    // size of the whole message, the message itself may have been cut at the requested length
+    pub message_size: Option<u16>,
+    // the phone's local time, `None` if missing or malformed
+    pub date: Option<NaiveDateTime>,
+    pub positive_action_label: Option<String>,
+    pub negative_action_label: Option<String>,
+}
+
+impl NotificationAttributes {
+    pub fn from_buffer(buffer: Vec<u8>) -> Self {
+        NotificationAttributesView::parse(&buffer).into_owned()
+    }
+
+    // The phone's response to `cmd`: every requested attribute in the requested order, empty if
+    // there is none, cut at its maximum length like the phone does even inside a character
+    pub fn to_buffer(&self, cmd: &NotificationAttributeCmd) -> Vec<u8> {
+        let mut buffer = vec![CommandID::GetNotificationAttributes as u8];
+        buffer.extend(cmd.notification_id.to_le_bytes());
+        for attribute in cmd.attributes.iter().copied() {
+            let value = match attribute {
+                NotificationAttributeID::AppIdentifier => self.app_identifier.clone(),
+                NotificationAttributeID::Title => self.title.clone(),
+                NotificationAttributeID::Subtitle => self.subtitle.clone(),
+                NotificationAttributeID::Message => self.message.clone(),
+                NotificationAttributeID::MessageSize => {
+                    self.message_size.map(|size| size.to_string())
+                }
+                NotificationAttributeID::Date => self.date.map(date::format),
+                NotificationAttributeID::PositiveActionLabel => self.positive_action_label.clone(),
+                NotificationAttributeID::NegativeActionLabel => self.negative_action_label.clone(),
+            }
+            .unwrap_or_default();
+            let max_length = match attribute.has_max_length() {
+                true => cmd.max_length(attribute),
+                false => u16::MAX,
+            };
+            encode_attribute(&mut buffer, attribute as u8, value.as_bytes(), max_length);
+        }
+        buffer
+    }
+}
+
+impl NotificationAttributes {
+    pub fn message_truncated(&self) -> bool {
+        message_truncated(self.message.as_deref(), self.message_size)
+    }
+}
+
+pub fn message_truncated(message: Option<&str>, message_size: Option<u16>) -> bool {
+    match (message, message_size) {
+        (Some(message), Some(message_size)) => message.len() < message_size as usize,
+        _ => false,
+    }
+}
+
+// App attributes borrowed from the response they were parsed from
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AppAttributesView<'a> {
+    pub app_identifier: Cow<'a, str>,
+    pub display_name: Option<Cow<'a, str>>,
+}
+
+impl<'a> AppAttributesView<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Self {
+        let mut cursor = Cursor::new(buffer);
+        cursor.u8();
+        let mut view = Self::default();
+        if let Some(app_identifier) = cursor.null_terminated() {
+            view.app_identifier = decode(app_identifier);
+        }
+        while let Some((attribute_id, value)) = cursor.attribute() {
+            let Some(value) = value else {
+                warn!(
+                    "Attribute {} of app {} is cut short",
+                    attribute_id, view.app_identifier
+                );
+                break;
+            };
+            if attribute_id == AppAttributeID::Displayname as u8 {
+                view.display_name = Some(decode(value));
+            }
+        }
+        view
+    }
+
+    pub fn into_owned(self) -> AppAttributes {
+        AppAttributes {
+            app_identifier: self.app_identifier.into_owned(),
+            display_name: self.display_name.map(Cow::into_owned),
+        }
+    }
+}
+
+// App Attributes
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AppAttributes {
+    pub app_identifier: String,
+    pub display_name: Option<String>,
+}
+
+impl AppAttributes {
+    pub fn from_buffer(buffer: Vec<u8>) -> Self {
+        AppAttributesView::parse(&buffer).into_owned()
+    }
+
+    // The phone's response to `cmd`, which echoes the app identifier it asked about
+    pub fn to_buffer(&self, cmd: &AppAttributeCmd) -> Vec<u8> {
+        let mut buffer = vec![CommandID::GetAppAttributes as u8];
+        buffer.extend(cmd.app_identifier.as_bytes());
+        buffer.push(0);
+        for attribute in &cmd.attributes {
+            let value = match attribute {
+                AppAttributeID::Displayname => self.display_name.as_deref(),
+            };
+            encode_attribute(
+                &mut buffer,
+                attribute.clone() as u8,
+                value.unwrap_or_default().as_bytes(),
+                u16::MAX,
+            );
+        }
+        buffer
+    }
+}
+
+fn encode_attribute(buffer: &mut Vec<u8>, attribute_id: u8, value: &[u8], max_length: u16) {
+    let value = &value[..value.len().min(max_length as usize)];
+    buffer.push(attribute_id);
+    buffer.extend((value.len() as u16).to_le_bytes());
+    buffer.extend(value);
+}
+
+// Splits a response into Data Source notifications, which carry at most the ATT MTU less the
+// 3 byte notification header
+pub fn fragments(response: &[u8], mtu: u16) -> std::slice::Chunks<'_, u8> {
+    response.chunks((mtu as usize).saturating_sub(3).max(1))
+}
+
+// The response a pending command waits for
+#[derive(Debug)]
+pub enum Expected {
+    Notification {
+        notification_id: u32,
+        attributes: usize,
+    },
+    App {
+        app_identifier: String,
+        attributes: usize,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Progress {
+    Complete,
+    Incomplete,
+    Unrelated,
+}
+
+impl Expected {
+    // The response to a raw Control Point write, `None` for actions and malformed commands
+    pub fn from_command(command: &[u8]) -> Option<Self> {
+        let (&command_id, rest) = command.split_first()?;
+        if command_id == CommandID::GetNotificationAttributes as u8 {
+            let notification_id = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?);
+            let mut attributes = 0;
+            let mut ids = &rest[4..];
+            while let Some((&attribute_id, tail)) = ids.split_first() {
+                let has_max_length = NotificationAttributeID::ALL
+                    .get(attribute_id as usize)
+                    .is_some_and(|attribute| attribute.has_max_length());
+                ids = if has_max_length { tail.get(2..)? } else { tail };
+                attributes += 1;
+            }
+            Some(Expected::Notification {
+                notification_id,
+                attributes,
+            })
+        } else if command_id == CommandID::GetAppAttributes as u8 {
+            let null_terminator = rest.iter().position(|&b| b == 0)?;
+            Some(Expected::App {
+                app_identifier: String::from_utf8_lossy(&rest[..null_terminator]).into_owned(),
+                attributes: rest.len() - null_terminator - 1,
+            })
+        } else {
+            None
+        }
+    }
+
+    // Responses hold every requested attribute, so they are complete once that many are in
+    pub fn progress(&self, buffer: &[u8]) -> Progress {
+        let Some((&command_id, rest)) = buffer.split_first() else {
+            return Progress::Incomplete;
+        };
+        match self {
+            Expected::Notification {
+                notification_id,
+                attributes,
+            } => {
+                if command_id != CommandID::GetNotificationAttributes as u8 {
+                    return Progress::Unrelated;
+                }
+                if rest.len() < 4 {
+                    return Progress::Incomplete;
+                }
+                if rest[..4] != notification_id.to_le_bytes() {
+                    return Progress::Unrelated;
+                }
+                attributes_progress(&rest[4..], *attributes)
+            }
+            Expected::App {
+                app_identifier,
+                attributes,
+            } => {
+                if command_id != CommandID::GetAppAttributes as u8 {
+                    return Progress::Unrelated;
+                }
+                match rest.iter().position(|&b| b == 0) {
+                    Some(null_terminator)
+                        if &rest[..null_terminator] == app_identifier.as_bytes() =>
+                    {
+                        attributes_progress(&rest[null_terminator + 1..], *attributes)
+                    }
+                    Some(_) => Progress::Unrelated,
+                    None if app_identifier.as_bytes().starts_with(rest) => Progress::Incomplete,
+                    None => Progress::Unrelated,
+                }
+            }
+        }
+    }
+}
+
+// Walks attribute id, length and value triples
+fn attributes_progress(mut buffer: &[u8], attributes: usize) -> Progress {
+    for _ in 0..attributes {
+        if buffer.len() < 3 {
+            return Progress::Incomplete;
+        }
+        let length = 3 + u16::from_le_bytes([buffer[1], buffer[2]]) as usize;
+        if buffer.len() < length {
+            return Progress::Incomplete;
+        }
+        buffer = &buffer[length..];
+    }
+    Progress::Complete
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(buffer: &mut Vec<u8>, attribute_id: NotificationAttributeID, value: &[u8]) {
+        buffer.push(attribute_id as u8);
+        buffer.extend((value.len() as u16).to_le_bytes());
+        buffer.extend(value);
+    }
+
+    #[test]
+    fn notification_attributes() {
+        let mut buffer = vec![0, 7, 0, 0, 0];
+        attribute(
+            &mut buffer,
+            NotificationAttributeID::AppIdentifier,
+            b"com.apple.MobileSMS",
+        );
+        attribute(&mut buffer, NotificationAttributeID::Title, b"Alice");
+        attribute(&mut buffer, NotificationAttributeID::Message, b"Hello");
+        attribute(&mut buffer, NotificationAttributeID::MessageSize, b"5");
+        attribute(
+            &mut buffer,
+            NotificationAttributeID::Date,
+            b"20240131T093005",
+        );
+
+        let attributes = NotificationAttributes::from_buffer(buffer);
+        assert_eq!(attributes.notification_id, 7);
+        assert_eq!(
+            attributes.app_identifier.as_deref(),
+            Some("com.apple.MobileSMS")
+        );
+        assert_eq!(attributes.title.as_deref(), Some("Alice"));
+        assert_eq!(attributes.message.as_deref(), Some("Hello"));
+        assert_eq!(attributes.message_size, Some(5));
+        assert_eq!(attributes.date, date::parse("20240131T093005"));
+        assert!(!attributes.message_truncated());
+    }
+
+    #[test]
+    fn truncated_message() {
+        // "Grüße" cut in the middle of the ü
+        let mut buffer = vec![0, 1, 0, 0, 0];
+        attribute(
+            &mut buffer,
+            NotificationAttributeID::Message,
+            &"Grüße".as_bytes()[..3],
+        );
+        attribute(&mut buffer, NotificationAttributeID::MessageSize, b"7");
+        attribute(&mut buffer, NotificationAttributeID::Subtitle, b"after");
+
+        let attributes = NotificationAttributes::from_buffer(buffer);
+        assert_eq!(attributes.message.as_deref(), Some("Gr"));
+        assert_eq!(attributes.message_size, Some(7));
+        assert_eq!(attributes.subtitle.as_deref(), Some("after"));
+        assert!(attributes.message_truncated());
+    }
+
+    #[test]
+    fn app_attributes() {
+        let mut buffer = vec![1];
+        buffer.extend(b"com.apple.MobileSMS\0");
+        buffer.extend([AppAttributeID::Displayname as u8, 8, 0]);
+        buffer.extend(b"Messages");
+        let attributes = AppAttributes::from_buffer(buffer);
+        assert_eq!(attributes.app_identifier, "com.apple.MobileSMS");
+        assert_eq!(attributes.display_name.as_deref(), Some("Messages"));
+    }
+
+    #[test]
+    fn cut_short() {
+        let mut buffer = vec![0, 1, 0, 0, 0];
+        attribute(&mut buffer, NotificationAttributeID::Title, b"Alice");
+        buffer.extend([NotificationAttributeID::Message as u8, 10, 0, b'H']);
+
+        let attributes = NotificationAttributes::from_buffer(buffer);
+        assert_eq!(attributes.title.as_deref(), Some("Alice"));
+        assert_eq!(attributes.message, None);
+        let attributes = NotificationAttributes::from_buffer(vec![0, 1]);
+        assert_eq!(attributes.notification_id, 0);
+        assert_eq!(attributes.title, None);
+        assert_eq!(AppAttributes::from_buffer(Vec::new()).display_name, None);
+    }
+
+    #[test]
+    fn borrowed_view() {
+        let mut buffer = vec![0, 3, 0, 0, 0];
+        attribute(&mut buffer, NotificationAttributeID::Title, b"Alice");
+        attribute(
+            &mut buffer,
+            NotificationAttributeID::Message,
+            &"Grüße".as_bytes()[..3],
+        );
+        attribute(&mut buffer, NotificationAttributeID::Subtitle, b"\xFFok");
+
+        let view = NotificationAttributesView::parse(&buffer);
+        assert_eq!(view.notification_id, 3);
+        assert!(matches!(view.title, Some(Cow::Borrowed("Alice"))));
+        assert!(matches!(view.message, Some(Cow::Borrowed("Gr"))));
+        // only values that are not UTF-8 are copied
+        assert!(matches!(view.subtitle, Some(Cow::Owned(_))));
+        assert_eq!(view.into_owned().subtitle.as_deref(), Some("\u{FFFD}ok"));
+
+        let mut buffer = vec![1];
+        buffer.extend(b"com.a\0");
+        buffer.extend([AppAttributeID::Displayname as u8, 4, 0]);
+        buffer.extend(b"Mail");
+        let view = AppAttributesView::parse(&buffer);
+        assert!(matches!(view.app_identifier, Cow::Borrowed("com.a")));
+        assert!(matches!(view.display_name, Some(Cow::Borrowed("Mail"))));
+    }
+
+    fn reassemble(expected: &Expected, response: &[u8], mtu: u16) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for fragment in fragments(response, mtu) {
+            assert_eq!(expected.progress(&buffer), Progress::Incomplete);
+            assert!(fragment.len() <= mtu as usize - 3);
+            buffer.extend(fragment);
+        }
+        assert_eq!(expected.progress(&buffer), Progress::Complete);
+        buffer
+    }
+
+    #[test]
+    fn notification_response_round_trip() {
+        let attributes = NotificationAttributes {
+            notification_id: 7,
+            app_identifier: Some("com.apple.MobileSMS".to_string()),
+            title: Some("Alice".to_string()),
+            subtitle: None,
+            message: Some("Grüße aus Köln".to_string()),
+            message_size: Some(16),
+            date: date::parse("20240131T093005"),
+            positive_action_label: Some("Reply".to_string()),
+            negative_action_label: None,
+        };
+        let cmd = NotificationAttributeCmd::new(7, NotificationAttributeID::ALL.to_vec());
+        let command = [
+            vec![CommandID::GetNotificationAttributes as u8],
+            cmd.to_buffer(),
+        ]
+        .concat();
+        let expected = Expected::from_command(&command).unwrap();
+        let response = reassemble(&expected, &attributes.to_buffer(&cmd), 23);
+        let decoded = NotificationAttributes::from_buffer(response);
+        // attributes the phone has none of come back empty
+        assert_eq!(
+            decoded,
+            NotificationAttributes {
+                subtitle: Some(String::new()),
+                negative_action_label: Some(String::new()),
+                ..attributes.clone()
+            }
+        );
+
+        // the message is cut inside the ü, which the client drops
+        let cmd = NotificationAttributeCmd::new(
+            7,
+            vec![
+                NotificationAttributeID::Message,
+                NotificationAttributeID::MessageSize,
+            ],
+        )
+        .with_max_length(NotificationAttributeID::Message, 3);
+        let decoded = NotificationAttributes::from_buffer(attributes.to_buffer(&cmd));
+        assert_eq!(decoded.message.as_deref(), Some("Gr"));
+        assert_eq!(decoded.message_size, Some(16));
+        assert!(decoded.message_truncated());
+        assert_eq!(decoded.title, None);
+    }
+
+    #[test]
+    fn app_response_round_trip() {
+        let attributes = AppAttributes {
+            app_identifier: "com.apple.MobileSMS".to_string(),
+            display_name: Some("Messages".to_string()),
+        };
+        let cmd = AppAttributeCmd::new(
+            attributes.app_identifier.clone(),
+            vec![AppAttributeID::Displayname],
+        );
+        let command = [vec![CommandID::GetAppAttributes as u8], cmd.to_buffer()].concat();
+        let expected = Expected::from_command(&command).unwrap();
+        let response = reassemble(&expected, &attributes.to_buffer(&cmd), 23);
+        assert_eq!(AppAttributes::from_buffer(response), attributes);
+    }
+
+    fn response(notification_id: u32) -> Vec<u8> {
+        let mut buffer = vec![0];
+        buffer.extend(notification_id.to_le_bytes());
+        buffer.extend([NotificationAttributeID::Title as u8, 5, 0]);
+        buffer.extend(b"Alice");
+        buffer.extend([NotificationAttributeID::Message as u8, 0, 0]);
+        buffer
+    }
+
+    #[test]
+    fn notification_progress() {
+        let expected = Expected::Notification {
+            notification_id: 7,
+            attributes: 2,
+        };
+        let buffer = response(7);
+        assert_eq!(expected.progress(&buffer), Progress::Complete);
+        for end in 0..buffer.len() {
+            assert_eq!(expected.progress(&buffer[..end]), Progress::Incomplete);
+        }
+        assert_eq!(expected.progress(&response(8)), Progress::Unrelated);
+        assert_eq!(expected.progress(&[1, b'a', 0]), Progress::Unrelated);
+    }
+
+    #[test]
+    fn app_progress() {
+        let expected = Expected::App {
+            app_identifier: "com.a".to_string(),
+            attributes: 1,
+        };
+        assert_eq!(expected.progress(b"\x01com"), Progress::Incomplete);
+        assert_eq!(
+            expected.progress(b"\x01com.a\0\0\x02\0"),
+            Progress::Incomplete
+        );
+        assert_eq!(
+            expected.progress(b"\x01com.a\0\0\x02\0hi"),
+            Progress::Complete
+        );
+        assert_eq!(expected.progress(b"\x01org"), Progress::Unrelated);
+        assert_eq!(expected.progress(b"\x01com.b\0"), Progress::Unrelated);
+    }
+
+    #[test]
+    fn expected_from_command() {
+        let cmd = NotificationAttributeCmd::new(
+            7,
+            vec![
+                NotificationAttributeID::Title,
+                NotificationAttributeID::Date,
+            ],
+        );
+        let command = [
+            vec![CommandID::GetNotificationAttributes as u8],
+            cmd.to_buffer(),
+        ]
+        .concat();
+        assert!(matches!(
+            Expected::from_command(&command),
+            Some(Expected::Notification {
+                notification_id: 7,
+                attributes: 2
+            })
+        ));
+        // the title's maximum length is cut off
+        assert!(Expected::from_command(&command[..7]).is_none());
+        assert!(matches!(
+            Expected::from_command(b"\x01com.a\0\0"),
+            Some(Expected::App { attributes: 1, .. })
+        ));
+        assert!(Expected::from_command(&[CommandID::PerformNotificationAction as u8]).is_none());
+    }
+}
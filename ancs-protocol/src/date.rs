@@ -0,0 +1,120 @@
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc,
+};
+use uuid::Uuid;
+
+// Current Time Service and its Current Time characteristic, used to learn the phone's time zone
+pub const CURRENT_TIME_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000180500001000800000805F9B34FB);
+pub const CURRENT_TIME_UUID: Uuid = Uuid::from_u128(0x00002A2B00001000800000805F9B34FB);
+
+// ANCS dates are the phone's wall clock time without a zone, e.g. 20240131T093000
+const ANCS_DATE_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+pub fn parse(date: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(date.trim_end_matches('\0'), ANCS_DATE_FORMAT).ok()
+}
+
+pub fn format(date: NaiveDateTime) -> String {
+    date.format(ANCS_DATE_FORMAT).to_string()
+}
+
+// Parses the exact time of a Current Time characteristic value into the phone's local time
+pub fn parse_current_time(buffer: &[u8]) -> Option<NaiveDateTime> {
+    if buffer.len() < 7 {
+        return None;
+    }
+    let year = u16::from_le_bytes([buffer[0], buffer[1]]) as i32;
+    NaiveDate::from_ymd_opt(year, buffer[2] as u32, buffer[3] as u32)?.and_hms_opt(
+        buffer[4] as u32,
+        buffer[5] as u32,
+        buffer[6] as u32,
+    )
+}
+
+// The Current Time characteristic value for `now`, without fractions or adjustment reasons
+pub fn format_current_time(now: NaiveDateTime) -> Vec<u8> {
+    let mut buffer = (now.year() as u16).to_le_bytes().to_vec();
+    buffer.extend([
+        now.month() as u8,
+        now.day() as u8,
+        now.hour() as u8,
+        now.minute() as u8,
+        now.second() as u8,
+        now.weekday().number_from_monday() as u8,
+        0,
+        0,
+    ]);
+    buffer
+}
+
+// Converts the phone's wall clock time into desktop time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PhoneClock {
+    // the phone's UTC offset if known, otherwise it is assumed to share the desktop's zone
+    offset: Option<FixedOffset>,
+}
+
+impl PhoneClock {
+    // Derives the phone's UTC offset by comparing its current time with ours. Offsets come in
+    // steps of 15 minutes, which also absorbs small clock drift.
+    pub fn from_current_time(phone_now: NaiveDateTime, now: DateTime<Utc>) -> Self {
+        const STEP: i64 = 15 * 60;
+        let difference = (phone_now - now.naive_utc()).num_seconds();
+        let rounded = (difference as f64 / STEP as f64).round() as i64 * STEP;
+        let offset = if rounded.abs() <= 14 * 3600 {
+            FixedOffset::east_opt(rounded as i32)
+        } else {
+            None
+        };
+        Self { offset }
+    }
+
+    pub fn offset(self) -> Option<FixedOffset> {
+        self.offset
+    }
+
+    pub fn to_local(self, date: NaiveDateTime) -> Option<DateTime<Local>> {
+        match self.offset {
+            Some(offset) => offset
+                .from_local_datetime(&date)
+                .single()
+                .map(|date| date.with_timezone(&Local)),
+            None => Local.from_local_datetime(&date).earliest(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ancs_date() {
+        let date = parse("20240131T093005").unwrap();
+        assert_eq!(date.to_string(), "2024-01-31 09:30:05");
+        assert!(parse("").is_none());
+        assert!(parse("2024-01-31").is_none());
+        assert!(parse("20241341T093005").is_none());
+        assert_eq!(format(date), "20240131T093005");
+    }
+
+    #[test]
+    fn current_time() {
+        // 2024-01-31 09:30:05, wednesday, no fractions, manual adjustment
+        let buffer = [0xE8, 0x07, 1, 31, 9, 30, 5, 3, 0, 1];
+        let phone_now = parse_current_time(&buffer).unwrap();
+        assert_eq!(phone_now, parse("20240131T093005").unwrap());
+        assert!(parse_current_time(&buffer[..5]).is_none());
+        assert_eq!(format_current_time(phone_now)[..9], buffer[..9]);
+
+        // phone is an hour ahead of UTC and a few seconds off
+        let now = Utc.with_ymd_and_hms(2024, 1, 31, 8, 29, 51).unwrap();
+        let clock = PhoneClock::from_current_time(phone_now, now);
+        assert_eq!(clock.offset(), FixedOffset::east_opt(3600));
+        let local = clock.to_local(phone_now).unwrap();
+        assert_eq!(
+            local.with_timezone(&Utc),
+            Utc.with_ymd_and_hms(2024, 1, 31, 8, 30, 5).unwrap()
+        );
+    }
+}
@@ -0,0 +1,11 @@
+// The Apple Notification Center Service as seen from the Notification Consumer: UUIDs, event and
+// attribute ids, Control Point commands and Data Source response parsing. Transport agnostic,
+// bring your own Bluetooth stack.
+pub mod control_point;
+pub mod data_source;
+pub mod date;
+pub mod notification_source;
+
+use uuid::Uuid;
+
+pub const ANCS_SERVICE_UUID: Uuid = Uuid::from_u128(0x7905F431B5CE4E99A40F4B1E122D00D0);
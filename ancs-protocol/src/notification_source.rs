@@ -0,0 +1,75 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const NOTIFICATION_SOURCE_UUID: Uuid = Uuid::from_u128(0x9FBF120D630142D98C5825E699A21DBD);
+
+// Contains notification event
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NotificationEvent {
+    pub event_id: u8,
+    pub event_flags: u8,
+    pub category_id: u8,
+    pub category_count: u8,
+    pub notification_id: u32,
+}
+
+impl NotificationEvent {
+    // `None` if the buffer is shorter than the 8 bytes of an event
+    pub fn from_buffer(buffer: Vec<u8>) -> Option<Self> {
+        let buffer = buffer.get(..8)?;
+        Some(Self {
+            event_id: buffer[0],
+            event_flags: buffer[1],
+            category_id: buffer[2],
+            category_count: buffer[3],
+            notification_id: u32::from_le_bytes(buffer[4..8].try_into().unwrap()),
+        })
+    }
+
+    pub fn to_buffer(&self) -> Vec<u8> {
+        let mut buffer = vec![
+            self.event_id,
+            self.event_flags,
+            self.category_id,
+            self.category_count,
+        ];
+        buffer.extend(self.notification_id.to_le_bytes());
+        buffer
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn notification_event() {
+        let buffer = vec![1, 2, 3, 4, 1, 2, 3, 4];
+
+        let event = NotificationEvent::from_buffer(buffer).unwrap();
+
+        assert_eq!(event.event_id, 1);
+        assert_eq!(event.event_flags, 2);
+        assert_eq!(event.category_id, 3);
+        assert_eq!(event.category_count, 4);
+        assert_eq!(event.notification_id, 67305985);
+        assert!(NotificationEvent::from_buffer(vec![1, 2, 3]).is_none());
+    }
+
+    #[test]
+    fn event_round_trip() {
+        let event = NotificationEvent {
+            event_id: 0,
+            event_flags: 0x14,
+            category_id: 4,
+            category_count: 2,
+            notification_id: 0xDEADBEEF,
+        };
+        assert_eq!(
+            NotificationEvent::from_buffer(event.to_buffer()),
+            Some(event)
+        );
+    }
+}
@@ -0,0 +1,8 @@
+#![no_main]
+
+use ancs_protocol::data_source::AppAttributes;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = AppAttributes::from_buffer(data.to_vec());
+});
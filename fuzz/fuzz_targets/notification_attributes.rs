@@ -0,0 +1,9 @@
+#![no_main]
+
+use ancs_protocol::data_source::NotificationAttributes;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let attributes = NotificationAttributes::from_buffer(data.to_vec());
+    let _ = attributes.message_truncated();
+});
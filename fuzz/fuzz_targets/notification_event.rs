@@ -0,0 +1,10 @@
+#![no_main]
+
+use ancs_protocol::notification_source::NotificationEvent;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(event) = NotificationEvent::from_buffer(data.to_vec()) {
+        assert_eq!(event.to_buffer(), data[..8]);
+    }
+});
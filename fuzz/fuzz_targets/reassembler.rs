@@ -0,0 +1,33 @@
+#![no_main]
+
+use ancs_protocol::data_source::{AppAttributes, NotificationAttributes};
+use ancs_protocol::data_source::{Expected, Progress};
+use libfuzzer_sys::fuzz_target;
+
+// A Control Point command and the Data Source fragments that follow it, put together the way the
+// client does
+fuzz_target!(|input: (Vec<u8>, Vec<Vec<u8>>)| {
+    let (command, fragments) = input;
+    let Some(expected) = Expected::from_command(&command) else {
+        return;
+    };
+    let mut buffer = Vec::new();
+    for fragment in fragments {
+        buffer.extend(fragment);
+        match expected.progress(&buffer) {
+            Progress::Complete => {
+                let response = std::mem::take(&mut buffer);
+                match expected {
+                    Expected::Notification { .. } => {
+                        let _ = NotificationAttributes::from_buffer(response).message_truncated();
+                    }
+                    Expected::App { .. } => {
+                        let _ = AppAttributes::from_buffer(response);
+                    }
+                }
+            }
+            Progress::Incomplete => {}
+            Progress::Unrelated => buffer.clear(),
+        }
+    }
+});
@@ -0,0 +1,170 @@
+use bluer::gatt::remote::Characteristic;
+use bluer::{Address, Device, DeviceEvent, DeviceProperty, Uuid};
+use futures::StreamExt;
+use log::debug;
+
+use crate::ancs::control_point::CONTROL_POINT_UUID;
+use crate::ancs::data_source::DATA_SOURCE_UUID;
+use crate::ancs::date::{CURRENT_TIME_SERVICE_UUID, CURRENT_TIME_UUID};
+use crate::ancs::notification_source::NOTIFICATION_SOURCE_UUID;
+use crate::ancs::transport::{
+    ConnectionEvent, ConnectionEvents, LinkInfo, Notifications, Transport, TransportError,
+};
+use crate::ancs::ANCS_SERVICE_UUID;
+use crate::utils::find_characteristic;
+
+const DEVICE_INFORMATION_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000180A00001000800000805F9B34FB);
+const MODEL_NUMBER_UUID: Uuid = Uuid::from_u128(0x00002A2400001000800000805F9B34FB);
+const SOFTWARE_REVISION_UUID: Uuid = Uuid::from_u128(0x00002A2800001000800000805F9B34FB);
+
+// ANCS over a BlueZ connection to the paired phone
+pub struct BluezTransport {
+    adapter_name: String,
+    device: Device,
+    notification_source: Characteristic,
+    control_point: Characteristic,
+    data_source: Characteristic,
+    current_time: Option<Characteristic>,
+}
+
+impl BluezTransport {
+    pub async fn connect(address: Address) -> Result<Self, String> {
+        let session = bluer::Session::new().await.map_err(|e| e.to_string())?;
+        let adapter = session.default_adapter().await.map_err(|e| e.to_string())?;
+        let device = adapter
+            .device(address)
+            .map_err(|_| format!("Cannot find your iphone ({})", address))?;
+
+        // Load ANCS characteristics
+        let notification_source =
+            find_characteristic(&device, ANCS_SERVICE_UUID, NOTIFICATION_SOURCE_UUID)
+                .await
+                .ok_or("Cannot find notification source characteristic")?;
+        let control_point = find_characteristic(&device, ANCS_SERVICE_UUID, CONTROL_POINT_UUID)
+            .await
+            .ok_or("Cannot find control point characteristic")?;
+        let data_source = find_characteristic(&device, ANCS_SERVICE_UUID, DATA_SOURCE_UUID)
+            .await
+            .ok_or("Cannot find data source characteristic")?;
+        debug!("Found all ANCS characteristics");
+        let current_time =
+            find_characteristic(&device, CURRENT_TIME_SERVICE_UUID, CURRENT_TIME_UUID).await;
+
+        Ok(Self {
+            adapter_name: adapter.name().to_string(),
+            device,
+            notification_source,
+            control_point,
+            data_source,
+            current_time,
+        })
+    }
+
+    // Model number or software revision, iPhones only offer some of the Device Information
+    async fn read_device_information(&self, uuid: Uuid) -> Option<String> {
+        let characteristic =
+            find_characteristic(&self.device, DEVICE_INFORMATION_SERVICE_UUID, uuid).await?;
+        let value = characteristic.read().await.ok()?;
+        Some(String::from_utf8_lossy(&value).into_owned())
+    }
+}
+
+impl Transport for BluezTransport {
+    async fn notification_source(&self) -> Result<Notifications, TransportError> {
+        let notify = self.notification_source.notify().await.map_err(other)?;
+        Ok(Box::pin(notify))
+    }
+
+    async fn data_source(&self) -> Result<Notifications, TransportError> {
+        let notify = self.data_source.notify().await.map_err(other)?;
+        Ok(Box::pin(notify))
+    }
+
+    async fn write_control_point(&self, value: Vec<u8>) -> Result<(), TransportError> {
+        self.control_point
+            .write(&value)
+            .await
+            .map_err(|e| match att_error_code(&e.message) {
+                Some(code) => TransportError::Att(code),
+                None if unsent(&e) => TransportError::Unsent(e.to_string()),
+                None => other(e),
+            })
+    }
+
+    async fn connection_events(&self) -> Result<ConnectionEvents, TransportError> {
+        let events = self.device.events().await.map_err(other)?;
+        Ok(Box::pin(events.filter_map(|event| async move {
+            match event {
+                DeviceEvent::PropertyChanged(DeviceProperty::Connected(true)) => {
+                    Some(ConnectionEvent::Connected)
+                }
+                DeviceEvent::PropertyChanged(DeviceProperty::Connected(false)) => {
+                    Some(ConnectionEvent::Disconnected)
+                }
+                _ => None,
+            }
+        })))
+    }
+
+    async fn read_current_time(&self) -> Result<Option<Vec<u8>>, TransportError> {
+        match &self.current_time {
+            Some(current_time) => current_time.read().await.map(Some).map_err(other),
+            None => Ok(None),
+        }
+    }
+
+    async fn reconnect(&self) -> Result<(), TransportError> {
+        if self.device.is_connected().await.map_err(other)? {
+            self.device.disconnect().await.map_err(other)?;
+        }
+        self.device.connect().await.map_err(other)
+    }
+
+    async fn link_info(&self) -> LinkInfo {
+        LinkInfo {
+            adapter: Some(self.adapter_name.clone()),
+            device: Some(self.device.address().to_string()),
+            mtu: self.data_source.mtu().await.ok(),
+            model: self.read_device_information(MODEL_NUMBER_UUID).await,
+            software: self.read_device_information(SOFTWARE_REVISION_UUID).await,
+        }
+    }
+}
+
+fn other(e: bluer::Error) -> TransportError {
+    TransportError::Other(e.to_string())
+}
+
+// BlueZ refused the write before sending anything, it reports a lost link as a plain failure
+// "Not connected"
+fn unsent(e: &bluer::Error) -> bool {
+    match e.kind {
+        bluer::ErrorKind::InProgress
+        | bluer::ErrorKind::NotReady
+        | bluer::ErrorKind::ServicesUnresolved => true,
+        bluer::ErrorKind::Failed => e.message == "Not connected",
+        _ => false,
+    }
+}
+
+// BlueZ only reports the ATT error code in the message, e.g.
+// "Operation failed with ATT error: 0xa2"
+fn att_error_code(message: &str) -> Option<u8> {
+    let (_, code) = message.rsplit_once("ATT error: 0x")?;
+    u8::from_str_radix(code.get(..2)?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn att_errors() {
+        assert_eq!(
+            att_error_code("Operation failed with ATT error: 0xa2"),
+            Some(0xA2)
+        );
+        assert_eq!(att_error_code("ATT error: 0xA0"), Some(0xA0));
+        assert_eq!(att_error_code("Not connected"), None);
+    }
+}
@@ -0,0 +1,436 @@
+use futures::{Stream, StreamExt};
+use log::{debug, error, warn};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ancs::control_point::{
+    self, AncsCommandError, AppAttributeCmd, CommandID, NotificationActionCmd,
+    NotificationAttributeCmd,
+};
+use crate::ancs::data_source::{AppAttributes, Expected, NotificationAttributes, Progress};
+use crate::ancs::transport::{Notifications, Transport, TransportError};
+
+// How long the phone gets to answer a command
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Writes that fail below ANCS, e.g. while BlueZ is busy, are tried this often
+const WRITE_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
+pub enum ClientError {
+    // the phone refused the command, it will not answer on the data source
+    Command(AncsCommandError),
+    Write(TransportError),
+    Timeout,
+    // the data source stream ended, e.g. the phone disconnected
+    Closed,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Command(e) => write!(f, "the phone rejected the command: {}", e),
+            ClientError::Write(e) => write!(f, "cannot write to the control point: {}", e),
+            ClientError::Timeout => write!(f, "no response from the phone"),
+            ClientError::Closed => write!(f, "the data source was closed"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+enum Request {
+    NotificationAttributes {
+        cmd: NotificationAttributeCmd,
+        timeout: Duration,
+        reply: oneshot::Sender<Result<NotificationAttributes, ClientError>>,
+    },
+    AppAttributes {
+        cmd: AppAttributeCmd,
+        timeout: Duration,
+        reply: oneshot::Sender<Result<AppAttributes, ClientError>>,
+    },
+    Action {
+        cmd: NotificationActionCmd,
+        reply: oneshot::Sender<Result<(), ClientError>>,
+    },
+}
+
+// Client for the Control Point and Data Source. ANCS allows a single outstanding command and
+// its response carries no request id, so commands are queued and sent one at a time, and the
+// Data Source is read until the response to the pending command is complete.
+#[derive(Clone)]
+pub struct AncsClient {
+    requests: mpsc::Sender<Request>,
+    timeout: Duration,
+}
+
+impl AncsClient {
+    // Subscribes to the data source and spawns the task that owns the control point
+    pub async fn connect<T: Transport>(transport: Arc<T>) -> Result<Self, TransportError> {
+        let data_source = transport.data_source().await?;
+        let (requests_tx, requests_rx) = mpsc::channel(64);
+        tokio::spawn(run(transport, data_source, requests_rx));
+        Ok(Self {
+            requests: requests_tx,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    // A client sharing the same queue whose requests wait `timeout` for the phone
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            requests: self.requests.clone(),
+            timeout,
+        }
+    }
+
+    pub async fn get_notification_attributes(
+        &self,
+        cmd: NotificationAttributeCmd,
+    ) -> Result<NotificationAttributes, ClientError> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::NotificationAttributes {
+            cmd,
+            timeout: self.timeout,
+            reply,
+        })
+        .await?;
+        response.await.map_err(|_| ClientError::Closed)?
+    }
+
+    pub async fn get_app_attributes(
+        &self,
+        cmd: AppAttributeCmd,
+    ) -> Result<AppAttributes, ClientError> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::AppAttributes {
+            cmd,
+            timeout: self.timeout,
+            reply,
+        })
+        .await?;
+        response.await.map_err(|_| ClientError::Closed)?
+    }
+
+    // Actions get no data source response, they are queued all the same so that they are not
+    // written while the phone is still answering another command
+    pub async fn perform_notification_action(
+        &self,
+        cmd: NotificationActionCmd,
+    ) -> Result<(), ClientError> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::Action { cmd, reply }).await?;
+        response.await.map_err(|_| ClientError::Closed)?
+    }
+
+    async fn send(&self, request: Request) -> Result<(), ClientError> {
+        self.requests
+            .send(request)
+            .await
+            .map_err(|_| ClientError::Closed)
+    }
+}
+
+async fn run<T: Transport>(
+    transport: Arc<T>,
+    mut data_source: Notifications,
+    mut requests: mpsc::Receiver<Request>,
+) {
+    while let Some(request) = requests.recv().await {
+        match request {
+            Request::NotificationAttributes {
+                cmd,
+                timeout,
+                reply,
+            } => {
+                let expected = Expected::Notification {
+                    notification_id: cmd.notification_id,
+                    attributes: cmd.attributes.len(),
+                };
+                let result = match write(
+                    transport.as_ref(),
+                    CommandID::GetNotificationAttributes,
+                    cmd.to_buffer(),
+                )
+                .await
+                {
+                    Ok(()) => receive(&mut data_source, &expected, timeout)
+                        .await
+                        .map(NotificationAttributes::from_buffer),
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+            }
+            Request::AppAttributes {
+                cmd,
+                timeout,
+                reply,
+            } => {
+                let expected = Expected::App {
+                    app_identifier: cmd.app_identifier.clone(),
+                    attributes: cmd.attributes.len(),
+                };
+                let result = match write(
+                    transport.as_ref(),
+                    CommandID::GetAppAttributes,
+                    cmd.to_buffer(),
+                )
+                .await
+                {
+                    Ok(()) => receive(&mut data_source, &expected, timeout)
+                        .await
+                        .map(AppAttributes::from_buffer),
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+            }
+            Request::Action { cmd, reply } => {
+                let result = write(
+                    transport.as_ref(),
+                    CommandID::PerformNotificationAction,
+                    cmd.to_buffer(),
+                )
+                .await;
+                let _ = reply.send(result);
+            }
+        }
+    }
+    debug!("ANCS client stopped");
+}
+
+// Retries writes that never reached the phone. ANCS errors are final, the phone understood the
+// command and refused it. Other failures and actions are not retried, the phone may already have
+// run the command.
+async fn write<T: Transport>(
+    transport: &T,
+    command_id: CommandID,
+    payload: Vec<u8>,
+) -> Result<(), ClientError> {
+    let mut attempt = 1;
+    loop {
+        let e = match control_point::write_command(transport, command_id.clone(), payload.clone())
+            .await
+        {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if let Some(error) = att_code(&e).and_then(AncsCommandError::from_att_code) {
+            log_command_error(&command_id, error);
+            return Err(ClientError::Command(error));
+        }
+        let retry = matches!(e, TransportError::Unsent(_))
+            && !matches!(command_id, CommandID::PerformNotificationAction);
+        if !retry || attempt == WRITE_ATTEMPTS {
+            error!("Cannot write {:?} to the control point: {}", command_id, e);
+            return Err(ClientError::Write(e));
+        }
+        debug!("Retrying {:?} after: {}", command_id, e);
+        attempt += 1;
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+fn att_code(e: &TransportError) -> Option<u8> {
+    match e {
+        TransportError::Att(code) => Some(*code),
+        TransportError::Unsent(_) | TransportError::Other(_) => None,
+    }
+}
+
+fn log_command_error(command_id: &CommandID, error: AncsCommandError) {
+    match error {
+        // we sent something the phone does not understand
+        AncsCommandError::UnknownCommand | AncsCommandError::InvalidCommand => {
+            error!("Phone rejected {:?}: {}", command_id, error)
+        }
+        // usually a notification that was removed in the meantime
+        AncsCommandError::InvalidParameter => debug!("Phone rejected {:?}: {}", command_id, error),
+        AncsCommandError::ActionFailed => warn!("Phone rejected {:?}: {}", command_id, error),
+    }
+}
+
+// Reads Data Source fragments until the expected response is complete. Leftovers of earlier
+// requests that timed out are dropped.
+async fn receive<S>(
+    data_source: &mut S,
+    expected: &Expected,
+    timeout: Duration,
+) -> Result<Vec<u8>, ClientError>
+where
+    S: Stream<Item = Vec<u8>> + Unpin,
+{
+    let deadline = Instant::now() + timeout;
+    let mut buffer = Vec::new();
+    loop {
+        let fragment = match tokio::time::timeout_at(deadline, data_source.next()).await {
+            Ok(Some(fragment)) => fragment,
+            Ok(None) => {
+                error!("Data source closed");
+                return Err(ClientError::Closed);
+            }
+            Err(_) => return Err(ClientError::Timeout),
+        };
+        buffer.extend(fragment);
+        match expected.progress(&buffer) {
+            Progress::Complete => return Ok(buffer),
+            Progress::Incomplete => {}
+            Progress::Unrelated => {
+                warn!("Dropping unexpected data source response: {:?}", buffer);
+                buffer.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::control_point::{ActionID, NotificationAttributeID};
+    use crate::ancs::fake_phone::{FakeNotification, FakePhone};
+    use crate::ancs::transport::ConnectionEvents;
+    use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+    use futures::stream;
+    use std::sync::Mutex;
+
+    fn response(notification_id: u32) -> Vec<u8> {
+        let mut buffer = vec![0];
+        buffer.extend(notification_id.to_le_bytes());
+        buffer.extend([NotificationAttributeID::Title as u8, 5, 0]);
+        buffer.extend(b"Alice");
+        buffer.extend([NotificationAttributeID::Message as u8, 0, 0]);
+        buffer
+    }
+
+    #[tokio::test]
+    async fn fragmented_response() {
+        let expected = Expected::Notification {
+            notification_id: 7,
+            attributes: 2,
+        };
+        // a late response to an earlier request, then ours in two fragments
+        let buffer = response(7);
+        let mut data_source = stream::iter(vec![
+            response(3),
+            buffer[..6].to_vec(),
+            buffer[6..].to_vec(),
+        ]);
+        let received = receive(&mut data_source, &expected, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(received, buffer);
+        let attributes = NotificationAttributes::from_buffer(received);
+        assert_eq!(attributes.title.as_deref(), Some("Alice"));
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let expected = Expected::Notification {
+            notification_id: 7,
+            attributes: 2,
+        };
+        let mut data_source = stream::pending::<Vec<u8>>();
+        let result = receive(&mut data_source, &expected, Duration::from_millis(10)).await;
+        assert!(matches!(result, Err(ClientError::Timeout)));
+        let mut data_source = stream::iter(vec![response(7)[..3].to_vec()]);
+        let result = receive(&mut data_source, &expected, DEFAULT_TIMEOUT).await;
+        assert!(matches!(result, Err(ClientError::Closed)));
+    }
+
+    // Answers notification 7 in two fragments, every other notification is gone
+    struct FakeTransport {
+        data_source_tx: UnboundedSender<Vec<u8>>,
+        data_source_rx: Mutex<Option<UnboundedReceiver<Vec<u8>>>>,
+    }
+
+    impl Transport for FakeTransport {
+        async fn notification_source(&self) -> Result<Notifications, TransportError> {
+            Ok(Box::pin(stream::pending()))
+        }
+
+        async fn data_source(&self) -> Result<Notifications, TransportError> {
+            let data_source_rx = self.data_source_rx.lock().unwrap().take().unwrap();
+            Ok(Box::pin(data_source_rx))
+        }
+
+        async fn write_control_point(&self, value: Vec<u8>) -> Result<(), TransportError> {
+            if value[1..5] != 7u32.to_le_bytes() {
+                return Err(TransportError::Att(
+                    AncsCommandError::InvalidParameter as u8,
+                ));
+            }
+            let buffer = response(7);
+            self.data_source_tx
+                .unbounded_send(buffer[..6].to_vec())
+                .unwrap();
+            self.data_source_tx
+                .unbounded_send(buffer[6..].to_vec())
+                .unwrap();
+            Ok(())
+        }
+
+        async fn connection_events(&self) -> Result<ConnectionEvents, TransportError> {
+            Ok(Box::pin(stream::pending()))
+        }
+
+        async fn read_current_time(&self) -> Result<Option<Vec<u8>>, TransportError> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn client() {
+        let (data_source_tx, data_source_rx) = unbounded();
+        let transport = FakeTransport {
+            data_source_tx,
+            data_source_rx: Mutex::new(Some(data_source_rx)),
+        };
+        let client = AncsClient::connect(Arc::new(transport)).await.unwrap();
+        let attributes = vec![
+            NotificationAttributeID::Title,
+            NotificationAttributeID::Message,
+        ];
+
+        let cmd = NotificationAttributeCmd::new(7, attributes.clone());
+        let attributes_7 = client.get_notification_attributes(cmd).await.unwrap();
+        assert_eq!(attributes_7.title.as_deref(), Some("Alice"));
+        assert_eq!(attributes_7.message.as_deref(), Some(""));
+
+        let cmd = NotificationAttributeCmd::new(8, attributes);
+        let result = client.get_notification_attributes(cmd).await;
+        assert!(matches!(
+            result,
+            Err(ClientError::Command(AncsCommandError::InvalidParameter))
+        ));
+    }
+
+    #[tokio::test]
+    async fn retries() {
+        let phone = FakePhone::new();
+        let notification_id = phone.add(FakeNotification::new("com.a", "Alice", "Hi"));
+        let client = AncsClient::connect(Arc::new(phone.clone())).await.unwrap();
+        let cmd =
+            || NotificationAttributeCmd::new(notification_id, vec![NotificationAttributeID::Title]);
+
+        // never sent, tried again
+        phone.fail_next_write(TransportError::Unsent("busy".to_string()));
+        assert!(client.get_notification_attributes(cmd()).await.is_ok());
+
+        // may have reached the phone
+        phone.fail_next_write(TransportError::Other("timeout".to_string()));
+        let result = client.get_notification_attributes(cmd()).await;
+        assert!(matches!(result, Err(ClientError::Write(_))));
+
+        // an action could run twice
+        phone.fail_next_write(TransportError::Unsent("busy".to_string()));
+        let action = NotificationActionCmd::new(notification_id, ActionID::Positive);
+        let result = client.perform_notification_action(action).await;
+        assert!(matches!(result, Err(ClientError::Write(_))));
+        assert!(phone.performed_actions().is_empty());
+    }
+}
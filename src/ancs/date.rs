@@ -0,0 +1,34 @@
+use chrono::{DateTime, Duration, Local};
+
+pub use ancs_protocol::date::*;
+
+// Human readable age, e.g. "received 5 min ago"
+pub fn relative(then: DateTime<Local>, now: DateTime<Local>) -> String {
+    let age = now - then;
+    if age < Duration::minutes(1) {
+        "just now".to_string()
+    } else if age < Duration::hours(1) {
+        format!("{} min ago", age.num_minutes())
+    } else if age < Duration::days(1) {
+        format!("{} h ago", age.num_hours())
+    } else if age < Duration::days(2) {
+        "yesterday".to_string()
+    } else {
+        format!("{} days ago", age.num_days())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_age() {
+        let now = Local::now();
+        assert_eq!(relative(now - Duration::seconds(10), now), "just now");
+        assert_eq!(relative(now - Duration::minutes(5), now), "5 min ago");
+        assert_eq!(relative(now - Duration::hours(3), now), "3 h ago");
+        assert_eq!(relative(now - Duration::hours(30), now), "yesterday");
+        assert_eq!(relative(now - Duration::days(4), now), "4 days ago");
+    }
+}
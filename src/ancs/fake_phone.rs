@@ -0,0 +1,441 @@
+use chrono::NaiveDateTime;
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::ancs::control_point::{
+    AncsCommandError, AppAttributeCmd, CategoryID, Command, EventFlag, EventID,
+    NotificationActionCmd, NotificationAttributeCmd,
+};
+use crate::ancs::data_source::{AppAttributes, NotificationAttributes};
+use crate::ancs::date;
+use crate::ancs::notification_source::NotificationEvent;
+use crate::ancs::transport::{
+    ConnectionEvent, ConnectionEvents, Notifications, Transport, TransportError,
+};
+
+// A notification held by the fake phone
+#[derive(Clone, Debug)]
+pub struct FakeNotification {
+    pub category_id: CategoryID,
+    pub event_flags: u8,
+    pub app_identifier: String,
+    pub title: String,
+    pub subtitle: String,
+    pub message: String,
+    pub date: Option<NaiveDateTime>,
+    pub positive_action_label: String,
+    pub negative_action_label: String,
+}
+
+impl FakeNotification {
+    pub fn new(app_identifier: &str, title: &str, message: &str) -> Self {
+        Self {
+            category_id: CategoryID::Other,
+            event_flags: 0,
+            app_identifier: app_identifier.to_string(),
+            title: title.to_string(),
+            subtitle: String::new(),
+            message: message.to_string(),
+            date: None,
+            positive_action_label: String::new(),
+            negative_action_label: String::new(),
+        }
+    }
+
+    fn attributes(&self, notification_id: u32) -> NotificationAttributes {
+        NotificationAttributes {
+            notification_id,
+            app_identifier: Some(self.app_identifier.clone()),
+            title: Some(self.title.clone()),
+            subtitle: Some(self.subtitle.clone()),
+            message: Some(self.message.clone()),
+            message_size: Some(self.message.len().min(u16::MAX as usize) as u16),
+            date: self.date,
+            positive_action_label: Some(self.positive_action_label.clone()),
+            negative_action_label: Some(self.negative_action_label.clone()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    disconnected: bool,
+    next_id: u32,
+    notifications: BTreeMap<u32, FakeNotification>,
+    display_names: HashMap<String, String>,
+    notification_source: Option<UnboundedSender<Vec<u8>>>,
+    data_source: Option<UnboundedSender<Vec<u8>>>,
+    connection_events: Vec<UnboundedSender<ConnectionEvent>>,
+    // responses are split into notifications of at most this many bytes
+    fragment_size: Option<usize>,
+    // failures for the next control point writes
+    errors: VecDeque<TransportError>,
+    actions: Vec<(u32, u8)>,
+    current_time: Option<NaiveDateTime>,
+}
+
+impl State {
+    fn event(&self, event_id: u8, event_flags: u8, notification_id: u32, category: CategoryID) {
+        let Some(notification_source) = &self.notification_source else {
+            return;
+        };
+        let category_count = self
+            .notifications
+            .values()
+            .filter(|notification| notification.category_id == category)
+            .count();
+        let event = NotificationEvent {
+            event_id,
+            event_flags,
+            category_id: category as u8,
+            category_count: category_count.min(u8::MAX as usize) as u8,
+            notification_id,
+        };
+        let _ = notification_source.unbounded_send(event.to_buffer());
+    }
+
+    fn respond(&self, buffer: Vec<u8>) {
+        let Some(data_source) = &self.data_source else {
+            return;
+        };
+        let fragment_size = self.fragment_size.unwrap_or(buffer.len()).max(1);
+        for fragment in buffer.chunks(fragment_size) {
+            let _ = data_source.unbounded_send(fragment.to_vec());
+        }
+    }
+
+    // Handles a control point write, returning the data source response if there is one
+    fn command(&mut self, value: &[u8]) -> Result<Option<Vec<u8>>, AncsCommandError> {
+        match Command::from_buffer(value)? {
+            Command::NotificationAttributes(cmd) => self.notification_attributes(&cmd).map(Some),
+            Command::AppAttributes(cmd) => self.app_attributes(&cmd).map(Some),
+            Command::PerformNotificationAction(cmd) => self.perform_action(&cmd).map(|_| None),
+        }
+    }
+
+    fn notification_attributes(
+        &self,
+        cmd: &NotificationAttributeCmd,
+    ) -> Result<Vec<u8>, AncsCommandError> {
+        let notification = self
+            .notifications
+            .get(&cmd.notification_id)
+            .ok_or(AncsCommandError::InvalidParameter)?;
+        Ok(notification.attributes(cmd.notification_id).to_buffer(cmd))
+    }
+
+    fn app_attributes(&self, cmd: &AppAttributeCmd) -> Result<Vec<u8>, AncsCommandError> {
+        let display_name = self
+            .display_names
+            .get(&cmd.app_identifier)
+            .ok_or(AncsCommandError::InvalidParameter)?;
+        let attributes = AppAttributes {
+            app_identifier: cmd.app_identifier.clone(),
+            display_name: Some(display_name.clone()),
+        };
+        Ok(attributes.to_buffer(cmd))
+    }
+
+    // Acting on a notification dismisses it, like answering or declining a call does
+    fn perform_action(&mut self, cmd: &NotificationActionCmd) -> Result<(), AncsCommandError> {
+        let notification = self
+            .notifications
+            .remove(&cmd.notification_id)
+            .ok_or(AncsCommandError::InvalidParameter)?;
+        self.actions
+            .push((cmd.notification_id, cmd.action_id as u8));
+        self.event(
+            EventID::NotificationRemoved as u8,
+            notification.event_flags,
+            cmd.notification_id,
+            notification.category_id,
+        );
+        Ok(())
+    }
+}
+
+// An iPhone acting as ANCS Notification Provider, for running the daemon without Bluetooth.
+// Clones share the same phone, so a test keeps one to drive it while the daemon uses another.
+#[derive(Clone, Default)]
+pub struct FakePhone {
+    state: Arc<Mutex<State>>,
+}
+
+impl FakePhone {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a notification and announces it on the notification source
+    pub fn add(&self, notification: FakeNotification) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        let notification_id = state.next_id;
+        state.next_id += 1;
+        let (event_flags, category) = (notification.event_flags, notification.category_id);
+        state.notifications.insert(notification_id, notification);
+        state.event(
+            EventID::NotificationAdded as u8,
+            event_flags,
+            notification_id,
+            category,
+        );
+        notification_id
+    }
+
+    pub fn modify<F: FnOnce(&mut FakeNotification)>(&self, notification_id: u32, f: F) {
+        let mut state = self.state.lock().unwrap();
+        let Some(notification) = state.notifications.get_mut(&notification_id) else {
+            return;
+        };
+        f(notification);
+        let (event_flags, category) = (notification.event_flags, notification.category_id);
+        state.event(
+            EventID::NotificationModified as u8,
+            event_flags,
+            notification_id,
+            category,
+        );
+    }
+
+    pub fn remove(&self, notification_id: u32) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(notification) = state.notifications.remove(&notification_id) {
+            state.event(
+                EventID::NotificationRemoved as u8,
+                notification.event_flags,
+                notification_id,
+                notification.category_id,
+            );
+        }
+    }
+
+    pub fn set_display_name(&self, app_identifier: &str, display_name: &str) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .display_names
+            .insert(app_identifier.to_string(), display_name.to_string());
+    }
+
+    pub fn set_fragment_size(&self, fragment_size: Option<usize>) {
+        self.state.lock().unwrap().fragment_size = fragment_size;
+    }
+
+    pub fn set_current_time(&self, current_time: Option<NaiveDateTime>) {
+        self.state.lock().unwrap().current_time = current_time;
+    }
+
+    // Makes the next control point write fail, e.g. with `TransportError::Att(0xA1)`
+    pub fn fail_next_write(&self, error: TransportError) {
+        self.state.lock().unwrap().errors.push_back(error);
+    }
+
+    // Notification ids and action ids of the actions performed so far
+    pub fn performed_actions(&self) -> Vec<(u32, u8)> {
+        self.state.lock().unwrap().actions.clone()
+    }
+
+    // Drops the link, the notification and data source streams end
+    pub fn disconnect(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.disconnected = true;
+        state.notification_source = None;
+        state.data_source = None;
+        state
+            .connection_events
+            .retain(|events| events.unbounded_send(ConnectionEvent::Disconnected).is_ok());
+    }
+
+    pub fn reconnect(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.disconnected = false;
+        state
+            .connection_events
+            .retain(|events| events.unbounded_send(ConnectionEvent::Connected).is_ok());
+    }
+
+    fn connected(&self) -> Result<std::sync::MutexGuard<'_, State>, TransportError> {
+        let state = self.state.lock().unwrap();
+        match state.disconnected {
+            true => Err(TransportError::Unsent("not connected".to_string())),
+            false => Ok(state),
+        }
+    }
+}
+
+impl Transport for FakePhone {
+    // Like the real phone, every notification it holds is announced as pre-existing when the
+    // notification source is subscribed to
+    async fn notification_source(&self) -> Result<Notifications, TransportError> {
+        let mut state = self.connected()?;
+        let (notification_source_tx, notification_source_rx) = unbounded();
+        state.notification_source = Some(notification_source_tx);
+        for (&notification_id, notification) in &state.notifications {
+            state.event(
+                EventID::NotificationAdded as u8,
+                notification.event_flags | EventFlag::PreExisting as u8,
+                notification_id,
+                notification.category_id,
+            );
+        }
+        Ok(Box::pin(notification_source_rx))
+    }
+
+    async fn data_source(&self) -> Result<Notifications, TransportError> {
+        let mut state = self.connected()?;
+        let (data_source_tx, data_source_rx) = unbounded();
+        state.data_source = Some(data_source_tx);
+        Ok(Box::pin(data_source_rx))
+    }
+
+    async fn write_control_point(&self, value: Vec<u8>) -> Result<(), TransportError> {
+        let mut state = self.connected()?;
+        if let Some(error) = state.errors.pop_front() {
+            return Err(error);
+        }
+        match state.command(&value) {
+            Ok(Some(response)) => {
+                state.respond(response);
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(e) => Err(TransportError::Att(e as u8)),
+        }
+    }
+
+    async fn connection_events(&self) -> Result<ConnectionEvents, TransportError> {
+        let (events_tx, events_rx) = unbounded();
+        self.state.lock().unwrap().connection_events.push(events_tx);
+        Ok(Box::pin(events_rx))
+    }
+
+    async fn reconnect(&self) -> Result<(), TransportError> {
+        self.disconnect();
+        FakePhone::reconnect(self);
+        Ok(())
+    }
+
+    async fn read_current_time(&self) -> Result<Option<Vec<u8>>, TransportError> {
+        let state = self.connected()?;
+        Ok(state.current_time.map(date::format_current_time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::control_point::{
+        ActionID, AppAttributeID, CommandID, NotificationAttributeID,
+    };
+    use futures::StreamExt;
+
+    async fn write(phone: &FakePhone, command_id: CommandID, payload: Vec<u8>) {
+        let mut value = vec![command_id as u8];
+        value.extend(payload);
+        phone.write_control_point(value).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn events() {
+        let phone = FakePhone::new();
+        let existing = phone.add(FakeNotification::new("com.a", "Alice", "Hi"));
+        let mut notification_source = phone.notification_source().await.unwrap();
+        let event =
+            NotificationEvent::from_buffer(notification_source.next().await.unwrap()).unwrap();
+        assert_eq!(event.notification_id, existing);
+        assert!(EventFlag::PreExisting.is_set(event.event_flags));
+
+        let added = phone.add(FakeNotification::new("com.a", "Bob", "Yo"));
+        phone.remove(existing);
+        let event =
+            NotificationEvent::from_buffer(notification_source.next().await.unwrap()).unwrap();
+        assert_eq!(event.event_id, EventID::NotificationAdded as u8);
+        assert_eq!((event.notification_id, event.category_count), (added, 2));
+        let event =
+            NotificationEvent::from_buffer(notification_source.next().await.unwrap()).unwrap();
+        assert_eq!(event.event_id, EventID::NotificationRemoved as u8);
+        assert_eq!((event.notification_id, event.category_count), (existing, 1));
+    }
+
+    #[tokio::test]
+    async fn attributes() {
+        let phone = FakePhone::new();
+        phone.set_fragment_size(Some(8));
+        phone.set_display_name("com.a", "App");
+        let mut notification = FakeNotification::new("com.a", "Alice", "Hello there");
+        notification.date = date::parse("20240131T093005");
+        let notification_id = phone.add(notification);
+        let mut data_source = phone.data_source().await.unwrap();
+
+        let cmd =
+            NotificationAttributeCmd::new(notification_id, NotificationAttributeID::ALL.to_vec())
+                .with_max_length(NotificationAttributeID::Message, 5);
+        write(
+            &phone,
+            CommandID::GetNotificationAttributes,
+            cmd.to_buffer(),
+        )
+        .await;
+        let mut buffer = Vec::new();
+        while let Ok(Some(fragment)) =
+            tokio::time::timeout(std::time::Duration::from_millis(10), data_source.next()).await
+        {
+            assert!(fragment.len() <= 8);
+            buffer.extend(fragment);
+        }
+        let attributes = NotificationAttributes::from_buffer(buffer);
+        assert_eq!(attributes.title.as_deref(), Some("Alice"));
+        assert_eq!(attributes.message.as_deref(), Some("Hello"));
+        assert_eq!(attributes.message_size, Some(11));
+        assert_eq!(attributes.date, date::parse("20240131T093005"));
+
+        phone.set_fragment_size(None);
+        let cmd = AppAttributeCmd::new("com.a".to_string(), vec![AppAttributeID::Displayname]);
+        write(&phone, CommandID::GetAppAttributes, cmd.to_buffer()).await;
+        let attributes = AppAttributes::from_buffer(data_source.next().await.unwrap());
+        assert_eq!(attributes.display_name.as_deref(), Some("App"));
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let phone = FakePhone::new();
+        let notification_id = phone.add(FakeNotification::new("com.a", "Alice", "Hi"));
+        let cmd = NotificationAttributeCmd::new(notification_id + 1, Vec::new());
+        let mut value = vec![CommandID::GetNotificationAttributes as u8];
+        value.extend(cmd.to_buffer());
+        assert_eq!(
+            phone.write_control_point(value).await,
+            Err(TransportError::Att(
+                AncsCommandError::InvalidParameter as u8
+            ))
+        );
+        assert_eq!(
+            phone.write_control_point(vec![7]).await,
+            Err(TransportError::Att(AncsCommandError::UnknownCommand as u8))
+        );
+        phone.fail_next_write(TransportError::Att(AncsCommandError::ActionFailed as u8));
+        let action = Command::PerformNotificationAction(NotificationActionCmd::new(
+            notification_id,
+            ActionID::Positive,
+        ))
+        .to_buffer();
+        assert!(phone.write_control_point(action.clone()).await.is_err());
+        assert!(phone.write_control_point(action).await.is_ok());
+        assert_eq!(phone.performed_actions(), vec![(notification_id, 0)]);
+    }
+
+    #[tokio::test]
+    async fn disconnect() {
+        let phone = FakePhone::new();
+        let mut events = phone.connection_events().await.unwrap();
+        let mut notification_source = phone.notification_source().await.unwrap();
+        phone.disconnect();
+        assert_eq!(events.next().await, Some(ConnectionEvent::Disconnected));
+        assert_eq!(notification_source.next().await, None);
+        assert!(phone.data_source().await.is_err());
+        phone.reconnect();
+        assert_eq!(events.next().await, Some(ConnectionEvent::Connected));
+        assert!(phone.data_source().await.is_ok());
+    }
+}
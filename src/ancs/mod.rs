@@ -1,8 +1,14 @@
+pub mod bluez;
+pub mod client;
 pub mod control_point;
-pub mod data_source;
+pub mod date;
+pub mod fake_phone;
 pub mod notification;
 pub mod notification_source;
+pub mod replay;
+pub mod socket;
+pub mod trace;
+pub mod transport;
 
-use bluer::Uuid;
-
-pub const ANCS_SERVICE_UUID: Uuid = Uuid::from_u128(0x7905F431B5CE4E99A40F4B1E122D00D0);
+// The protocol itself lives in the `ancs-protocol` crate, the modules above add the transports
+pub use ancs_protocol::{data_source, ANCS_SERVICE_UUID};
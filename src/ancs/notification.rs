@@ -1,47 +1,138 @@
-use libnotify::Notification;
+use chrono::{DateTime, Duration, Local};
+use log::error;
+
+use crate::ancs::data_source::{self, NotificationAttributes};
+use crate::ancs::date;
+use crate::ancs::notification_source::NotificationEvent;
+use crate::events::Notification;
+use crate::notify::{Desktop, FULL_MESSAGE_ACTION};
 
 #[derive(Debug)]
 pub struct ANCSNotification {
-    notification: Notification,
-    title: Option<String>,
-    message: Option<String>,
-    icon: Option<String>,
+    // id of the desktop notification once shown
+    pub desktop_id: Option<u32>,
+    pub category_id: u8,
+    // flags of the latest added or modified event
+    pub event_flags: u8,
+    pub app_identifier: Option<String>,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub message: Option<String>,
+    pub message_size: Option<u16>,
+    // when the phone received the notification
+    pub date: Option<DateTime<Local>>,
+    pub positive_action_label: Option<String>,
+    pub negative_action_label: Option<String>,
 }
 
 impl ANCSNotification {
-    pub fn new() -> Self {
-        let n = Notification::new(String::new().as_str(), None, None);
-
+    pub fn new(event: NotificationEvent) -> Self {
         Self {
-            notification: n,
+            desktop_id: None,
+            category_id: event.category_id,
+            event_flags: event.event_flags,
+            app_identifier: None,
             title: None,
+            subtitle: None,
             message: None,
-            icon: None,
+            message_size: None,
+            date: None,
+            positive_action_label: None,
+            negative_action_label: None,
         }
     }
 
-    pub fn update(&mut self, title: Option<String>, message: Option<String>) {
-        if title.is_some() {
-            self.title = title;
+    // `date` is the attributes' date converted with the phone's clock
+    pub fn update(&mut self, attributes: NotificationAttributes, date: Option<DateTime<Local>>) {
+        if attributes.app_identifier.is_some() {
+            self.app_identifier = attributes.app_identifier;
+        }
+        if attributes.title.is_some() {
+            self.title = attributes.title;
+        }
+        if attributes.subtitle.is_some() {
+            self.subtitle = attributes.subtitle;
+        }
+        if attributes.message.is_some() {
+            self.message = attributes.message;
+        }
+        if attributes.message_size.is_some() {
+            self.message_size = attributes.message_size;
         }
-        if message.is_some() {
-            self.message = message;
+        if date.is_some() {
+            self.date = date;
+        }
+        if attributes.positive_action_label.is_some() {
+            self.positive_action_label = attributes.positive_action_label;
+        }
+        if attributes.negative_action_label.is_some() {
+            self.negative_action_label = attributes.negative_action_label;
+        }
+    }
+
+    // What front-ends are told about the notification, `app_name` is the app's display name
+    pub fn snapshot(&self, notification_id: u32, app_name: Option<String>) -> Notification {
+        Notification {
+            notification_id,
+            app_identifier: self.app_identifier.clone(),
+            app_name,
+            category_id: self.category_id,
+            event_flags: self.event_flags,
+            title: self.title.clone(),
+            subtitle: self.subtitle.clone(),
+            message: self.message.clone(),
+            message_truncated: self.message_truncated(),
+            date: self.date,
+            positive_action_label: self.positive_action_label.clone(),
+            negative_action_label: self.negative_action_label.clone(),
         }
-        self.notification.update(
-            self.title.as_deref().unwrap_or(String::new().as_str()),
-            self.message.as_deref(),
-            self.icon.as_deref(),
-        ).unwrap();
-        if self.displayable() {
-            self.show();
+    }
+
+    pub fn body(&self) -> String {
+        let mut body = self.message.clone().unwrap_or_default();
+        if self.message_truncated() {
+            body += "…";
+        }
+        // notifications that reach us late, e.g. after a reconnect, say how old they are
+        let now = Local::now();
+        if let Some(date) = self.date.filter(|date| now - *date > Duration::minutes(1)) {
+            body += &format!("\nreceived {}", date::relative(date, now));
+        }
+        body
+    }
+
+    // Shows the notification on the desktop, or updates it in place if it is already shown
+    pub async fn show(&mut self, desktop: &impl Desktop) {
+        let mut actions = Vec::new();
+        if self.message_truncated() {
+            actions.push((FULL_MESSAGE_ACTION, "Show full message"));
+        }
+        match desktop
+            .notify(
+                self.desktop_id.unwrap_or(0),
+                self.title.as_deref().unwrap_or_default(),
+                &self.body(),
+                &actions,
+            )
+            .await
+        {
+            Ok(desktop_id) => self.desktop_id = Some(desktop_id),
+            Err(e) => error!("Cannot show notification: {}", e),
+        }
+    }
+
+    pub async fn close(&mut self, desktop: &impl Desktop) {
+        if let Some(desktop_id) = self.desktop_id.take() {
+            let _ = desktop.close(desktop_id).await;
         }
     }
 
-    pub fn show(&self) {
-        self.notification.show().unwrap();
+    pub fn message_truncated(&self) -> bool {
+        data_source::message_truncated(self.message.as_deref(), self.message_size)
     }
 
+    // all requested attributes arrive together, the message may not be requested at all
     pub fn displayable(&self) -> bool {
-        self.title.is_some() && self.message.is_some()
+        self.title.is_some()
     }
 }
@@ -0,0 +1,257 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use log::{debug, info, warn};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use std::time::Duration;
+
+use crate::ancs::trace::{hex, Packet, Trace};
+use crate::ancs::transport::{
+    ConnectionEvent, ConnectionEvents, LinkInfo, Notifications, Transport, TransportError,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pace {
+    // the recorded gaps divided by the factor, 1 keeps the original timing
+    Scaled(f64),
+    Fast,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    notification_source: Option<UnboundedSender<Vec<u8>>>,
+    data_source: Option<UnboundedSender<Vec<u8>>>,
+    connection_events: Vec<UnboundedSender<ConnectionEvent>>,
+}
+
+type Write = (Vec<u8>, oneshot::Sender<Result<(), TransportError>>);
+
+// Plays a recorded trace back as if the phone sent it. Data Source responses follow the daemon's
+// Control Point writes in the recorded order, so a replay goes the same way at any pace.
+pub struct ReplayTransport {
+    subscribers: watch::Sender<Subscribers>,
+    writes: mpsc::UnboundedSender<Write>,
+    current_time: Option<Vec<u8>>,
+    link: LinkInfo,
+}
+
+impl ReplayTransport {
+    pub fn new(trace: Trace, pace: Pace) -> Self {
+        let (subscribers, _) = watch::channel(Subscribers::default());
+        let (writes_tx, writes_rx) = mpsc::unbounded_channel();
+        let current_time = trace
+            .records
+            .iter()
+            .find_map(|record| match &record.packet {
+                Packet::Time { data } => Some(data.clone()),
+                _ => None,
+            });
+        let link = trace.header.link.clone();
+        tokio::spawn(play(trace, pace, subscribers.clone(), writes_rx));
+        Self {
+            subscribers,
+            writes: writes_tx,
+            current_time,
+            link,
+        }
+    }
+}
+
+async fn play(
+    trace: Trace,
+    pace: Pace,
+    sender: watch::Sender<Subscribers>,
+    mut writes: mpsc::UnboundedReceiver<Write>,
+) {
+    let mut subscribers = sender.subscribe();
+    // nothing is sent before the daemon listens
+    let _ = subscribers
+        .wait_for(|subscribers| subscribers.notification_source.is_some())
+        .await;
+    let mut previous = 0;
+    for record in trace.records {
+        if let Pace::Scaled(speed) = pace {
+            let gap = record.t.saturating_sub(previous) as f64 / speed;
+            tokio::time::sleep(Duration::from_secs_f64(gap / 1_000_000.0)).await;
+        }
+        previous = record.t;
+        match record.packet {
+            Packet::Ns { data } => {
+                if let Ok(subscribers) = subscribers
+                    .wait_for(|subscribers| subscribers.notification_source.is_some())
+                    .await
+                {
+                    let _ = subscribers
+                        .notification_source
+                        .as_ref()
+                        .unwrap()
+                        .unbounded_send(data);
+                }
+            }
+            Packet::Ds { data } => {
+                if let Ok(subscribers) = subscribers
+                    .wait_for(|subscribers| subscribers.data_source.is_some())
+                    .await
+                {
+                    let _ = subscribers
+                        .data_source
+                        .as_ref()
+                        .unwrap()
+                        .unbounded_send(data);
+                }
+            }
+            Packet::Cp {
+                data,
+                att_error,
+                failed,
+                unsent,
+            } => {
+                debug!("Waiting for control point write {}", hex::encode(&data));
+                let Some((written, reply)) = writes.recv().await else {
+                    return;
+                };
+                if written != data {
+                    warn!(
+                        "Control point write {} differs from the trace, {} was recorded",
+                        hex::encode(&written),
+                        hex::encode(&data)
+                    );
+                }
+                let result = match (att_error, failed) {
+                    (Some(code), _) => Err(TransportError::Att(code)),
+                    (None, Some(message)) if unsent => Err(TransportError::Unsent(message)),
+                    (None, Some(message)) => Err(TransportError::Other(message)),
+                    (None, None) => Ok(()),
+                };
+                let _ = reply.send(result);
+            }
+            // answered by `read_current_time`
+            Packet::Time { .. } => {}
+            Packet::Connected => {
+                if let Ok(subscribers) = subscribers
+                    .wait_for(|subscribers| !subscribers.connection_events.is_empty())
+                    .await
+                {
+                    for events in &subscribers.connection_events {
+                        let _ = events.unbounded_send(ConnectionEvent::Connected);
+                    }
+                }
+            }
+            Packet::Disconnected => {
+                // subscriptions end with the connection
+                sender.send_modify(|subscribers| {
+                    subscribers.notification_source = None;
+                    subscribers.data_source = None;
+                    for events in &subscribers.connection_events {
+                        let _ = events.unbounded_send(ConnectionEvent::Disconnected);
+                    }
+                });
+            }
+        }
+    }
+    info!("Replay finished");
+    // later writes fail instead of waiting for a response that will never come
+    writes.close();
+    while let Some((_, reply)) = writes.recv().await {
+        let _ = reply.send(Err(TransportError::Other("replay finished".to_string())));
+    }
+}
+
+impl Transport for ReplayTransport {
+    async fn notification_source(&self) -> Result<Notifications, TransportError> {
+        let (notify_tx, notify_rx) = unbounded();
+        self.subscribers
+            .send_modify(|subscribers| subscribers.notification_source = Some(notify_tx));
+        Ok(Box::pin(notify_rx))
+    }
+
+    async fn data_source(&self) -> Result<Notifications, TransportError> {
+        let (notify_tx, notify_rx) = unbounded();
+        self.subscribers
+            .send_modify(|subscribers| subscribers.data_source = Some(notify_tx));
+        Ok(Box::pin(notify_rx))
+    }
+
+    async fn write_control_point(&self, value: Vec<u8>) -> Result<(), TransportError> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let finished = || TransportError::Other("replay finished".to_string());
+        self.writes
+            .send((value, reply_tx))
+            .map_err(|_| finished())?;
+        reply_rx.await.map_err(|_| finished())?
+    }
+
+    async fn connection_events(&self) -> Result<ConnectionEvents, TransportError> {
+        let (events_tx, events_rx) = unbounded();
+        self.subscribers
+            .send_modify(|subscribers| subscribers.connection_events.push(events_tx));
+        Ok(Box::pin(events_rx))
+    }
+
+    async fn read_current_time(&self) -> Result<Option<Vec<u8>>, TransportError> {
+        Ok(self.current_time.clone())
+    }
+
+    async fn link_info(&self) -> LinkInfo {
+        self.link.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::client::AncsClient;
+    use crate::ancs::control_point::{NotificationAttributeCmd, NotificationAttributeID};
+    use crate::ancs::fake_phone::{FakeNotification, FakePhone};
+    use crate::ancs::notification_source::NotificationEvent;
+    use crate::ancs::trace::RecordingTransport;
+    use futures::StreamExt;
+    use std::sync::Arc;
+
+    // what a client sees of one notification
+    async fn session<T: Transport>(transport: Arc<T>) -> (u32, Option<String>) {
+        let mut notification_source = transport.notification_source().await.unwrap();
+        let client = AncsClient::connect(transport.clone()).await.unwrap();
+        let event =
+            NotificationEvent::from_buffer(notification_source.next().await.unwrap()).unwrap();
+        let cmd = NotificationAttributeCmd::new(
+            event.notification_id,
+            vec![NotificationAttributeID::Title],
+        );
+        let attributes = client.get_notification_attributes(cmd).await.unwrap();
+        (event.notification_id, attributes.title)
+    }
+
+    #[tokio::test]
+    async fn replays_recording() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("trace.jsonl");
+        let phone = FakePhone::new();
+        phone.set_fragment_size(Some(5));
+        phone.add(FakeNotification::new("com.a", "Alice", "Hi"));
+        let recording = RecordingTransport::create(phone, &path).await.unwrap();
+        let recorded = session(Arc::new(recording)).await;
+        assert_eq!(recorded.1.as_deref(), Some("Alice"));
+
+        for pace in [Pace::Fast, Pace::Scaled(100.0)] {
+            let replay = ReplayTransport::new(Trace::read(&path).unwrap(), pace);
+            assert_eq!(session(Arc::new(replay)).await, recorded);
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_after_the_end_fail() {
+        let trace = Trace::from_reader(
+            "{\"ancs_trace\":1,\"started\":\"2024-05-01T09:30:00+02:00\"}\n\
+             {\"t\":10,\"kind\":\"cp\",\"data\":\"02\",\"att_error\":161}\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let replay = ReplayTransport::new(trace, Pace::Fast);
+        let _notification_source = replay.notification_source().await.unwrap();
+        assert_eq!(
+            replay.write_control_point(vec![2]).await,
+            Err(TransportError::Att(0xA1))
+        );
+        assert!(replay.write_control_point(vec![2]).await.is_err());
+    }
+}
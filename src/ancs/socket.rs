@@ -0,0 +1,382 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::StreamExt;
+use log::{debug, warn};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::UnixStream;
+use tokio::sync::{mpsc, oneshot};
+
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::ancs::transport::{
+    ConnectionEvent, ConnectionEvents, Notifications, Transport, TransportError,
+};
+
+// ANCS over a local socket to `ancs-sim`. Frames are a kind byte, a little endian u32 length
+// and the payload. Requests from the daemon are answered in order with a `Reply`.
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FrameKind {
+    // simulator to daemon
+    NotificationSource,
+    DataSource,
+    Connection,
+    Reply,
+    // daemon to simulator, each is answered with a `Reply`
+    Subscribe,
+    ControlPoint,
+    CurrentTime,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FrameKind::NotificationSource),
+            1 => Ok(FrameKind::DataSource),
+            2 => Ok(FrameKind::Connection),
+            3 => Ok(FrameKind::Reply),
+            4 => Ok(FrameKind::Subscribe),
+            5 => Ok(FrameKind::ControlPoint),
+            6 => Ok(FrameKind::CurrentTime),
+            _ => Err(value),
+        }
+    }
+}
+
+// Subscribe payloads
+const NOTIFICATION_SOURCE: u8 = 0;
+const DATA_SOURCE: u8 = 1;
+
+// Reply payloads start with a status, followed by data, an ATT error code or a message
+const REPLY_OK: u8 = 0;
+const REPLY_ATT_ERROR: u8 = 1;
+const REPLY_ERROR: u8 = 2;
+const REPLY_UNSENT: u8 = 3;
+
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    kind: FrameKind,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut buffer = vec![kind as u8];
+    buffer.extend((payload.len() as u32).to_le_bytes());
+    buffer.extend(payload);
+    writer.write_all(&buffer).await
+}
+
+// `None` once the other side closed the socket
+async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<(FrameKind, Vec<u8>)>> {
+    let kind = match reader.read_u8().await {
+        Ok(kind) => kind,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let kind = FrameKind::try_from(kind).map_err(|kind| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown frame kind {}", kind),
+        )
+    })?;
+    let length = reader.read_u32_le().await?;
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some((kind, payload)))
+}
+
+fn reply_payload(result: Result<Vec<u8>, TransportError>) -> Vec<u8> {
+    match result {
+        Ok(data) => [vec![REPLY_OK], data].concat(),
+        Err(TransportError::Att(code)) => vec![REPLY_ATT_ERROR, code],
+        Err(TransportError::Unsent(message)) => [vec![REPLY_UNSENT], message.into_bytes()].concat(),
+        Err(TransportError::Other(message)) => [vec![REPLY_ERROR], message.into_bytes()].concat(),
+    }
+}
+
+fn parse_reply(payload: &[u8]) -> Result<Vec<u8>, TransportError> {
+    match payload.split_first() {
+        Some((&REPLY_OK, data)) => Ok(data.to_vec()),
+        Some((&REPLY_ATT_ERROR, [code])) => Err(TransportError::Att(*code)),
+        Some((&REPLY_UNSENT, message)) => Err(TransportError::Unsent(
+            String::from_utf8_lossy(message).into_owned(),
+        )),
+        Some((&REPLY_ERROR, message)) => Err(TransportError::Other(
+            String::from_utf8_lossy(message).into_owned(),
+        )),
+        _ => Err(TransportError::Other("malformed reply".to_string())),
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    notification_source: Option<UnboundedSender<Vec<u8>>>,
+    data_source: Option<UnboundedSender<Vec<u8>>>,
+    connection_events: Vec<UnboundedSender<ConnectionEvent>>,
+    replies: VecDeque<oneshot::Sender<Vec<u8>>>,
+    closed: bool,
+}
+
+// The daemon's side of the socket
+pub struct SocketTransport {
+    writer: tokio::sync::Mutex<OwnedWriteHalf>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl SocketTransport {
+    pub async fn connect(socket_path: &Path) -> io::Result<Self> {
+        let (mut reader, writer) = UnixStream::connect(socket_path).await?.into_split();
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let reader_shared = shared.clone();
+        tokio::spawn(async move {
+            loop {
+                match read_frame(&mut reader).await {
+                    Ok(Some((kind, payload))) => dispatch(&reader_shared, kind, payload),
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("Simulator socket: {}", e);
+                        break;
+                    }
+                }
+            }
+            debug!("Simulator closed the socket");
+            // ends the streams and fails pending requests
+            let mut shared = reader_shared.lock().unwrap();
+            shared.closed = true;
+            shared.notification_source = None;
+            shared.data_source = None;
+            shared.replies.clear();
+            for events in shared.connection_events.drain(..) {
+                let _ = events.unbounded_send(ConnectionEvent::Disconnected);
+            }
+        });
+        Ok(Self {
+            writer: tokio::sync::Mutex::new(writer),
+            shared,
+        })
+    }
+
+    async fn request(&self, kind: FrameKind, payload: &[u8]) -> Result<Vec<u8>, TransportError> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        {
+            // queued and written under one lock so replies come back in queue order
+            let mut writer = self.writer.lock().await;
+            {
+                let mut shared = self.shared.lock().unwrap();
+                if shared.closed {
+                    return Err(TransportError::Other("simulator closed".to_string()));
+                }
+                shared.replies.push_back(reply_tx);
+            }
+            write_frame(&mut *writer, kind, payload)
+                .await
+                .map_err(|e| TransportError::Other(e.to_string()))?;
+        }
+        let reply = reply_rx
+            .await
+            .map_err(|_| TransportError::Other("simulator closed".to_string()))?;
+        parse_reply(&reply)
+    }
+
+    async fn subscribe(&self, source: u8) -> Result<Notifications, TransportError> {
+        let (notify_tx, notify_rx) = unbounded();
+        {
+            let mut shared = self.shared.lock().unwrap();
+            match source {
+                NOTIFICATION_SOURCE => shared.notification_source = Some(notify_tx),
+                _ => shared.data_source = Some(notify_tx),
+            }
+        }
+        self.request(FrameKind::Subscribe, &[source]).await?;
+        Ok(Box::pin(notify_rx))
+    }
+}
+
+fn dispatch(shared: &Mutex<Shared>, kind: FrameKind, payload: Vec<u8>) {
+    let mut shared = shared.lock().unwrap();
+    match kind {
+        FrameKind::NotificationSource => {
+            if let Some(notification_source) = &shared.notification_source {
+                let _ = notification_source.unbounded_send(payload);
+            }
+        }
+        FrameKind::DataSource => {
+            if let Some(data_source) = &shared.data_source {
+                let _ = data_source.unbounded_send(payload);
+            }
+        }
+        FrameKind::Connection => {
+            let event = match payload.first() {
+                Some(1) => ConnectionEvent::Connected,
+                _ => {
+                    // subscriptions end with the connection, like they do over BlueZ
+                    shared.notification_source = None;
+                    shared.data_source = None;
+                    ConnectionEvent::Disconnected
+                }
+            };
+            shared
+                .connection_events
+                .retain(|events| events.unbounded_send(event).is_ok());
+        }
+        FrameKind::Reply => match shared.replies.pop_front() {
+            Some(reply) => {
+                let _ = reply.send(payload);
+            }
+            None => warn!("Unexpected reply from the simulator"),
+        },
+        kind => warn!("Unexpected {:?} frame from the simulator", kind),
+    }
+}
+
+impl Transport for SocketTransport {
+    async fn notification_source(&self) -> Result<Notifications, TransportError> {
+        self.subscribe(NOTIFICATION_SOURCE).await
+    }
+
+    async fn data_source(&self) -> Result<Notifications, TransportError> {
+        self.subscribe(DATA_SOURCE).await
+    }
+
+    async fn write_control_point(&self, value: Vec<u8>) -> Result<(), TransportError> {
+        self.request(FrameKind::ControlPoint, &value)
+            .await
+            .map(|_| ())
+    }
+
+    async fn connection_events(&self) -> Result<ConnectionEvents, TransportError> {
+        let (events_tx, events_rx) = unbounded();
+        self.shared
+            .lock()
+            .unwrap()
+            .connection_events
+            .push(events_tx);
+        Ok(Box::pin(events_rx))
+    }
+
+    async fn read_current_time(&self) -> Result<Option<Vec<u8>>, TransportError> {
+        let current_time = self.request(FrameKind::CurrentTime, &[]).await?;
+        Ok(Some(current_time).filter(|current_time| !current_time.is_empty()))
+    }
+}
+
+// The simulator's side: plays `phone` to the daemon connected on `stream` until it disconnects
+pub async fn serve<T: Transport>(stream: UnixStream, phone: Arc<T>) -> io::Result<()> {
+    let (mut reader, mut writer) = stream.into_split();
+    let (frames_tx, mut frames_rx) = mpsc::unbounded_channel::<(FrameKind, Vec<u8>)>();
+    let writer_task = tokio::spawn(async move {
+        while let Some((kind, payload)) = frames_rx.recv().await {
+            write_frame(&mut writer, kind, &payload).await?;
+        }
+        Ok::<(), io::Error>(())
+    });
+
+    if let Ok(mut events) = phone.connection_events().await {
+        let frames_tx = frames_tx.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                let connected = (event == ConnectionEvent::Connected) as u8;
+                if frames_tx
+                    .send((FrameKind::Connection, vec![connected]))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    while let Some((kind, payload)) = read_frame(&mut reader).await? {
+        let result = match kind {
+            FrameKind::Subscribe => {
+                let (subscription, kind) = match payload.first() {
+                    Some(&NOTIFICATION_SOURCE) => (
+                        phone.notification_source().await,
+                        FrameKind::NotificationSource,
+                    ),
+                    _ => (phone.data_source().await, FrameKind::DataSource),
+                };
+                subscription.map(|mut notifications| {
+                    let frames_tx = frames_tx.clone();
+                    tokio::spawn(async move {
+                        while let Some(value) = notifications.next().await {
+                            if frames_tx.send((kind, value)).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                    Vec::new()
+                })
+            }
+            FrameKind::ControlPoint => phone.write_control_point(payload).await.map(|_| Vec::new()),
+            FrameKind::CurrentTime => phone
+                .read_current_time()
+                .await
+                .map(|current_time| current_time.unwrap_or_default()),
+            kind => {
+                warn!("Unexpected {:?} frame from the daemon", kind);
+                continue;
+            }
+        };
+        if frames_tx
+            .send((FrameKind::Reply, reply_payload(result)))
+            .is_err()
+        {
+            break;
+        }
+    }
+    drop(frames_tx);
+    writer_task.abort();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::client::AncsClient;
+    use crate::ancs::control_point::{NotificationAttributeCmd, NotificationAttributeID};
+    use crate::ancs::fake_phone::{FakeNotification, FakePhone};
+    use crate::ancs::notification_source::NotificationEvent;
+    use tokio::net::UnixListener;
+
+    #[tokio::test]
+    async fn over_socket() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket_path = directory.path().join("sim.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let phone = FakePhone::new();
+        phone.set_fragment_size(Some(4));
+        let notification_id = phone.add(FakeNotification::new("com.a", "Alice", "Hi"));
+        let simulator = phone.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(stream, Arc::new(simulator)).await.unwrap();
+        });
+
+        let transport = Arc::new(SocketTransport::connect(&socket_path).await.unwrap());
+        assert_eq!(transport.read_current_time().await, Ok(None));
+        let mut events = transport.connection_events().await.unwrap();
+        let mut notification_source = transport.notification_source().await.unwrap();
+        let event =
+            NotificationEvent::from_buffer(notification_source.next().await.unwrap()).unwrap();
+        assert_eq!(event.notification_id, notification_id);
+
+        let client = AncsClient::connect(transport.clone()).await.unwrap();
+        let cmd =
+            NotificationAttributeCmd::new(notification_id, vec![NotificationAttributeID::Title]);
+        let attributes = client.get_notification_attributes(cmd).await.unwrap();
+        assert_eq!(attributes.title.as_deref(), Some("Alice"));
+        let cmd = NotificationAttributeCmd::new(notification_id + 1, Vec::new());
+        assert!(client.get_notification_attributes(cmd).await.is_err());
+
+        phone.disconnect();
+        assert_eq!(events.next().await, Some(ConnectionEvent::Disconnected));
+        assert_eq!(notification_source.next().await, None);
+    }
+}
@@ -0,0 +1,369 @@
+use chrono::{DateTime, Local};
+use futures::StreamExt;
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::ancs::transport::{
+    ConnectionEvent, ConnectionEvents, LinkInfo, Notifications, Transport, TransportError,
+};
+
+// Raw ANCS traffic as JSON lines: a header, then one record per packet, e.g.
+//
+//   {"ancs_trace":1,"started":"2024-05-01T09:30:00+02:00","adapter":"hci0","mtu":185}
+//   {"t":1520,"kind":"ns","data":"0018020100000000"}
+//   {"t":1730,"kind":"cp","data":"000000000001ff00"}
+//   {"t":1810,"kind":"ds","data":"0000000000010500416c696365"}
+//
+// `t` counts microseconds from the start, data is hex. Readers reject newer versions.
+pub const VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    #[serde(rename = "ancs_trace")]
+    pub version: u32,
+    pub started: DateTime<Local>,
+    #[serde(flatten)]
+    pub link: LinkInfo,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub t: u64,
+    #[serde(flatten)]
+    pub packet: Packet,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Packet {
+    // Notification Source notification
+    Ns {
+        #[serde(with = "hex")]
+        data: Vec<u8>,
+    },
+    // Data Source notification
+    Ds {
+        #[serde(with = "hex")]
+        data: Vec<u8>,
+    },
+    // Control Point write, with the ATT error or failure if the write did not go through
+    Cp {
+        #[serde(with = "hex")]
+        data: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        att_error: Option<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failed: Option<String>,
+        // the failed write never left this side
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        unsent: bool,
+    },
+    // Current Time read
+    Time {
+        #[serde(with = "hex")]
+        data: Vec<u8>,
+    },
+    Connected,
+    Disconnected,
+}
+
+impl Packet {
+    pub fn cp(data: Vec<u8>, result: &Result<(), TransportError>) -> Self {
+        let (att_error, failed, unsent) = match result {
+            Ok(()) => (None, None, false),
+            Err(TransportError::Att(code)) => (Some(*code), None, false),
+            Err(TransportError::Unsent(message)) => (None, Some(message.clone()), true),
+            Err(TransportError::Other(message)) => (None, Some(message.clone()), false),
+        };
+        Packet::Cp {
+            data,
+            att_error,
+            failed,
+            unsent,
+        }
+    }
+}
+
+pub mod hex {
+    use super::*;
+
+    pub fn encode(data: &[u8]) -> String {
+        data.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn decode(hex: &str) -> Option<Vec<u8>> {
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        decode(&hex).ok_or_else(|| serde::de::Error::custom(format!("invalid hex {:?}", hex)))
+    }
+}
+
+pub struct Trace {
+    pub header: Header,
+    pub records: Vec<Record>,
+}
+
+impl Trace {
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::from_reader(BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self, String> {
+        let mut lines = reader.lines().enumerate();
+        let header: Header = match lines.next() {
+            Some((_, line)) => serde_json::from_str(&line.map_err(|e| e.to_string())?)
+                .map_err(|e| format!("not an ANCS trace: {}", e))?,
+            None => return Err("empty trace".to_string()),
+        };
+        if header.version > VERSION {
+            return Err(format!(
+                "trace version {} is newer than {}",
+                header.version, VERSION
+            ));
+        }
+        let mut records = Vec::new();
+        for (index, line) in lines {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record =
+                serde_json::from_str(&line).map_err(|e| format!("line {}: {}", index + 1, e))?;
+            records.push(record);
+        }
+        Ok(Self { header, records })
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", serde_json::to_string(&self.header)?)?;
+        for record in &self.records {
+            writeln!(writer, "{}", serde_json::to_string(record)?)?;
+        }
+        Ok(())
+    }
+}
+
+// Appends records as they happen, flushed per line so a crash keeps what came before
+pub struct TraceWriter {
+    file: Mutex<LineWriter<File>>,
+    start: Instant,
+}
+
+impl TraceWriter {
+    pub fn create(path: &Path, link: LinkInfo) -> io::Result<Self> {
+        let mut file = LineWriter::new(File::create(path)?);
+        let header = Header {
+            version: VERSION,
+            started: Local::now(),
+            link,
+        };
+        writeln!(file, "{}", serde_json::to_string(&header)?)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn write(&self, packet: Packet) {
+        let record = Record {
+            t: self.start.elapsed().as_micros() as u64,
+            packet,
+        };
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = serde_json::to_string(&record)
+            .map_err(io::Error::from)
+            .and_then(|line| writeln!(file, "{}", line))
+        {
+            warn!("Cannot write trace: {}", e);
+        }
+    }
+}
+
+// Sees every packet a `RecordingTransport` passes through
+pub trait Tap: Send + Sync {
+    fn packet(&self, packet: Packet);
+}
+
+impl Tap for TraceWriter {
+    fn packet(&self, packet: Packet) {
+        self.write(packet)
+    }
+}
+
+impl Tap for Vec<Arc<dyn Tap>> {
+    fn packet(&self, packet: Packet) {
+        for tap in self {
+            tap.packet(packet.clone());
+        }
+    }
+}
+
+// Passes everything through to `inner` and hands it to the tap on the way
+pub struct RecordingTransport<T> {
+    inner: T,
+    tap: Arc<dyn Tap>,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    pub fn new(inner: T, tap: Arc<dyn Tap>) -> Self {
+        Self { inner, tap }
+    }
+
+    // Records to a trace file
+    pub async fn create(inner: T, path: &Path) -> io::Result<Self> {
+        let trace = TraceWriter::create(path, inner.link_info().await)?;
+        Ok(Self::new(inner, Arc::new(trace)))
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    async fn notification_source(&self) -> Result<Notifications, TransportError> {
+        let tap = self.tap.clone();
+        let notifications = self.inner.notification_source().await?;
+        Ok(Box::pin(notifications.inspect(move |data| {
+            tap.packet(Packet::Ns { data: data.clone() })
+        })))
+    }
+
+    async fn data_source(&self) -> Result<Notifications, TransportError> {
+        let tap = self.tap.clone();
+        let notifications = self.inner.data_source().await?;
+        Ok(Box::pin(notifications.inspect(move |data| {
+            tap.packet(Packet::Ds { data: data.clone() })
+        })))
+    }
+
+    async fn write_control_point(&self, value: Vec<u8>) -> Result<(), TransportError> {
+        let result = self.inner.write_control_point(value.clone()).await;
+        self.tap.packet(Packet::cp(value, &result));
+        result
+    }
+
+    async fn connection_events(&self) -> Result<ConnectionEvents, TransportError> {
+        let tap = self.tap.clone();
+        let events = self.inner.connection_events().await?;
+        Ok(Box::pin(events.inspect(move |event| {
+            tap.packet(match event {
+                ConnectionEvent::Connected => Packet::Connected,
+                ConnectionEvent::Disconnected => Packet::Disconnected,
+            })
+        })))
+    }
+
+    async fn reconnect(&self) -> Result<(), TransportError> {
+        self.inner.reconnect().await
+    }
+
+    async fn read_current_time(&self) -> Result<Option<Vec<u8>>, TransportError> {
+        let current_time = self.inner.read_current_time().await?;
+        if let Some(data) = &current_time {
+            self.tap.packet(Packet::Time { data: data.clone() });
+        }
+        Ok(current_time)
+    }
+
+    async fn link_info(&self) -> LinkInfo {
+        self.inner.link_info().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::control_point::{self, CommandID};
+    use crate::ancs::fake_phone::{FakeNotification, FakePhone};
+
+    #[tokio::test]
+    async fn records_traffic() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("trace.jsonl");
+        let phone = FakePhone::new();
+        let notification_id = phone.add(FakeNotification::new("com.a", "Alice", "Hi"));
+        let transport = RecordingTransport::create(phone.clone(), &path)
+            .await
+            .unwrap();
+
+        let mut notification_source = transport.notification_source().await.unwrap();
+        let mut data_source = transport.data_source().await.unwrap();
+        let event = notification_source.next().await.unwrap();
+        let command = [notification_id.to_le_bytes().to_vec(), vec![1, 8, 0]].concat();
+        control_point::write_command(
+            &transport,
+            CommandID::GetNotificationAttributes,
+            command.clone(),
+        )
+        .await
+        .unwrap();
+        let response = data_source.next().await.unwrap();
+        phone.fail_next_write(TransportError::Att(0xA2));
+        let rejected = transport.write_control_point(vec![0xFF]).await;
+        assert_eq!(rejected, Err(TransportError::Att(0xA2)));
+
+        let trace = Trace::read(&path).unwrap();
+        assert_eq!(trace.header.version, VERSION);
+        let packets: Vec<Packet> = trace.records.iter().map(|r| r.packet.clone()).collect();
+        assert_eq!(
+            packets,
+            vec![
+                Packet::Ns { data: event },
+                Packet::cp(
+                    [vec![CommandID::GetNotificationAttributes as u8], command].concat(),
+                    &Ok(())
+                ),
+                Packet::Ds { data: response },
+                Packet::cp(vec![0xFF], &Err(TransportError::Att(0xA2))),
+            ]
+        );
+        assert!(trace.records.windows(2).all(|w| w[0].t <= w[1].t));
+    }
+
+    #[test]
+    fn format() {
+        let trace = Trace::from_reader(
+            "{\"ancs_trace\":1,\"started\":\"2024-05-01T09:30:00+02:00\",\"mtu\":185}\n\
+             {\"t\":1520,\"kind\":\"ns\",\"data\":\"0018020100000000\"}\n\
+             {\"t\":1900,\"kind\":\"disconnected\"}\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(trace.header.link.mtu, Some(185));
+        assert_eq!(
+            trace.records[0].packet,
+            Packet::Ns {
+                data: vec![0, 0x18, 2, 1, 0, 0, 0, 0]
+            }
+        );
+        assert_eq!(trace.records[1].packet, Packet::Disconnected);
+
+        let mut written = Vec::new();
+        trace.write_to(&mut written).unwrap();
+        let reread = Trace::from_reader(written.as_slice()).unwrap();
+        assert_eq!(reread.records, trace.records);
+
+        let newer = "{\"ancs_trace\":2,\"started\":\"2024-05-01T09:30:00+02:00\"}\n";
+        assert!(Trace::from_reader(newer.as_bytes()).is_err());
+        let bad_hex = "{\"ancs_trace\":1,\"started\":\"2024-05-01T09:30:00+02:00\"}\n\
+                       {\"t\":1,\"kind\":\"ds\",\"data\":\"0g\"}\n";
+        assert!(Trace::from_reader(bad_hex.as_bytes()).is_err());
+    }
+}
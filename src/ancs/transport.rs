@@ -0,0 +1,93 @@
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+// Values notified on the Notification Source or Data Source
+pub type Notifications = Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>;
+pub type ConnectionEvents = Pin<Box<dyn Stream<Item = ConnectionEvent> + Send>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionEvent {
+    Connected,
+    Disconnected,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransportError {
+    // the phone answered a write with this ATT error code
+    Att(u8),
+    // the write never left this side, e.g. while not connected, and can safely be tried again
+    Unsent(String),
+    // anything else, a write may have reached the phone
+    Other(String),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Att(code) => write!(f, "ATT error 0x{:02X}", code),
+            TransportError::Unsent(message) | TransportError::Other(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+// What the backend knows about the adapter and the phone, recorded in trace headers
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LinkInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adapter: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<usize>,
+    // Device Information model number, e.g. "iPhone15,2"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    // Device Information software revision, the iOS version on phones that report it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub software: Option<String>,
+}
+
+// The link to the phone's ANCS characteristics. BlueZ is one backend, tests drive the same
+// protocol flow through fakes.
+pub trait Transport: Send + Sync + 'static {
+    fn notification_source(
+        &self,
+    ) -> impl Future<Output = Result<Notifications, TransportError>> + Send;
+
+    fn data_source(&self) -> impl Future<Output = Result<Notifications, TransportError>> + Send;
+
+    fn write_control_point(
+        &self,
+        value: Vec<u8>,
+    ) -> impl Future<Output = Result<(), TransportError>> + Send;
+
+    fn connection_events(
+        &self,
+    ) -> impl Future<Output = Result<ConnectionEvents, TransportError>> + Send;
+
+    // Current Time characteristic value, `None` if the phone does not offer the service
+    fn read_current_time(
+        &self,
+    ) -> impl Future<Output = Result<Option<Vec<u8>>, TransportError>> + Send;
+
+    fn link_info(&self) -> impl Future<Output = LinkInfo> + Send {
+        async { LinkInfo::default() }
+    }
+
+    // Drops the link and connects again, the phone shows up again as a connection event
+    fn reconnect(&self) -> impl Future<Output = Result<(), TransportError>> + Send {
+        async {
+            Err(TransportError::Other(
+                "reconnecting is not supported here".to_string(),
+            ))
+        }
+    }
+}
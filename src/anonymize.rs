@@ -0,0 +1,245 @@
+use crate::ancs::control_point::{AppAttributeID, NotificationAttributeID};
+use crate::ancs::data_source::{Expected, Progress};
+use crate::ancs::trace::{Packet, Trace};
+
+// Replaces titles, subtitles, messages and app display names in a trace so it can be attached
+// to a bug report. Every character keeps its UTF-8 length and values cut in the middle of a
+// character stay cut, so lengths, attribute framing and fragment boundaries are unchanged and
+// the trace still reproduces parser bugs. The phone's address is dropped from the header.
+pub fn trace(trace: &mut Trace) {
+    trace.header.link.device = None;
+    let mut expected = None;
+    // Data Source records of the response being put together
+    let mut fragments = Vec::new();
+    for index in 0..trace.records.len() {
+        match &trace.records[index].packet {
+            Packet::Cp { data, .. } => {
+                let next = Expected::from_command(data);
+                flush(trace, &mut fragments, expected.as_ref());
+                expected = next;
+            }
+            Packet::Ds { .. } => {
+                fragments.push(index);
+                let response = concat(trace, &fragments);
+                match expected
+                    .as_ref()
+                    .map(|expected| expected.progress(&response))
+                {
+                    Some(Progress::Complete) => {
+                        flush(trace, &mut fragments, expected.as_ref());
+                        expected = None;
+                    }
+                    Some(Progress::Incomplete) => {}
+                    // not ours to read, scrubbed as if it were a notification response
+                    _ => flush(trace, &mut fragments, None),
+                }
+            }
+            Packet::Disconnected => {
+                flush(trace, &mut fragments, expected.as_ref());
+                expected = None;
+            }
+            _ => {}
+        }
+    }
+    flush(trace, &mut fragments, expected.as_ref());
+}
+
+fn concat(trace: &Trace, fragments: &[usize]) -> Vec<u8> {
+    fragments
+        .iter()
+        .flat_map(|&index| match &trace.records[index].packet {
+            Packet::Ds { data } => data.clone(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+// Scrubs the response in `fragments` and puts it back with the same fragment lengths
+fn flush(trace: &mut Trace, fragments: &mut Vec<usize>, expected: Option<&Expected>) {
+    if fragments.is_empty() {
+        return;
+    }
+    let mut response = concat(trace, fragments);
+    scrub_response(
+        &mut response,
+        matches!(expected, Some(Expected::App { .. })),
+    );
+    let mut rest = response.as_slice();
+    for index in fragments.drain(..) {
+        if let Packet::Ds { data } = &mut trace.records[index].packet {
+            let (fragment, tail) = rest.split_at(data.len());
+            data.copy_from_slice(fragment);
+            rest = tail;
+        }
+    }
+}
+
+// Walks the attribute framing as far as the response goes, a value cut short is scrubbed as far
+// as it is there
+fn scrub_response(response: &mut [u8], app: bool) {
+    let attributes_start = if app {
+        match response.iter().position(|&b| b == 0) {
+            Some(null_terminator) => null_terminator + 1,
+            None => return,
+        }
+    } else {
+        // command id and notification id
+        5
+    };
+    let mut offset = attributes_start;
+    while offset + 3 <= response.len() {
+        let attribute_id = response[offset];
+        let length = u16::from_le_bytes([response[offset + 1], response[offset + 2]]) as usize;
+        let start = offset + 3;
+        let end = (start + length).min(response.len());
+        let personal = if app {
+            attribute_id == AppAttributeID::Displayname as u8
+        } else {
+            attribute_id == NotificationAttributeID::Title as u8
+                || attribute_id == NotificationAttributeID::Subtitle as u8
+                || attribute_id == NotificationAttributeID::Message as u8
+        };
+        if personal {
+            scrub(&mut response[start..end]);
+        }
+        offset = start + length;
+    }
+}
+
+// Letters become x, digits 0 and other characters one of the same UTF-8 length. Whitespace and
+// ASCII punctuation are kept, they rarely say much and often matter to layout bugs.
+fn scrub(value: &mut [u8]) {
+    let mut scrubbed = Vec::with_capacity(value.len());
+    for chunk in value.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            let replacement = match c {
+                'a'..='z' => 'x',
+                'A'..='Z' => 'X',
+                '0'..='9' => '0',
+                c if c.is_ascii() || c.is_whitespace() => c,
+                c => same_length(c.len_utf8()),
+            };
+            let mut buffer = [0; 4];
+            scrubbed.extend(replacement.encode_utf8(&mut buffer).as_bytes());
+        }
+        // a character cut by the maximum length keeps the lead byte's length, other bytes that
+        // are not UTF-8 carry no text
+        let invalid = chunk.invalid();
+        match invalid.first().map(|&lead| lead.leading_ones() as usize) {
+            Some(length @ 2..=4) => {
+                let mut buffer = [0; 4];
+                let replacement = same_length(length).encode_utf8(&mut buffer);
+                scrubbed.extend(&replacement.as_bytes()[..invalid.len().min(length)]);
+                scrubbed.extend(&invalid[invalid.len().min(length)..]);
+            }
+            _ => scrubbed.extend(invalid),
+        }
+    }
+    value.copy_from_slice(&scrubbed);
+}
+
+fn same_length(utf8_length: usize) -> char {
+    match utf8_length {
+        1 => 'x',
+        2 => 'é',
+        3 => '…',
+        _ => '😀',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::trace::{Header, Record, VERSION};
+    use crate::ancs::transport::LinkInfo;
+    use chrono::Local;
+    use std::str;
+
+    fn trace(packets: Vec<Packet>) -> Trace {
+        Trace {
+            header: Header {
+                version: VERSION,
+                started: Local::now(),
+                link: LinkInfo {
+                    device: Some("11:22:33:44:55:66".to_string()),
+                    ..Default::default()
+                },
+            },
+            records: packets
+                .into_iter()
+                .map(|packet| Record { t: 0, packet })
+                .collect(),
+        }
+    }
+
+    fn ds(data: &[u8]) -> Packet {
+        Packet::Ds {
+            data: data.to_vec(),
+        }
+    }
+
+    fn data(packet: &Packet) -> &[u8] {
+        match packet {
+            Packet::Ds { data } => data,
+            _ => panic!("not a data source packet"),
+        }
+    }
+
+    #[test]
+    fn characters() {
+        let mut value = "Hi Zoë, 10€ 🎉?".as_bytes().to_vec();
+        scrub(&mut value);
+        assert_eq!(str::from_utf8(&value).unwrap(), "Xx Xxé, 00… 😀?");
+        // the last character was cut after two of its three bytes
+        let mut value = "ok€".as_bytes()[..4].to_vec();
+        scrub(&mut value);
+        assert_eq!(value, [b'x', b'x', 0xE2, 0x80]);
+    }
+
+    #[test]
+    fn fragmented_responses() {
+        // title "Anna" and message "Zoë 🎉" cut at 6 bytes, split across three fragments
+        let mut response = vec![0, 7, 0, 0, 0, 0, 5, 0];
+        response.extend(b"com.a");
+        response.extend([1, 4, 0]);
+        response.extend(b"Anna");
+        response.extend([3, 6, 0]);
+        response.extend(&"Zoë 🎉".as_bytes()[..6]);
+        response.extend([4, 2, 0, b'1', b'2']);
+        let app_response = b"\x01com.a\0\0\x04\0Mail".to_vec();
+        let mut anonymized = trace(vec![
+            Packet::cp(vec![0, 7, 0, 0, 0, 0, 1, 0xFF, 0xFF, 3, 6, 0, 4], &Ok(())),
+            ds(&response[..10]),
+            ds(&response[10..21]),
+            ds(&response[21..]),
+            Packet::cp(b"\x01com.a\0\0".to_vec(), &Ok(())),
+            ds(&app_response),
+        ]);
+        super::trace(&mut anonymized);
+
+        assert_eq!(anonymized.header.link.device, None);
+        let lengths: Vec<usize> = [1, 2, 3, 5]
+            .iter()
+            .map(|&index| data(&anonymized.records[index].packet).len())
+            .collect();
+        assert_eq!(
+            lengths,
+            vec![10, 11, response.len() - 21, app_response.len()]
+        );
+        let scrubbed: Vec<u8> = (1..4)
+            .flat_map(|index| data(&anonymized.records[index].packet).to_vec())
+            .collect();
+        let mut expected = vec![0, 7, 0, 0, 0, 0, 5, 0];
+        expected.extend(b"com.a");
+        expected.extend([1, 4, 0]);
+        expected.extend(b"Xxxx");
+        expected.extend([3, 6, 0]);
+        expected.extend(&"Xxé 😀".as_bytes()[..6]);
+        expected.extend([4, 2, 0, b'1', b'2']);
+        assert_eq!(scrubbed, expected);
+        assert_eq!(
+            data(&anonymized.records[5].packet),
+            b"\x01com.a\0\0\x04\0Xxxx"
+        );
+    }
+}
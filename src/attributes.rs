@@ -0,0 +1,147 @@
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::ancs::control_point::{NotificationAttributeCmd, NotificationAttributeID};
+
+// `[attributes]` section of ancs.toml, selects what is fetched for every notification
+#[derive(Clone, Debug, Deserialize)]
+pub struct AttributesConfig {
+    #[serde(default = "default_request")]
+    pub request: Vec<String>,
+    // e.g. { title = 64, message = 256 }
+    #[serde(default)]
+    pub max_length: HashMap<String, u16>,
+}
+
+fn default_request() -> Vec<String> {
+    [
+        "AppIdentifier",
+        "Title",
+        "Subtitle",
+        "Message",
+        "MessageSize",
+        "Date",
+    ]
+    .iter()
+    .map(|attribute| attribute.to_string())
+    .collect()
+}
+
+impl Default for AttributesConfig {
+    fn default() -> Self {
+        Self {
+            request: default_request(),
+            max_length: HashMap::new(),
+        }
+    }
+}
+
+// Attributes requested when a notification is added or modified
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttributeRequest {
+    attributes: Vec<NotificationAttributeID>,
+    max_lengths: Vec<(NotificationAttributeID, u16)>,
+}
+
+impl AttributeRequest {
+    pub fn from_config(config: &AttributesConfig) -> Result<Self, String> {
+        // the app identifier drives do-not-disturb exceptions and grouping and notifications are
+        // only shown once they have a title, both are always needed
+        let mut attributes = vec![
+            NotificationAttributeID::AppIdentifier,
+            NotificationAttributeID::Title,
+        ];
+        for name in &config.request {
+            let attribute = NotificationAttributeID::from_str(name)?;
+            if !attributes.contains(&attribute) {
+                attributes.push(attribute);
+            }
+        }
+        let mut max_lengths = Vec::new();
+        for (name, max_length) in &config.max_length {
+            let attribute = NotificationAttributeID::from_str(name)?;
+            if !attribute.has_max_length() {
+                return Err(format!("{:?} does not take a maximum length", attribute));
+            }
+            max_lengths.push((attribute, *max_length));
+        }
+        Ok(Self {
+            attributes,
+            max_lengths,
+        })
+    }
+
+    pub fn command(&self, notification_id: u32) -> NotificationAttributeCmd {
+        self.max_lengths.iter().fold(
+            NotificationAttributeCmd::new(notification_id, self.attributes.clone()),
+            |cmd, (attribute, max_length)| cmd.with_max_length(*attribute, *max_length),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private() {
+        let config = AttributesConfig {
+            request: vec!["Title".to_string(), "Date".to_string()],
+            max_length: HashMap::from([("title".to_string(), 64)]),
+        };
+        let request = AttributeRequest::from_config(&config).unwrap();
+        assert_eq!(
+            request.command(1).to_buffer(),
+            vec![1, 0, 0, 0, 0, 1, 64, 0, 5]
+        );
+    }
+
+    // everything the history records, e.g. subtitles and dates, is fetched by default
+    #[test]
+    fn default() {
+        let request = AttributeRequest::from_config(&AttributesConfig::default()).unwrap();
+        let attributes = request.command(1).attributes;
+        for attribute in [
+            NotificationAttributeID::Title,
+            NotificationAttributeID::Subtitle,
+            NotificationAttributeID::Message,
+            NotificationAttributeID::MessageSize,
+            NotificationAttributeID::Date,
+        ] {
+            assert!(attributes.contains(&attribute), "{:?}", attribute);
+        }
+    }
+
+    #[test]
+    fn required() {
+        let config = AttributesConfig {
+            request: vec!["Message".to_string()],
+            ..Default::default()
+        };
+        let request = AttributeRequest::from_config(&config).unwrap();
+        assert_eq!(
+            request.command(1).attributes,
+            vec![
+                NotificationAttributeID::AppIdentifier,
+                NotificationAttributeID::Title,
+                NotificationAttributeID::Message,
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let config = AttributesConfig {
+            request: vec!["Body".to_string()],
+            ..Default::default()
+        };
+        assert!(AttributeRequest::from_config(&config).is_err());
+        let config = AttributesConfig {
+            max_length: HashMap::from([("date".to_string(), 10)]),
+            ..Default::default()
+        };
+        assert!(AttributeRequest::from_config(&config).is_err());
+    }
+}
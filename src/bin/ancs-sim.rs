@@ -0,0 +1,80 @@
+use clap::Parser;
+use log::{error, info};
+use tokio::net::UnixListener;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ancs_desktop::ancs::fake_phone::FakePhone;
+use ancs_desktop::ancs::socket;
+use ancs_desktop::scenario::Scenario;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "ancs-sim",
+    version,
+    about = "Plays a scenario file into `ancs run --simulator` as if an iPhone were connected"
+)]
+struct Args {
+    /// Scenario file, TOML or YAML
+    scenario: PathBuf,
+    /// Socket to listen on, defaults to $XDG_RUNTIME_DIR/ancs/sim.sock
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    /// Playback speed, 2 plays twice as fast
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let mut builder = env_logger::Builder::from_default_env();
+    builder.filter_level(log::LevelFilter::Info).init();
+    let args = Args::parse();
+    if let Err(e) = run(args).await {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    if !args.speed.is_finite() || args.speed <= 0.0 {
+        return Err(format!("invalid speed {}", args.speed));
+    }
+    let scenario = Scenario::load(&args.scenario)?;
+    let socket_path = match args.socket {
+        Some(socket_path) => socket_path,
+        None => xdg::BaseDirectories::with_prefix("ancs")
+            .map_err(|e| e.to_string())?
+            .place_runtime_file("sim.sock")
+            .map_err(|e| e.to_string())?,
+    };
+    // a stale socket from a previous run would make bind fail
+    let _ = std::fs::remove_file(&socket_path);
+    let listener = UnixListener::bind(&socket_path)
+        .map_err(|e| format!("{}: {}", socket_path.display(), e))?;
+    info!(
+        "Waiting for `ancs run --simulator {}`",
+        socket_path.display()
+    );
+    let (stream, _) = listener.accept().await.map_err(|e| e.to_string())?;
+    info!("Daemon connected, playing {}", args.scenario.display());
+
+    let phone = FakePhone::new();
+    scenario.prepare(&phone);
+    let mut serve = tokio::spawn(socket::serve(stream, Arc::new(phone.clone())));
+    tokio::select! {
+        _ = &mut serve => {
+            info!("Daemon disconnected");
+            return Ok(());
+        }
+        _ = scenario.play(&phone, args.speed) => {}
+    }
+    // keep answering, e.g. for full messages, until the daemon or the user quits
+    info!("Scenario finished, press Ctrl-C to quit");
+    tokio::select! {
+        _ = serve => info!("Daemon disconnected"),
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    Ok(())
+}
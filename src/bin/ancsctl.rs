@@ -0,0 +1,174 @@
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ancs_desktop::control;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "ancsctl",
+    version,
+    about = "Controls a running ANCS daemon over its control socket"
+)]
+struct Args {
+    /// Control socket, defaults to $XDG_RUNTIME_DIR/ancs/control.sock
+    #[arg(long, global = true)]
+    socket: Option<PathBuf>,
+    /// Print the daemon's JSON result as is
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Connection, notification count and do-not-disturb
+    Status,
+    /// Notifications currently on the phone
+    List,
+    /// Performs one of the actions the phone offers, e.g. answering or declining a call
+    Action {
+        notification_id: u32,
+        /// positive or negative
+        action: String,
+    },
+    /// Closes the desktop notification, it stays on the phone
+    Dismiss { notification_id: u32 },
+    /// Shows or sets do-not-disturb: on, off or auto (back to the schedule)
+    Dnd { state: Option<String> },
+    /// Fetches the full message of a notification
+    Message { notification_id: u32 },
+    /// Re-reads ancs.toml
+    Reload,
+    /// Drops the link to the phone and connects again
+    Reconnect,
+    /// Prints every event as a JSON object per line until interrupted
+    Events,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let socket_path = match args.socket {
+        Some(socket_path) => socket_path,
+        None => xdg::BaseDirectories::with_prefix("ancs")
+            .map_err(|e| e.to_string())?
+            .find_runtime_file("control.sock")
+            .ok_or("the daemon is not running")?,
+    };
+    let (method, params) = match &args.command {
+        Command::Status => ("status", Value::Null),
+        Command::List => ("list", Value::Null),
+        Command::Action {
+            notification_id,
+            action,
+        } => (
+            "action",
+            json!({ "notification_id": notification_id, "action": action }),
+        ),
+        Command::Dismiss { notification_id } => {
+            ("dismiss", json!({ "notification_id": notification_id }))
+        }
+        Command::Dnd { state } => ("dnd", json!({ "state": state })),
+        Command::Message { notification_id } => {
+            ("message", json!({ "notification_id": notification_id }))
+        }
+        Command::Reload => ("reload", Value::Null),
+        Command::Reconnect => ("reconnect", Value::Null),
+        Command::Events => return events(&socket_path).await,
+    };
+    // full messages and reconnects wait for the phone
+    let result = tokio::time::timeout(
+        Duration::from_secs(15),
+        control::call(&socket_path, method, params),
+    )
+    .await
+    .map_err(|_| "no response from the daemon".to_string())?
+    .map_err(|e| format!("{}: {}", socket_path.display(), e))?
+    .map_err(|e| e.to_string())?;
+
+    if args.json {
+        println!("{}", result);
+        return Ok(());
+    }
+    match args.command {
+        Command::Status => {
+            let connected = match result["connected"].as_bool() {
+                Some(true) => "connected",
+                _ => "disconnected",
+            };
+            println!(
+                "{}, {} notifications, {}",
+                connected,
+                result["notifications"],
+                dnd(&result["dnd"])
+            );
+        }
+        Command::List => {
+            for notification in result.as_array().into_iter().flatten() {
+                println!("{}", list_line(notification));
+            }
+        }
+        Command::Dnd { .. } => println!("{}", dnd(&result)),
+        Command::Message { .. } => println!("{}", result.as_str().unwrap_or_default()),
+        // nothing to show beyond success
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn events(socket_path: &Path) -> Result<(), String> {
+    let mut subscription = control::subscribe(socket_path)
+        .await
+        .map_err(|e| format!("{}: {}", socket_path.display(), e))?;
+    while let Some(event) = subscription.next().await.map_err(|e| e.to_string())? {
+        println!("{}", event);
+    }
+    Ok(())
+}
+
+// Like the daemon's own summary, e.g. "dnd: on (manual), 1 suppressed"
+fn dnd(status: &Value) -> String {
+    format!(
+        "dnd: {} ({}), {} suppressed",
+        if status["active"].as_bool() == Some(true) {
+            "on"
+        } else {
+            "off"
+        },
+        status["mode"].as_str().unwrap_or_default(),
+        status["suppressed"]
+    )
+}
+
+// "<id> [<category>] <app>: <title> - <message>"
+fn list_line(notification: &Value) -> String {
+    let text = |key: &str| notification[key].as_str().unwrap_or_default().to_string();
+    let app = match notification["app_name"].as_str() {
+        Some(app_name) => app_name.to_string(),
+        None => text("app_identifier"),
+    };
+    let mut line = format!(
+        "{} [{}] {}: {}",
+        notification["notification_id"],
+        text("category"),
+        app,
+        text("title")
+    );
+    let message = text("message");
+    if !message.is_empty() {
+        line.push_str(" - ");
+        line.push_str(&message);
+    }
+    line
+}
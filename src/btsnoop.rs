@@ -0,0 +1,643 @@
+use bluer::Uuid;
+use chrono::{Local, TimeZone};
+use log::{debug, warn};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::ancs::control_point::CONTROL_POINT_UUID;
+use crate::ancs::data_source::DATA_SOURCE_UUID;
+use crate::ancs::date::CURRENT_TIME_UUID;
+use crate::ancs::notification_source::NOTIFICATION_SOURCE_UUID;
+use crate::ancs::trace::{self, Header, Packet, Record, Trace};
+use crate::ancs::transport::LinkInfo;
+
+// btsnoop captures as written by `btmon -w`, Android's HCI snoop log or Wireshark. ATT traffic on
+// the ANCS characteristics is turned into a trace, one phone per capture.
+
+const MAGIC: &[u8; 8] = b"btsnoop\0";
+// microseconds from year 0 to the Unix epoch
+const EPOCH_OFFSET: i64 = 0x00DC_DDB3_0F2F_8000;
+
+// datalink types
+const HCI_UNENCAPSULATED: u32 = 1001;
+const HCI_UART: u32 = 1002;
+const LINUX_MONITOR: u32 = 2001;
+
+const H4_ACL: u8 = 0x02;
+const H4_EVENT: u8 = 0x04;
+
+const MONITOR_EVENT: u16 = 3;
+const MONITOR_ACL_TX: u16 = 4;
+const MONITOR_ACL_RX: u16 = 5;
+
+const EVENT_DISCONNECTION_COMPLETE: u8 = 0x05;
+const EVENT_LE_META: u8 = 0x3E;
+const LE_CONNECTION_COMPLETE: u8 = 0x01;
+const LE_ENHANCED_CONNECTION_COMPLETE: u8 = 0x0A;
+
+const L2CAP_ATT: u16 = 0x0004;
+// ACL packet boundary flag of continuing fragments
+const CONTINUING: u16 = 0b01;
+
+const ATT_ERROR_RSP: u8 = 0x01;
+const ATT_EXCHANGE_MTU_REQ: u8 = 0x02;
+const ATT_EXCHANGE_MTU_RSP: u8 = 0x03;
+const ATT_READ_BY_TYPE_RSP: u8 = 0x09;
+const ATT_READ_REQ: u8 = 0x0A;
+const ATT_READ_RSP: u8 = 0x0B;
+const ATT_WRITE_REQ: u8 = 0x12;
+const ATT_WRITE_RSP: u8 = 0x13;
+const ATT_NOTIFICATION: u8 = 0x1B;
+const ATT_INDICATION: u8 = 0x1D;
+const ATT_WRITE_CMD: u8 = 0x52;
+
+// Handles of the ANCS characteristic values, as given on the command line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Handles {
+    pub notification_source: u16,
+    pub control_point: u16,
+    pub data_source: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Direction {
+    Sent,
+    Received,
+}
+
+enum Hci {
+    Acl(Vec<u8>),
+    Event(Vec<u8>),
+}
+
+struct HciRecord {
+    // microseconds since the Unix epoch
+    timestamp: i64,
+    adapter: Option<u16>,
+    direction: Direction,
+    packet: Hci,
+}
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn parse_records(capture: &[u8]) -> Result<Vec<HciRecord>, String> {
+    if capture.len() < 16 || &capture[..8] != MAGIC {
+        return Err("not a btsnoop capture".to_string());
+    }
+    let datalink = be_u32(capture, 12);
+    if ![HCI_UNENCAPSULATED, HCI_UART, LINUX_MONITOR].contains(&datalink) {
+        return Err(format!("unsupported btsnoop datalink {}", datalink));
+    }
+    let mut records = Vec::new();
+    let mut rest = &capture[16..];
+    while !rest.is_empty() {
+        if rest.len() < 24 {
+            warn!("Capture ends in a truncated record");
+            break;
+        }
+        let included = be_u32(rest, 4) as usize;
+        let flags = be_u32(rest, 8);
+        let timestamp = i64::from_be_bytes(rest[16..24].try_into().unwrap())
+            .checked_sub(EPOCH_OFFSET)
+            .ok_or("invalid capture timestamp")?;
+        let Some(data) = rest.get(24..24 + included) else {
+            warn!("Capture ends in a truncated record");
+            break;
+        };
+        rest = &rest[24 + included..];
+
+        let record = match datalink {
+            LINUX_MONITOR => {
+                let (adapter, opcode) = ((flags >> 16) as u16, flags as u16);
+                let (direction, packet) = match opcode {
+                    MONITOR_EVENT => (Direction::Received, Hci::Event(data.to_vec())),
+                    MONITOR_ACL_TX => (Direction::Sent, Hci::Acl(data.to_vec())),
+                    MONITOR_ACL_RX => (Direction::Received, Hci::Acl(data.to_vec())),
+                    _ => continue,
+                };
+                HciRecord {
+                    timestamp,
+                    adapter: Some(adapter),
+                    direction,
+                    packet,
+                }
+            }
+            _ => {
+                let direction = match flags & 1 {
+                    0 => Direction::Sent,
+                    _ => Direction::Received,
+                };
+                let packet = if datalink == HCI_UART {
+                    match data.split_first() {
+                        Some((&H4_ACL, acl)) => Hci::Acl(acl.to_vec()),
+                        Some((&H4_EVENT, event)) => Hci::Event(event.to_vec()),
+                        _ => continue,
+                    }
+                } else if flags & 2 == 0 {
+                    Hci::Acl(data.to_vec())
+                } else if direction == Direction::Received {
+                    Hci::Event(data.to_vec())
+                } else {
+                    continue;
+                };
+                HciRecord {
+                    timestamp,
+                    adapter: None,
+                    direction,
+                    packet,
+                }
+            }
+        };
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[derive(Default)]
+struct Connection {
+    // L2CAP frames being reassembled, per direction
+    sent: Vec<u8>,
+    received: Vec<u8>,
+    address: Option<String>,
+    client_mtu: Option<u16>,
+    server_mtu: Option<u16>,
+    ancs: bool,
+}
+
+#[derive(Default)]
+struct Discovered {
+    notification_source: Option<u16>,
+    control_point: Option<u16>,
+    data_source: Option<u16>,
+    current_time: Option<u16>,
+}
+
+struct Importer {
+    discovered: Discovered,
+    handles: Option<Handles>,
+    // the direction notifications come from the phone in, learned from discovery
+    from_phone: Direction,
+    connections: HashMap<u16, Connection>,
+    // index of the Control Point write waiting for its response
+    pending_write: Option<usize>,
+    pending_read: bool,
+    disconnected: bool,
+    start: i64,
+    link: LinkInfo,
+    records: Vec<Record>,
+}
+
+impl Importer {
+    fn record(&mut self, timestamp: i64, packet: Packet) {
+        self.records.push(Record {
+            t: timestamp.saturating_sub(self.start).max(0) as u64,
+            packet,
+        });
+    }
+
+    fn handles(&self) -> Option<Handles> {
+        if self.handles.is_some() {
+            return self.handles;
+        }
+        Some(Handles {
+            notification_source: self.discovered.notification_source?,
+            control_point: self.discovered.control_point?,
+            data_source: self.discovered.data_source?,
+        })
+    }
+
+    fn event(&mut self, timestamp: i64, event: &[u8]) {
+        let (Some(&code), Some(parameters)) = (event.first(), event.get(2..)) else {
+            return;
+        };
+        match code {
+            EVENT_DISCONNECTION_COMPLETE => {
+                let (Some(0), Some(handle)) = (parameters.first(), le_u16(parameters, 1)) else {
+                    return;
+                };
+                let connection = self.connections.remove(&(handle & 0x0FFF));
+                if connection.is_some_and(|connection| connection.ancs) {
+                    self.disconnected = true;
+                    self.pending_write = None;
+                    self.pending_read = false;
+                    self.record(timestamp, Packet::Disconnected);
+                }
+            }
+            EVENT_LE_META => {
+                let (Some(&subevent), Some(0)) = (parameters.first(), parameters.get(1)) else {
+                    return;
+                };
+                if subevent != LE_CONNECTION_COMPLETE && subevent != LE_ENHANCED_CONNECTION_COMPLETE
+                {
+                    return;
+                }
+                let (Some(handle), Some(address)) = (le_u16(parameters, 2), parameters.get(6..12))
+                else {
+                    return;
+                };
+                let address: Vec<String> =
+                    address.iter().rev().map(|b| format!("{:02X}", b)).collect();
+                let connection = Connection {
+                    address: Some(address.join(":")),
+                    ..Default::default()
+                };
+                self.connections.insert(handle & 0x0FFF, connection);
+                // the phone came back, its handles stay the same while bonded
+                if self.disconnected {
+                    self.disconnected = false;
+                    self.record(timestamp, Packet::Connected);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn acl(&mut self, record: &HciRecord, acl: &[u8]) {
+        let (Some(header), Some(data)) = (le_u16(acl, 0), acl.get(4..)) else {
+            return;
+        };
+        let handle = header & 0x0FFF;
+        let connection = self.connections.entry(handle).or_default();
+        let buffer = match record.direction {
+            Direction::Sent => &mut connection.sent,
+            Direction::Received => &mut connection.received,
+        };
+        if (header >> 12) & 0b11 != CONTINUING {
+            buffer.clear();
+        }
+        buffer.extend(data);
+        let Some(length) = le_u16(buffer, 0) else {
+            return;
+        };
+        if buffer.len() < 4 + length as usize {
+            return;
+        }
+        let frame = std::mem::take(buffer);
+        if le_u16(&frame, 2) == Some(L2CAP_ATT) {
+            self.att(record, handle, &frame[4..4 + length as usize]);
+        }
+    }
+
+    fn att(&mut self, record: &HciRecord, connection: u16, pdu: &[u8]) {
+        let Some((&opcode, parameters)) = pdu.split_first() else {
+            return;
+        };
+        match opcode {
+            ATT_READ_BY_TYPE_RSP => self.discovery(record, connection, parameters),
+            ATT_EXCHANGE_MTU_REQ | ATT_EXCHANGE_MTU_RSP => {
+                let Some(connection) = self.connections.get_mut(&connection) else {
+                    return;
+                };
+                let mtu = le_u16(parameters, 0);
+                if opcode == ATT_EXCHANGE_MTU_REQ {
+                    connection.client_mtu = mtu;
+                } else {
+                    connection.server_mtu = mtu;
+                }
+            }
+            ATT_READ_RSP => {
+                if record.direction == self.from_phone && std::mem::take(&mut self.pending_read) {
+                    self.ancs_traffic(record, connection);
+                    let data = parameters.to_vec();
+                    self.record(record.timestamp, Packet::Time { data });
+                }
+            }
+            _ => {
+                let Some(handles) = self.handles() else {
+                    return;
+                };
+                let Some(handle) = le_u16(parameters, 0) else {
+                    return;
+                };
+                let from_phone = record.direction == self.from_phone;
+                let value = parameters[2..].to_vec();
+                let packet = match opcode {
+                    ATT_NOTIFICATION | ATT_INDICATION if from_phone => {
+                        if handle == handles.notification_source {
+                            Packet::Ns { data: value }
+                        } else if handle == handles.data_source {
+                            Packet::Ds { data: value }
+                        } else {
+                            return;
+                        }
+                    }
+                    ATT_WRITE_REQ | ATT_WRITE_CMD
+                        if !from_phone && handle == handles.control_point =>
+                    {
+                        if opcode == ATT_WRITE_REQ {
+                            self.pending_write = Some(self.records.len());
+                        }
+                        Packet::cp(value, &Ok(()))
+                    }
+                    ATT_WRITE_RSP if from_phone => {
+                        self.pending_write = None;
+                        return;
+                    }
+                    ATT_ERROR_RSP if from_phone => {
+                        // request opcode, handle and error code
+                        let (Some(&ATT_WRITE_REQ), Some(&code)) =
+                            (parameters.first(), parameters.get(3))
+                        else {
+                            return;
+                        };
+                        if le_u16(parameters, 1) != Some(handles.control_point) {
+                            return;
+                        }
+                        if let Some(index) = self.pending_write.take() {
+                            if let Packet::Cp { att_error, .. } = &mut self.records[index].packet {
+                                *att_error = Some(code);
+                            }
+                        }
+                        return;
+                    }
+                    ATT_READ_REQ if !from_phone => {
+                        self.pending_read = Some(handle) == self.discovered.current_time;
+                        return;
+                    }
+                    _ => return,
+                };
+                self.ancs_traffic(record, connection);
+                self.record(record.timestamp, packet);
+            }
+        }
+    }
+
+    // Characteristic declarations found by the client's discovery, the values follow them
+    fn discovery(&mut self, record: &HciRecord, connection: u16, parameters: &[u8]) {
+        let Some((&length, entries)) = parameters.split_first() else {
+            return;
+        };
+        // handle, properties, value handle and a 16 or 128 bit UUID
+        if length != 7 && length != 21 {
+            return;
+        }
+        for entry in entries.chunks_exact(length as usize) {
+            let Some(value_handle) = le_u16(entry, 3) else {
+                continue;
+            };
+            let uuid = match length {
+                7 => Uuid::from_u128(
+                    (le_u16(entry, 5).unwrap() as u128) << 96 | 0x00001000800000805F9B34FB,
+                ),
+                _ => {
+                    let mut bytes: [u8; 16] = entry[5..21].try_into().unwrap();
+                    bytes.reverse();
+                    Uuid::from_bytes(bytes)
+                }
+            };
+            let discovered = &mut self.discovered;
+            let slot = if uuid == NOTIFICATION_SOURCE_UUID {
+                &mut discovered.notification_source
+            } else if uuid == CONTROL_POINT_UUID {
+                &mut discovered.control_point
+            } else if uuid == DATA_SOURCE_UUID {
+                &mut discovered.data_source
+            } else if uuid == CURRENT_TIME_UUID {
+                &mut discovered.current_time
+            } else {
+                continue;
+            };
+            debug!("Found {} at handle 0x{:04X}", uuid, value_handle);
+            *slot = Some(value_handle);
+            self.from_phone = record.direction;
+            self.ancs_traffic(record, connection);
+        }
+    }
+
+    fn ancs_traffic(&mut self, record: &HciRecord, connection: u16) {
+        let Some(connection) = self.connections.get_mut(&connection) else {
+            return;
+        };
+        connection.ancs = true;
+        if self.link.device.is_none() {
+            self.link.adapter = record.adapter.map(|index| format!("hci{}", index));
+            self.link.device = connection.address.clone();
+        }
+        if let (Some(client), Some(server)) = (connection.client_mtu, connection.server_mtu) {
+            self.link.mtu = Some(client.min(server) as usize);
+        }
+    }
+}
+
+// Turns the ANCS traffic of a capture into a trace, with the handles found in the captured
+// discovery unless they are given
+pub fn import(capture: &[u8], handles: Option<Handles>) -> Result<Trace, String> {
+    let hci_records = parse_records(capture)?;
+    let start = hci_records.first().map_or(0, |record| record.timestamp);
+    let mut importer = Importer {
+        discovered: Discovered::default(),
+        handles,
+        from_phone: Direction::Received,
+        connections: HashMap::new(),
+        pending_write: None,
+        pending_read: false,
+        disconnected: false,
+        start,
+        link: LinkInfo::default(),
+        records: Vec::new(),
+    };
+    for record in &hci_records {
+        match &record.packet {
+            Hci::Event(event) => importer.event(record.timestamp, event),
+            Hci::Acl(acl) => importer.acl(record, acl),
+        }
+    }
+    if importer.handles().is_none() {
+        return Err(
+            "no ANCS discovery in the capture, give the characteristic handles".to_string(),
+        );
+    }
+    let started = Local
+        .timestamp_micros(start)
+        .single()
+        .ok_or("invalid capture timestamp")?;
+    Ok(Trace {
+        header: Header {
+            version: trace::VERSION,
+            started,
+            link: importer.link,
+        },
+        records: importer.records,
+    })
+}
+
+pub fn read(path: &Path, handles: Option<Handles>) -> Result<Trace, String> {
+    let capture = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    import(&capture, handles).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::transport::TransportError;
+
+    const CONNECTION: u16 = 0x0040;
+
+    fn capture(datalink: u32, records: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut capture = MAGIC.to_vec();
+        capture.extend(1u32.to_be_bytes());
+        capture.extend(datalink.to_be_bytes());
+        for (index, (flags, data)) in records.iter().enumerate() {
+            capture.extend((data.len() as u32).to_be_bytes());
+            capture.extend((data.len() as u32).to_be_bytes());
+            capture.extend(flags.to_be_bytes());
+            capture.extend(0u32.to_be_bytes());
+            let timestamp = EPOCH_OFFSET + 1_700_000_000_000_000 + index as i64 * 1000;
+            capture.extend(timestamp.to_be_bytes());
+            capture.extend(data);
+        }
+        capture
+    }
+
+    // ACL packets carrying one ATT PDU, split after `split` bytes of the L2CAP frame
+    fn att(pdu: &[u8], split: usize) -> Vec<Vec<u8>> {
+        let mut frame = (pdu.len() as u16).to_le_bytes().to_vec();
+        frame.extend(L2CAP_ATT.to_le_bytes());
+        frame.extend(pdu);
+        let split = split.min(frame.len());
+        let (first, rest) = frame.split_at(split);
+        let mut packets = vec![(0b10, first)];
+        if !rest.is_empty() {
+            packets.push((CONTINUING, rest));
+        }
+        packets
+            .into_iter()
+            .map(|(boundary, data)| {
+                let mut acl = (CONNECTION | boundary << 12).to_le_bytes().to_vec();
+                acl.extend((data.len() as u16).to_le_bytes());
+                acl.extend(data);
+                acl
+            })
+            .collect()
+    }
+
+    fn declaration(value_handle: u16, uuid: Uuid) -> Vec<u8> {
+        let mut entry = (value_handle - 1).to_le_bytes().to_vec();
+        entry.push(0x10);
+        entry.extend(value_handle.to_le_bytes());
+        entry.extend(uuid.as_bytes().iter().rev());
+        entry
+    }
+
+    #[test]
+    fn monitor_capture() {
+        let rx = MONITOR_ACL_RX as u32;
+        let tx = MONITOR_ACL_TX as u32;
+        let mut records = Vec::new();
+        let mut connected = vec![EVENT_LE_META, 19, LE_CONNECTION_COMPLETE, 0, 0x40, 0, 0, 0];
+        connected.extend([0x66, 0x55, 0x44, 0x33, 0x22, 0x11]);
+        connected.extend([0; 7]);
+        records.push((MONITOR_EVENT as u32, connected));
+        records.extend(
+            att(&[ATT_EXCHANGE_MTU_REQ, 247, 0], 64)
+                .into_iter()
+                .map(|p| (tx, p)),
+        );
+        records.extend(
+            att(&[ATT_EXCHANGE_MTU_RSP, 185, 0], 64)
+                .into_iter()
+                .map(|p| (rx, p)),
+        );
+        let mut discovery = vec![ATT_READ_BY_TYPE_RSP, 21];
+        discovery.extend(declaration(0x0C, NOTIFICATION_SOURCE_UUID));
+        discovery.extend(declaration(0x0F, CONTROL_POINT_UUID));
+        discovery.extend(declaration(0x12, DATA_SOURCE_UUID));
+        records.extend(att(&discovery, 27).into_iter().map(|p| (rx, p)));
+        let event = [0, 0, 4, 1, 7, 0, 0, 0];
+        let notification = [&[ATT_NOTIFICATION, 0x0C, 0][..], &event].concat();
+        records.extend(att(&notification, 6).into_iter().map(|p| (rx, p)));
+        let command = [0, 7, 0, 0, 0, 1, 0xFF, 0xFF];
+        let write = [&[ATT_WRITE_REQ, 0x0F, 0][..], &command].concat();
+        records.extend(att(&write, 64).into_iter().map(|p| (tx, p)));
+        let error = [ATT_ERROR_RSP, ATT_WRITE_REQ, 0x0F, 0, 0xA2];
+        records.extend(att(&error, 64).into_iter().map(|p| (rx, p)));
+        records.extend(att(&write, 64).into_iter().map(|p| (tx, p)));
+        records.extend(att(&[ATT_WRITE_RSP], 64).into_iter().map(|p| (rx, p)));
+        let response = [0, 7, 0, 0, 0, 1, 0, 0];
+        let notification = [&[ATT_NOTIFICATION, 0x12, 0][..], &response].concat();
+        records.extend(att(&notification, 64).into_iter().map(|p| (rx, p)));
+        // the phone's notifications to our own GATT server are not ANCS
+        let write = [&[ATT_WRITE_REQ, 0x0C, 0][..], &event].concat();
+        records.extend(att(&write, 64).into_iter().map(|p| (rx, p)));
+        records.push((
+            MONITOR_EVENT as u32,
+            vec![EVENT_DISCONNECTION_COMPLETE, 4, 0, 0x40, 0, 0x13],
+        ));
+
+        let trace = import(&capture(LINUX_MONITOR, &records), None).unwrap();
+        assert_eq!(trace.header.link.adapter.as_deref(), Some("hci0"));
+        assert_eq!(
+            trace.header.link.device.as_deref(),
+            Some("11:22:33:44:55:66")
+        );
+        assert_eq!(trace.header.link.mtu, Some(185));
+        let packets: Vec<Packet> = trace.records.into_iter().map(|r| r.packet).collect();
+        assert_eq!(
+            packets,
+            vec![
+                Packet::Ns {
+                    data: event.to_vec()
+                },
+                Packet::cp(command.to_vec(), &Err(TransportError::Att(0xA2))),
+                Packet::cp(command.to_vec(), &Ok(())),
+                Packet::Ds {
+                    data: response.to_vec()
+                },
+                Packet::Disconnected,
+            ]
+        );
+    }
+
+    #[test]
+    fn uart_capture_with_handles() {
+        let event = [1, 0, 4, 1, 7, 0, 0, 0];
+        let notification = [&[ATT_NOTIFICATION, 0x2A, 0][..], &event].concat();
+        let records: Vec<(u32, Vec<u8>)> = att(&notification, 64)
+            .into_iter()
+            .map(|p| (1, [vec![H4_ACL], p].concat()))
+            .collect();
+        let capture = capture(HCI_UART, &records);
+        assert!(import(&capture, None).is_err());
+
+        let handles = Handles {
+            notification_source: 0x2A,
+            control_point: 0x2D,
+            data_source: 0x30,
+        };
+        let trace = import(&capture, Some(handles)).unwrap();
+        assert_eq!(
+            trace.records[0].packet,
+            Packet::Ns {
+                data: event.to_vec()
+            }
+        );
+        assert!(import(b"not a capture", None).is_err());
+
+        // corrupt timestamps, before the btsnoop epoch or too far apart to subtract
+        let mut corrupt = capture.clone();
+        corrupt[32..40].copy_from_slice(&i64::MIN.to_be_bytes());
+        assert_eq!(
+            import(&corrupt, Some(handles)).err().as_deref(),
+            Some("invalid capture timestamp")
+        );
+        let fragments: Vec<(u32, Vec<u8>)> = att(&notification, 4)
+            .into_iter()
+            .map(|p| (1, [vec![H4_ACL], p].concat()))
+            .collect();
+        let mut corrupt = self::capture(HCI_UART, &fragments);
+        corrupt[32..40].copy_from_slice(&(i64::MIN + EPOCH_OFFSET).to_be_bytes());
+        assert_eq!(
+            import(&corrupt, Some(handles)).err().as_deref(),
+            Some("invalid capture timestamp")
+        );
+    }
+}
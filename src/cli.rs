@@ -0,0 +1,450 @@
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use clap::{Args, Parser, Subcommand};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::ancs::control_point::{category_name, CategoryID};
+use crate::ancs::date;
+use crate::ancs::trace::Trace;
+use crate::anonymize;
+use crate::btsnoop::{self, Handles};
+use crate::conformance;
+use crate::control;
+use crate::export::{self, Format};
+use crate::history::{Entry, History, Query};
+use crate::timeline;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "ancs",
+    version,
+    about = "Apple Notification Center Service for the desktop"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the notification daemon (default)
+    Run(RunArgs),
+    /// Query the notification history
+    #[command(subcommand)]
+    History(HistoryCommand),
+    /// Export the notification history
+    Export(ExportArgs),
+    /// Fetch the full message of a notification from the running daemon
+    Message {
+        /// Notification id as printed by `ancs history show`
+        notification_id: u32,
+    },
+    /// Inspect and convert ANCS traffic traces
+    #[command(subcommand)]
+    Trace(TraceCommand),
+}
+
+#[derive(Debug, Default, Args)]
+pub struct RunArgs {
+    /// Talk to `ancs-sim` on this socket instead of the phone
+    #[arg(long, value_name = "SOCKET")]
+    pub simulator: Option<PathBuf>,
+    /// Record the raw ANCS traffic to this trace file
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+    /// Log where the phone strays from the ANCS specification
+    #[arg(long)]
+    pub check: bool,
+    /// Replay a trace recorded with `--record` instead of talking to the phone
+    #[arg(long, value_name = "FILE", conflicts_with = "simulator")]
+    pub replay: Option<PathBuf>,
+    /// Replay speed, 2 replays twice as fast [default: 1]
+    #[arg(long, requires = "replay")]
+    pub speed: Option<f64>,
+    /// Replay without the recorded pauses
+    #[arg(long, requires = "replay", conflicts_with = "speed")]
+    pub fast: bool,
+    /// Print every ANCS event to stdout as a JSON object per line
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum HistoryCommand {
+    /// List the most recent notifications
+    List {
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Search titles, subtitles and messages
+    Search {
+        /// Text to look for, case-insensitive
+        text: String,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Show a single entry in full
+    Show {
+        id: u64,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TraceCommand {
+    /// Print a decoded timeline of a trace recorded with `ancs run --record`
+    Show { trace: PathBuf },
+    /// Read the ANCS traffic from a btsnoop capture, e.g. from `btmon -w`
+    Import {
+        capture: PathBuf,
+        /// Write a trace for `ancs run --replay` instead of printing the timeline
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Notification Source value handle, e.g. 0x000c, for captures without the discovery
+        #[arg(long, value_parser = parse_handle, requires_all = ["control_point", "data_source"])]
+        notification_source: Option<u16>,
+        /// Control Point value handle
+        #[arg(long, value_parser = parse_handle, requires = "notification_source")]
+        control_point: Option<u16>,
+        /// Data Source value handle
+        #[arg(long, value_parser = parse_handle, requires = "notification_source")]
+        data_source: Option<u16>,
+    },
+    /// Report where the phone strays from the ANCS specification
+    Check { trace: PathBuf },
+    /// Replace titles, subtitles, messages and app names in a trace before sharing it, keeping
+    /// every length so it reproduces the same parsing
+    Anonymize {
+        trace: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct FilterArgs {
+    /// Only notifications from apps whose identifier contains this
+    #[arg(long)]
+    app: Option<String>,
+    /// Only notifications of this category, e.g. Social
+    #[arg(long, value_parser = CategoryID::from_str)]
+    category: Option<CategoryID>,
+    /// Only notifications received after this time, e.g. 2h, 3d or 2024-01-31 12:00
+    #[arg(long, value_parser = parse_time)]
+    since: Option<DateTime<Local>>,
+    /// Only notifications received before this time
+    #[arg(long, value_parser = parse_time)]
+    until: Option<DateTime<Local>>,
+    /// Maximum number of entries, newest first
+    #[arg(short = 'n', long, default_value_t = 20)]
+    limit: usize,
+    /// Print JSON instead of text
+    #[arg(long)]
+    json: bool,
+}
+
+impl FilterArgs {
+    fn query(&self, text: Option<String>) -> Query {
+        Query {
+            text,
+            app: self.app.clone(),
+            category_id: self.category.map(|category| category as u8),
+            since: self.since,
+            until: self.until,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[arg(short, long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+    /// Only notifications received after this time, e.g. 7d or 2024-01-01
+    #[arg(long, value_parser = parse_time)]
+    since: Option<DateTime<Local>>,
+    /// Only notifications received before this time
+    #[arg(long, value_parser = parse_time)]
+    until: Option<DateTime<Local>>,
+    /// Only notifications from apps whose identifier contains this
+    #[arg(long)]
+    app: Option<String>,
+    /// Replace message bodies with a placeholder
+    #[arg(long)]
+    redact: bool,
+    /// Write to a file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+// ATT handles as printed by btmon (0x002a) or in decimal
+fn parse_handle(s: &str) -> Result<u16, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("invalid handle {}: {}", s, e))
+}
+
+// Accepts a duration before now (30m, 2h, 3d) or a local date with optional time
+pub fn parse_time(s: &str) -> Result<DateTime<Local>, String> {
+    let s = s.trim();
+    if let Some(unit) = s.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        if let Ok(amount) = s[..s.len() - 1].parse::<i64>() {
+            let duration = match unit {
+                's' => Duration::seconds(amount),
+                'm' => Duration::minutes(amount),
+                'h' => Duration::hours(amount),
+                'd' => Duration::days(amount),
+                'w' => Duration::weeks(amount),
+                _ => return Err(format!("unknown time unit: {}", unit)),
+            };
+            return Ok(Local::now() - duration);
+        }
+    }
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(|| format!("invalid time: {}", s))?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| format!("time does not exist locally: {}", s))
+}
+
+fn print_list(entries: &[&Entry], json: bool) -> Result<(), String> {
+    if json {
+        let json = serde_json::to_string_pretty(entries).map_err(|e| e.to_string())?;
+        println!("{}", json);
+        return Ok(());
+    }
+    let now = Local::now();
+    for entry in entries {
+        let mut text = entry.title.clone().unwrap_or_default();
+        if let Some(message) = &entry.message {
+            text += &format!(": {}", message.replace('\n', " "));
+        }
+        let received_at = entry.received_at();
+        println!(
+            "{:>6}  {} {:>12}  {:<32}  {:<18}  {}",
+            entry.id,
+            received_at.format("%Y-%m-%d %H:%M"),
+            date::relative(received_at, now),
+            entry.app_identifier.as_deref().unwrap_or("-"),
+            category_name(entry.category_id),
+            text
+        );
+    }
+    Ok(())
+}
+
+fn print_entry(entry: &Entry, json: bool) -> Result<(), String> {
+    if json {
+        let json = serde_json::to_string_pretty(entry).map_err(|e| e.to_string())?;
+        println!("{}", json);
+        return Ok(());
+    }
+    let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    println!("id:              {}", entry.id);
+    println!("notification id: {}", entry.notification_id);
+    println!("app:             {}", optional(&entry.app_identifier));
+    println!("category:        {}", category_name(entry.category_id));
+    println!("flags:           {:#04x}", entry.event_flags);
+    println!(
+        "date:            {}",
+        entry.date.map_or_else(
+            || "-".to_string(),
+            |date| format!(
+                "{} ({})",
+                date.to_rfc3339(),
+                date::relative(date, Local::now())
+            )
+        )
+    );
+    println!("arrived:         {}", entry.arrived_at.to_rfc3339());
+    println!(
+        "removed:         {}",
+        entry
+            .removed_at
+            .map_or_else(|| "-".to_string(), |removed_at| removed_at.to_rfc3339())
+    );
+    for action in &entry.actions {
+        println!(
+            "action:          {} at {}",
+            action.action,
+            action.at.to_rfc3339()
+        );
+    }
+    println!("title:           {}", optional(&entry.title));
+    println!("subtitle:        {}", optional(&entry.subtitle));
+    println!();
+    println!("{}", optional(&entry.message));
+    if entry.message_truncated() {
+        println!(
+            "(message truncated, {} of {} bytes)",
+            entry.message.as_deref().map_or(0, str::len),
+            entry.message_size.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+pub fn history(xdg_dirs: &xdg::BaseDirectories, command: HistoryCommand) -> Result<(), String> {
+    let path = History::default_path(xdg_dirs).map_err(|e| e.to_string())?;
+    let entries = History::load(&path).map_err(|e| e.to_string())?;
+
+    let (filter, query) = match &command {
+        HistoryCommand::List { filter } => (filter, filter.query(None)),
+        HistoryCommand::Search { text, filter } => (filter, filter.query(Some(text.clone()))),
+        HistoryCommand::Show { id, json } => {
+            let entry = entries
+                .iter()
+                .find(|entry| entry.id == *id)
+                .ok_or_else(|| format!("no history entry {}", id))?;
+            return print_entry(entry, *json);
+        }
+    };
+    let mut matches: Vec<&Entry> = entries
+        .iter()
+        .rev()
+        .filter(|entry| query.matches(entry))
+        .take(filter.limit)
+        .collect();
+    // oldest first so the newest ends up next to the prompt
+    if !filter.json {
+        matches.reverse();
+    }
+    print_list(&matches, filter.json)
+}
+
+pub fn export(xdg_dirs: &xdg::BaseDirectories, args: ExportArgs) -> Result<(), String> {
+    let path = History::default_path(xdg_dirs).map_err(|e| e.to_string())?;
+    let query = Query {
+        app: args.app,
+        since: args.since,
+        until: args.until,
+        ..Default::default()
+    };
+    let mut entries: Vec<Entry> = History::load(&path)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|entry| query.matches(entry))
+        .collect();
+    if args.redact {
+        export::redact(&mut entries);
+    }
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(output) => Box::new(BufWriter::new(
+            File::create(output).map_err(|e| format!("{}: {}", output.display(), e))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    export::write(&mut out, &entries, args.format)
+        .and_then(|_| out.flush())
+        .map_err(|e| e.to_string())
+}
+
+pub fn trace(command: TraceCommand) -> Result<(), String> {
+    let (trace, output) = match command {
+        TraceCommand::Show { trace } => (Trace::read(&trace)?, None),
+        TraceCommand::Check { trace } => {
+            let trace = Trace::read(&trace)?;
+            let mut out = io::stdout().lock();
+            return match conformance::report(&mut out, &trace).map_err(|e| e.to_string())? {
+                0 => {
+                    println!("No conformance problems");
+                    Ok(())
+                }
+                count => Err(format!("{} conformance problems", count)),
+            };
+        }
+        TraceCommand::Import {
+            capture,
+            output,
+            notification_source,
+            control_point,
+            data_source,
+        } => {
+            let handles = match (notification_source, control_point, data_source) {
+                (Some(notification_source), Some(control_point), Some(data_source)) => {
+                    Some(Handles {
+                        notification_source,
+                        control_point,
+                        data_source,
+                    })
+                }
+                _ => None,
+            };
+            (btsnoop::read(&capture, handles)?, output)
+        }
+        TraceCommand::Anonymize { trace, output } => {
+            let mut trace = Trace::read(&trace)?;
+            anonymize::trace(&mut trace);
+            (trace, Some(output))
+        }
+    };
+    match output {
+        Some(output) => File::create(&output)
+            .map(BufWriter::new)
+            .and_then(|mut out| {
+                trace.write_to(&mut out)?;
+                out.flush()
+            })
+            .map_err(|e| format!("{}: {}", output.display(), e)),
+        None => {
+            let mut out = io::stdout().lock();
+            timeline::write(&mut out, &trace).map_err(|e| e.to_string())
+        }
+    }
+}
+
+pub async fn message(xdg_dirs: &xdg::BaseDirectories, notification_id: u32) -> Result<(), String> {
+    let socket_path = xdg_dirs
+        .find_runtime_file("control.sock")
+        .ok_or("the daemon is not running")?;
+    let params = serde_json::json!({ "notification_id": notification_id });
+    let result = tokio::time::timeout(
+        std::time::Duration::from_secs(15),
+        control::call(&socket_path, "message", params),
+    )
+    .await
+    .map_err(|_| "no response from the phone".to_string())?
+    .map_err(|e| format!("{}: {}", socket_path.display(), e))?
+    .map_err(|e| e.to_string())?;
+    println!("{}", result.as_str().unwrap_or_default());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_time() {
+        let before = Local::now() - Duration::hours(2);
+        let parsed = parse_time("2h").unwrap();
+        assert!(parsed >= before && parsed <= Local::now() - Duration::hours(2));
+        assert!(parse_time("2y").is_err());
+    }
+
+    #[test]
+    fn absolute_time() {
+        let expected = Local.with_ymd_and_hms(2024, 1, 31, 12, 30, 0).unwrap();
+        assert_eq!(parse_time("2024-01-31 12:30").unwrap(), expected);
+        assert_eq!(
+            parse_time("2024-01-31").unwrap(),
+            Local.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap()
+        );
+        assert!(parse_time("yesterday").is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use serde::Deserialize;
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+// `[coalesce]` section of ancs.toml, coalescing is off unless a window is configured
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct CoalesceConfig {
+    #[serde(default)]
+    pub window_secs: u64,
+    // group by title as well, e.g. to keep separate group chats apart
+    #[serde(default)]
+    pub by_title: bool,
+}
+
+// How a displayable notification should be shown
+#[derive(Clone, Debug, PartialEq)]
+pub enum Coalesced {
+    // first of a burst, shown on its own
+    Single,
+    // part of a burst, shown as the group's summary notification
+    Group {
+        // the burst's first notification, it names the group until its last one is removed
+        group: u32,
+        // notification whose bubble the summary replaces when the group is formed
+        replaces: Option<u32>,
+        summary: String,
+        body: String,
+    },
+}
+
+struct Group {
+    first: u32,
+    // a notification pushed again, e.g. after it was modified, is not counted twice
+    notification_ids: HashSet<u32>,
+    last: Instant,
+    // the summary is up, it replaced the first notification's bubble
+    formed: bool,
+}
+
+impl Group {
+    fn coalesced(
+        &self,
+        replaces: Option<u32>,
+        app_name: &str,
+        title: &str,
+        message: &str,
+    ) -> Coalesced {
+        if !self.formed {
+            return Coalesced::Single;
+        }
+        Coalesced::Group {
+            group: self.first,
+            replaces,
+            summary: format!(
+                "{} new messages in {}",
+                self.notification_ids.len(),
+                app_name
+            ),
+            body: format!("latest: {}: {}", title, message),
+        }
+    }
+}
+
+pub struct Coalescer {
+    window: Duration,
+    by_title: bool,
+    // bursts still within the window, by app or by app and title
+    groups: HashMap<String, Group>,
+    // bursts that ended while their summary is still up
+    ended: Vec<Group>,
+}
+
+impl Coalescer {
+    pub fn from_config(config: &CoalesceConfig) -> Self {
+        Self {
+            window: Duration::from_secs(config.window_secs),
+            by_title: config.by_title,
+            groups: HashMap::new(),
+            ended: Vec::new(),
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        notification_id: u32,
+        app_identifier: &str,
+        app_name: &str,
+        title: &str,
+        message: &str,
+        now: Instant,
+    ) -> Coalesced {
+        if self.window.is_zero() {
+            return Coalesced::Single;
+        }
+        let window = self.window;
+        let expired = self
+            .groups
+            .extract_if(|_, group| now.duration_since(group.last) > window);
+        self.ended
+            .extend(expired.map(|(_, group)| group).filter(|group| group.formed));
+
+        // a notification pushed again, e.g. after it was modified, stays where it was counted
+        let pushed_before = self
+            .groups
+            .values()
+            .chain(self.ended.iter())
+            .find(|group| group.notification_ids.contains(&notification_id));
+        if let Some(group) = pushed_before {
+            return group.coalesced(None, app_name, title, message);
+        }
+
+        let key = if self.by_title {
+            format!("{}\0{}", app_identifier, title)
+        } else {
+            app_identifier.to_string()
+        };
+        let group = self.groups.entry(key).or_insert(Group {
+            first: notification_id,
+            notification_ids: HashSet::new(),
+            last: now,
+            formed: false,
+        });
+        group.notification_ids.insert(notification_id);
+        group.last = now;
+        if group.notification_ids.len() == 1 {
+            return Coalesced::Single;
+        }
+        let replaces = (!group.formed).then_some(group.first);
+        group.formed = true;
+        group.coalesced(replaces, app_name, title, message)
+    }
+
+    // The phone removed a notification, e.g. a chat read there. Returns the group once none of
+    // its notifications are left, its summary can go as well.
+    pub fn remove(&mut self, notification_id: u32) -> Option<u32> {
+        let contains = |group: &Group| group.notification_ids.contains(&notification_id);
+        if let Some(key) = self
+            .groups
+            .iter()
+            .find(|(_, group)| contains(group))
+            .map(|(key, _)| key.clone())
+        {
+            let group = self.groups.get_mut(&key)?;
+            group.notification_ids.remove(&notification_id);
+            if !group.notification_ids.is_empty() {
+                return None;
+            }
+            return self.groups.remove(&key).map(|group| group.first);
+        }
+        let index = self.ended.iter().position(contains)?;
+        let group = &mut self.ended[index];
+        group.notification_ids.remove(&notification_id);
+        if !group.notification_ids.is_empty() {
+            return None;
+        }
+        Some(self.ended.swap_remove(index).first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coalescer(by_title: bool) -> Coalescer {
+        Coalescer::from_config(&CoalesceConfig {
+            window_secs: 5,
+            by_title,
+        })
+    }
+
+    #[test]
+    fn disabled() {
+        let mut coalescer = Coalescer::from_config(&CoalesceConfig::default());
+        let now = Instant::now();
+        for id in 0..3 {
+            let coalesced = coalescer.push(id, "org.signal", "Signal", "Bob", "hi", now);
+            assert_eq!(coalesced, Coalesced::Single);
+        }
+    }
+
+    #[test]
+    fn burst() {
+        let mut coalescer = coalescer(false);
+        let now = Instant::now();
+        assert_eq!(
+            coalescer.push(1, "org.signal", "Signal", "Alice", "hi", now),
+            Coalesced::Single
+        );
+        assert_eq!(
+            coalescer.push(2, "org.signal", "Signal", "Bob", "hey", now),
+            Coalesced::Group {
+                group: 1,
+                replaces: Some(1),
+                summary: "2 new messages in Signal".to_string(),
+                body: "latest: Bob: hey".to_string(),
+            }
+        );
+        let later = now + Duration::from_secs(4);
+        match coalescer.push(3, "org.signal", "Signal", "Carol", "yo", later) {
+            Coalesced::Group {
+                replaces, summary, ..
+            } => {
+                assert_eq!(replaces, None);
+                assert_eq!(summary, "3 new messages in Signal");
+            }
+            Coalesced::Single => panic!("expected a group"),
+        }
+        // a quiet period ends the burst
+        let much_later = later + Duration::from_secs(6);
+        assert_eq!(
+            coalescer.push(4, "org.signal", "Signal", "Dave", "hello", much_later),
+            Coalesced::Single
+        );
+    }
+
+    // the summary stays up after the burst until the phone removed all of its notifications
+    #[test]
+    fn removed() {
+        let mut coalescer = coalescer(false);
+        let now = Instant::now();
+        for id in 1..=3 {
+            coalescer.push(id, "org.signal", "Signal", "Alice", "hi", now);
+        }
+        assert_eq!(coalescer.remove(1), None);
+        assert_eq!(coalescer.remove(2), None);
+        // still in the window, counted without the removed ones
+        match coalescer.push(4, "org.signal", "Signal", "Bob", "hey", now) {
+            Coalesced::Group {
+                group,
+                replaces,
+                summary,
+                ..
+            } => {
+                assert_eq!((group, replaces), (1, None));
+                assert_eq!(summary, "2 new messages in Signal");
+            }
+            Coalesced::Single => panic!("expected a group"),
+        }
+
+        let later = now + Duration::from_secs(6);
+        assert_eq!(
+            coalescer.push(5, "org.signal", "Signal", "Carol", "yo", later),
+            Coalesced::Single
+        );
+        assert_eq!(coalescer.remove(3), None);
+        assert_eq!(coalescer.remove(4), Some(1));
+        assert_eq!(coalescer.remove(5), Some(5));
+        assert_eq!(coalescer.remove(5), None);
+    }
+
+    // modified notifications come through again, they are neither counted nor grouped twice
+    #[test]
+    fn same_notification() {
+        let mut coalescer = coalescer(false);
+        let now = Instant::now();
+        coalescer.push(1, "org.signal", "Signal", "Alice", "hi", now);
+        assert_eq!(
+            coalescer.push(1, "org.signal", "Signal", "Alice", "hi!", now),
+            Coalesced::Single
+        );
+        coalescer.push(2, "org.signal", "Signal", "Bob", "hey", now);
+        assert_eq!(
+            coalescer.push(2, "org.signal", "Signal", "Bob", "hey!", now),
+            Coalesced::Group {
+                group: 1,
+                replaces: None,
+                summary: "2 new messages in Signal".to_string(),
+                body: "latest: Bob: hey!".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn by_title() {
+        let mut coalescer = coalescer(true);
+        let now = Instant::now();
+        coalescer.push(1, "org.signal", "Signal", "Family", "hi", now);
+        assert_eq!(
+            coalescer.push(2, "org.signal", "Signal", "Work", "hi", now),
+            Coalesced::Single
+        );
+        assert_ne!(
+            coalescer.push(3, "org.signal", "Signal", "Family", "hi", now),
+            Coalesced::Single
+        );
+    }
+}
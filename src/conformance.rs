@@ -0,0 +1,544 @@
+use log::warn;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::str;
+use std::sync::Mutex;
+
+use crate::ancs::control_point::{CategoryID, EventFlag, EventID, NotificationAttributeID};
+use crate::ancs::data_source::{Expected, Progress};
+use crate::ancs::date;
+use crate::ancs::notification_source::NotificationEvent;
+use crate::ancs::trace::{hex, Packet, Tap, Trace};
+
+// Where the phone strays from the ANCS specification
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    MalformedEvent(Vec<u8>),
+    UnknownEvent(u8),
+    UnknownCategory(u8),
+    ReservedFlags(u8),
+    // PreExisting is only meant for notifications added when the client subscribes
+    PreExistingNotAdded {
+        event_id: u8,
+        notification_id: u32,
+    },
+    // modified or removed before it was added
+    UnannouncedEvent {
+        event_id: u8,
+        notification_id: u32,
+    },
+    ResponseWithoutCommand(Vec<u8>),
+    OutOfOrderResponse {
+        expected: String,
+        response: Vec<u8>,
+    },
+    // the next command or a disconnection came first
+    IncompleteResponse(Vec<u8>),
+    UnannouncedResponse(u32),
+    UnrequestedAttribute(u8),
+    AttributeTooLong {
+        attribute_id: u8,
+        max_length: u16,
+        length: u16,
+    },
+    MalformedAppIdentifier {
+        app_identifier: Vec<u8>,
+        reason: &'static str,
+    },
+    MalformedDate(Vec<u8>),
+    TrailingBytes(Vec<u8>),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::MalformedEvent(data) => {
+                write!(
+                    f,
+                    "notification source event is not 8 bytes: {}",
+                    hex::encode(data)
+                )
+            }
+            Problem::UnknownEvent(id) => write!(f, "unknown event id {}", id),
+            Problem::UnknownCategory(id) => write!(f, "unknown category id {}", id),
+            Problem::ReservedFlags(flags) => {
+                write!(f, "reserved event flags set: 0x{:02X}", flags)
+            }
+            Problem::PreExistingNotAdded {
+                event_id,
+                notification_id,
+            } => write!(
+                f,
+                "PreExisting flag on event {} for notification {}",
+                event_id, notification_id
+            ),
+            Problem::UnannouncedEvent {
+                event_id,
+                notification_id,
+            } => write!(
+                f,
+                "event {} for notification {} that was never added",
+                event_id, notification_id
+            ),
+            Problem::ResponseWithoutCommand(data) => {
+                write!(f, "response without a command: {}", hex::encode(data))
+            }
+            Problem::OutOfOrderResponse { expected, response } => write!(
+                f,
+                "response for {} while waiting for {}",
+                describe(response),
+                expected
+            ),
+            Problem::IncompleteResponse(data) => {
+                write!(f, "incomplete response: {}", hex::encode(data))
+            }
+            Problem::UnannouncedResponse(notification_id) => write!(
+                f,
+                "attributes of notification {} that was never announced",
+                notification_id
+            ),
+            Problem::UnrequestedAttribute(id) => {
+                write!(f, "attribute {} was not requested", attribute_name(*id))
+            }
+            Problem::AttributeTooLong {
+                attribute_id,
+                max_length,
+                length,
+            } => write!(
+                f,
+                "{} is {} bytes, {} were requested",
+                attribute_name(*attribute_id),
+                length,
+                max_length
+            ),
+            Problem::MalformedAppIdentifier {
+                app_identifier,
+                reason,
+            } => write!(
+                f,
+                "app identifier {:?} {}",
+                String::from_utf8_lossy(app_identifier),
+                reason
+            ),
+            Problem::MalformedDate(value) => {
+                write!(f, "malformed date {:?}", String::from_utf8_lossy(value))
+            }
+            Problem::TrailingBytes(data) => {
+                write!(f, "bytes after the last attribute: {}", hex::encode(data))
+            }
+        }
+    }
+}
+
+fn attribute_name(id: u8) -> String {
+    match NotificationAttributeID::ALL.get(id as usize) {
+        Some(attribute) => format!("{:?}", attribute),
+        None => id.to_string(),
+    }
+}
+
+// What a response is for, from its start
+fn describe(response: &[u8]) -> String {
+    match response {
+        [0, id @ ..] if id.len() >= 4 => format!(
+            "notification {}",
+            u32::from_le_bytes(id[..4].try_into().unwrap())
+        ),
+        [1, app_identifier @ ..] => {
+            let end = app_identifier
+                .iter()
+                .position(|&b| b == 0)
+                .unwrap_or(app_identifier.len());
+            format!("app {}", String::from_utf8_lossy(&app_identifier[..end]))
+        }
+        _ => format!("unknown response {}", hex::encode(response)),
+    }
+}
+
+// Bundle identifiers are alphanumerics, hyphens and periods
+fn app_identifier_problem(app_identifier: &[u8]) -> Option<&'static str> {
+    match str::from_utf8(app_identifier) {
+        Ok("") => Some("is empty"),
+        Ok(app_identifier) => app_identifier
+            .chars()
+            .any(|c| !(c.is_ascii_alphanumeric() || c == '.' || c == '-'))
+            .then_some("has characters other than letters, digits, hyphens and periods"),
+        Err(_) => Some("is not UTF-8"),
+    }
+}
+
+// A command waiting for its response
+struct Request {
+    expected: Expected,
+    // requested attribute ids, with the maximum length where there is one
+    attributes: Vec<(u8, Option<u16>)>,
+}
+
+impl Request {
+    fn from_command(command: &[u8]) -> Option<Self> {
+        let expected = Expected::from_command(command)?;
+        let mut attributes = Vec::new();
+        match &expected {
+            Expected::Notification { .. } => {
+                let mut ids = &command[5..];
+                while let Some((&attribute_id, tail)) = ids.split_first() {
+                    let has_max_length = NotificationAttributeID::ALL
+                        .get(attribute_id as usize)
+                        .is_some_and(|attribute| attribute.has_max_length());
+                    if has_max_length {
+                        let max_length = u16::from_le_bytes([tail[0], tail[1]]);
+                        attributes.push((attribute_id, Some(max_length)));
+                        ids = &tail[2..];
+                    } else {
+                        attributes.push((attribute_id, None));
+                        ids = tail;
+                    }
+                }
+            }
+            Expected::App { .. } => {
+                let null_terminator = command.iter().position(|&b| b == 0)?;
+                let ids = &command[null_terminator + 1..];
+                attributes.extend(ids.iter().map(|&attribute_id| (attribute_id, None)));
+            }
+        }
+        Some(Self {
+            expected,
+            attributes,
+        })
+    }
+}
+
+// Follows the traffic of one phone packet by packet
+#[derive(Default)]
+pub struct Checker {
+    // notifications added and not removed yet
+    announced: HashSet<u32>,
+    request: Option<Request>,
+    response: Vec<u8>,
+}
+
+impl Checker {
+    pub fn packet(&mut self, packet: &Packet) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        match packet {
+            Packet::Ns { data } => self.event(data, &mut problems),
+            Packet::Cp {
+                data,
+                att_error,
+                failed,
+                ..
+            } => {
+                problems.extend(self.finish());
+                // a rejected command gets no response
+                if att_error.is_none() && failed.is_none() {
+                    self.request = Request::from_command(data);
+                }
+            }
+            Packet::Ds { data } => self.response(data, &mut problems),
+            Packet::Disconnected => {
+                problems.extend(self.finish());
+                // notification ids only hold for a connection
+                self.announced.clear();
+            }
+            Packet::Time { .. } | Packet::Connected => {}
+        }
+        problems
+    }
+
+    // A response still coming in when the trace ends is incomplete
+    pub fn finish(&mut self) -> Option<Problem> {
+        self.request = None;
+        if self.response.is_empty() {
+            return None;
+        }
+        Some(Problem::IncompleteResponse(std::mem::take(
+            &mut self.response,
+        )))
+    }
+
+    fn event(&mut self, data: &[u8], problems: &mut Vec<Problem>) {
+        let event = match NotificationEvent::from_buffer(data.to_vec()) {
+            Some(event) if data.len() == 8 => event,
+            _ => {
+                problems.push(Problem::MalformedEvent(data.to_vec()));
+                return;
+            }
+        };
+        if CategoryID::try_from(event.category_id).is_err() {
+            problems.push(Problem::UnknownCategory(event.category_id));
+        }
+        if event.event_flags & 0xE0 != 0 {
+            problems.push(Problem::ReservedFlags(event.event_flags));
+        }
+        let added = event.event_id == EventID::NotificationAdded as u8;
+        if EventFlag::PreExisting.is_set(event.event_flags) && !added {
+            problems.push(Problem::PreExistingNotAdded {
+                event_id: event.event_id,
+                notification_id: event.notification_id,
+            });
+        }
+        if added {
+            self.announced.insert(event.notification_id);
+        } else if event.event_id == EventID::NotificationModified as u8
+            || event.event_id == EventID::NotificationRemoved as u8
+        {
+            let known = if event.event_id == EventID::NotificationRemoved as u8 {
+                self.announced.remove(&event.notification_id)
+            } else {
+                self.announced.contains(&event.notification_id)
+            };
+            if !known {
+                problems.push(Problem::UnannouncedEvent {
+                    event_id: event.event_id,
+                    notification_id: event.notification_id,
+                });
+            }
+        } else {
+            problems.push(Problem::UnknownEvent(event.event_id));
+        }
+    }
+
+    fn response(&mut self, data: &[u8], problems: &mut Vec<Problem>) {
+        self.response.extend(data);
+        let Some(request) = &self.request else {
+            problems.push(Problem::ResponseWithoutCommand(std::mem::take(
+                &mut self.response,
+            )));
+            return;
+        };
+        match request.expected.progress(&self.response) {
+            Progress::Complete => {
+                let request = self.request.take().unwrap();
+                let response = std::mem::take(&mut self.response);
+                self.attributes(&request, &response, problems);
+            }
+            Progress::Incomplete => {}
+            Progress::Unrelated => {
+                let expected = match &request.expected {
+                    Expected::Notification {
+                        notification_id, ..
+                    } => format!("notification {}", notification_id),
+                    Expected::App { app_identifier, .. } => format!("app {}", app_identifier),
+                };
+                problems.push(Problem::OutOfOrderResponse {
+                    expected,
+                    response: std::mem::take(&mut self.response),
+                });
+            }
+        }
+    }
+
+    // Checks a complete response, which holds as many attributes as were requested
+    fn attributes(&self, request: &Request, response: &[u8], problems: &mut Vec<Problem>) {
+        let (app, mut rest) = match &request.expected {
+            Expected::Notification {
+                notification_id, ..
+            } => {
+                if !self.announced.contains(notification_id) {
+                    problems.push(Problem::UnannouncedResponse(*notification_id));
+                }
+                (false, &response[5..])
+            }
+            Expected::App { .. } => {
+                let null_terminator = response.iter().position(|&b| b == 0).unwrap();
+                let app_identifier = &response[1..null_terminator];
+                if let Some(reason) = app_identifier_problem(app_identifier) {
+                    problems.push(Problem::MalformedAppIdentifier {
+                        app_identifier: app_identifier.to_vec(),
+                        reason,
+                    });
+                }
+                (true, &response[null_terminator + 1..])
+            }
+        };
+        for _ in 0..request.attributes.len() {
+            let attribute_id = rest[0];
+            let length = u16::from_le_bytes([rest[1], rest[2]]);
+            let value = &rest[3..3 + length as usize];
+            rest = &rest[3 + length as usize..];
+            match request
+                .attributes
+                .iter()
+                .find(|(requested, _)| *requested == attribute_id)
+            {
+                None => problems.push(Problem::UnrequestedAttribute(attribute_id)),
+                Some(&(_, Some(max_length))) if length > max_length => {
+                    problems.push(Problem::AttributeTooLong {
+                        attribute_id,
+                        max_length,
+                        length,
+                    })
+                }
+                Some(_) => {}
+            }
+            if app {
+                continue;
+            }
+            if attribute_id == NotificationAttributeID::AppIdentifier as u8 {
+                if let Some(reason) = app_identifier_problem(value) {
+                    problems.push(Problem::MalformedAppIdentifier {
+                        app_identifier: value.to_vec(),
+                        reason,
+                    });
+                }
+            }
+            if attribute_id == NotificationAttributeID::Date as u8
+                && !value.is_empty()
+                && str::from_utf8(value).ok().and_then(date::parse).is_none()
+            {
+                problems.push(Problem::MalformedDate(value.to_vec()));
+            }
+        }
+        if !rest.is_empty() {
+            problems.push(Problem::TrailingBytes(rest.to_vec()));
+        }
+    }
+}
+
+// Logs problems as they happen, for `ancs run --check`
+impl Tap for Mutex<Checker> {
+    fn packet(&self, packet: Packet) {
+        for problem in self.lock().unwrap().packet(&packet) {
+            warn!("ANCS conformance: {}", problem);
+        }
+    }
+}
+
+// Checks a whole trace, one line per problem. Returns how many there were.
+pub fn report<W: Write>(out: &mut W, trace: &Trace) -> io::Result<usize> {
+    let mut checker = Checker::default();
+    let mut count = 0;
+    for record in &trace.records {
+        let seconds = record.t as f64 / 1_000_000.0;
+        for problem in checker.packet(&record.packet) {
+            writeln!(out, "{:>12.6} {}", seconds, problem)?;
+            count += 1;
+        }
+    }
+    if let Some(problem) = checker.finish() {
+        writeln!(out, "         end {}", problem)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ns(event_id: u8, event_flags: u8, category_id: u8, notification_id: u32) -> Packet {
+        let mut data = vec![event_id, event_flags, category_id, 1];
+        data.extend(notification_id.to_le_bytes());
+        Packet::Ns { data }
+    }
+
+    fn check(packets: &[Packet]) -> Vec<Problem> {
+        let mut checker = Checker::default();
+        let mut problems: Vec<Problem> = packets
+            .iter()
+            .flat_map(|packet| checker.packet(packet))
+            .collect();
+        problems.extend(checker.finish());
+        problems
+    }
+
+    #[test]
+    fn events() {
+        let problems = check(&[
+            ns(0, EventFlag::PreExisting as u8, 4, 1),
+            ns(1, 0, 4, 1),
+            ns(3, 0, 4, 1),
+            ns(0, 0x40, 12, 2),
+            ns(1, EventFlag::PreExisting as u8, 4, 2),
+            ns(2, 0, 4, 1),
+            ns(2, 0, 4, 1),
+            Packet::Ns { data: vec![0, 0] },
+        ]);
+        assert_eq!(
+            problems,
+            vec![
+                Problem::UnknownEvent(3),
+                Problem::UnknownCategory(12),
+                Problem::ReservedFlags(0x40),
+                Problem::PreExistingNotAdded {
+                    event_id: 1,
+                    notification_id: 2
+                },
+                Problem::UnannouncedEvent {
+                    event_id: 2,
+                    notification_id: 1
+                },
+                Problem::MalformedEvent(vec![0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn responses() {
+        // title up to 4 bytes and the date of notification 7
+        let command = vec![0, 7, 0, 0, 0, 1, 4, 0, 5];
+        let mut too_long = vec![0, 7, 0, 0, 0, 1, 5, 0];
+        too_long.extend(b"Alice");
+        too_long.extend([5, 3, 0]);
+        too_long.extend(b"now");
+        let problems = check(&[
+            ns(0, 0, 4, 7),
+            Packet::cp(command.clone(), &Ok(())),
+            Packet::Ds {
+                data: too_long[..6].to_vec(),
+            },
+            Packet::Ds {
+                data: too_long[6..].to_vec(),
+            },
+            Packet::Ds {
+                data: vec![0, 7, 0, 0, 0, 1, 0, 0],
+            },
+            Packet::cp(vec![0, 9, 0, 0, 0, 3, 4, 0], &Ok(())),
+            Packet::Ds {
+                data: vec![0, 9, 0, 0, 0, 1, 0, 0, 0xFF],
+            },
+            Packet::cp(command.clone(), &Ok(())),
+            Packet::Ds {
+                data: vec![0, 8, 0, 0, 0],
+            },
+            Packet::cp(b"\x01com.a b\0\0".to_vec(), &Ok(())),
+            Packet::Ds {
+                data: b"\x01com.a b\0\0\x01\0X".to_vec(),
+            },
+            Packet::cp(command, &Ok(())),
+            Packet::Ds {
+                data: vec![0, 7, 0, 0, 0, 1, 2],
+            },
+        ]);
+        assert_eq!(
+            problems,
+            vec![
+                Problem::AttributeTooLong {
+                    attribute_id: 1,
+                    max_length: 4,
+                    length: 5
+                },
+                Problem::MalformedDate(b"now".to_vec()),
+                Problem::ResponseWithoutCommand(vec![0, 7, 0, 0, 0, 1, 0, 0]),
+                Problem::UnannouncedResponse(9),
+                Problem::UnrequestedAttribute(1),
+                Problem::TrailingBytes(vec![0xFF]),
+                Problem::OutOfOrderResponse {
+                    expected: "notification 7".to_string(),
+                    response: vec![0, 8, 0, 0, 0]
+                },
+                Problem::MalformedAppIdentifier {
+                    app_identifier: b"com.a b".to_vec(),
+                    reason: "has characters other than letters, digits, hyphens and periods"
+                },
+                Problem::IncompleteResponse(vec![0, 7, 0, 0, 0, 1, 2]),
+            ]
+        );
+        assert_eq!(
+            problems[6].to_string(),
+            "response for notification 8 while waiting for notification 7"
+        );
+    }
+}
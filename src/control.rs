@@ -0,0 +1,475 @@
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::ancs::control_point::ActionID;
+use crate::events::{Event, Events};
+
+// JSON-RPC 2.0 over a Unix socket, one request and one response object per line, e.g.
+// {"jsonrpc": "2.0", "id": 1, "method": "action", "params": {"notification_id": 3, "action": "negative"}}
+// After `subscribe` the connection also carries every daemon event as an `event` notification.
+
+const SUBSCRIBE: &str = "subscribe";
+// method of the notifications that carry events
+const EVENT: &str = "event";
+
+// Standard JSON-RPC error codes, failures of a well-formed call are `SERVER_ERROR`
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const SERVER_ERROR: i64 = -32000;
+
+// Commands accepted on the control socket
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    // connection, notification count and do-not-disturb
+    Status,
+    // notifications currently on the phone
+    List,
+    // performs one of the actions the phone offers on a notification
+    Action(u32, ActionID),
+    // closes the desktop notification, the notification stays on the phone
+    Dismiss(u32),
+    // `on`, `off` and `auto` (follow the configured schedule)
+    Dnd(Option<bool>),
+    DndStatus,
+    // fetches the whole message of a notification
+    FullMessage(u32),
+    // re-reads ancs.toml
+    Reload,
+    // drops the link to the phone and connects again
+    Reconnect,
+}
+
+#[derive(Deserialize)]
+struct NotificationParams {
+    notification_id: u32,
+}
+
+#[derive(Deserialize)]
+struct ActionParams {
+    notification_id: u32,
+    action: String,
+}
+
+#[derive(Default, Deserialize)]
+struct DndParams {
+    state: Option<String>,
+}
+
+impl Command {
+    pub fn from_rpc(method: &str, params: Value) -> Result<Self, RpcError> {
+        match method {
+            "status" => Ok(Command::Status),
+            "list" => Ok(Command::List),
+            "action" => {
+                let params: ActionParams = params_from(params)?;
+                let action =
+                    parse_action(&params.action).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+                Ok(Command::Action(params.notification_id, action))
+            }
+            "dismiss" => {
+                let params: NotificationParams = params_from(params)?;
+                Ok(Command::Dismiss(params.notification_id))
+            }
+            "dnd" => {
+                let params: DndParams = match params {
+                    Value::Null => DndParams::default(),
+                    params => params_from(params)?,
+                };
+                match params.state.as_deref() {
+                    None | Some("status") => Ok(Command::DndStatus),
+                    Some("on") => Ok(Command::Dnd(Some(true))),
+                    Some("off") => Ok(Command::Dnd(Some(false))),
+                    Some("auto") => Ok(Command::Dnd(None)),
+                    Some(state) => Err(RpcError::new(
+                        INVALID_PARAMS,
+                        format!("unknown dnd state: {}", state),
+                    )),
+                }
+            }
+            "message" => {
+                let params: NotificationParams = params_from(params)?;
+                Ok(Command::FullMessage(params.notification_id))
+            }
+            "reload" => Ok(Command::Reload),
+            "reconnect" => Ok(Command::Reconnect),
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method: {}", method),
+            )),
+        }
+    }
+}
+
+fn params_from<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+pub fn parse_action(action: &str) -> Result<ActionID, String> {
+    match action {
+        "positive" => Ok(ActionID::Positive),
+        "negative" => Ok(ActionID::Negative),
+        _ => Err(format!("unknown action: {}", action)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    // requests without an id are notifications and get no response
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: String) -> Self {
+        Self { code, message }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RpcResponse {
+    jsonrpc: String,
+    id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+// Command forwarded to the main loop, which answers on `reply` with a result or an error message
+pub struct Request {
+    pub command: Command,
+    pub reply: oneshot::Sender<Result<Value, String>>,
+}
+
+// Answers one line, `None` for notifications
+async fn handle_line(line: &str, request_tx: &mpsc::Sender<Request>) -> Option<RpcResponse> {
+    let request: RpcRequest = match serde_json::from_str::<Value>(line) {
+        Ok(value) => match serde_json::from_value(value) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = RpcError::new(INVALID_REQUEST, e.to_string());
+                return Some(RpcResponse::new(Value::Null, Err(error)));
+            }
+        },
+        Err(e) => {
+            let error = RpcError::new(PARSE_ERROR, e.to_string());
+            return Some(RpcResponse::new(Value::Null, Err(error)));
+        }
+    };
+    let result = match request.jsonrpc.as_str() {
+        // answered by the connection itself, see `handle_client`
+        "2.0" if request.method == SUBSCRIBE => Ok(Value::Null),
+        "2.0" => match Command::from_rpc(&request.method, request.params) {
+            Ok(command) => {
+                let (reply, response) = oneshot::channel();
+                match request_tx.send(Request { command, reply }).await {
+                    Ok(()) => match response.await {
+                        Ok(result) => result.map_err(|e| RpcError::new(SERVER_ERROR, e)),
+                        Err(_) => Err(RpcError::new(SERVER_ERROR, "no reply".to_string())),
+                    },
+                    Err(_) => Err(RpcError::new(
+                        SERVER_ERROR,
+                        "the daemon stopped".to_string(),
+                    )),
+                }
+            }
+            Err(error) => Err(error),
+        },
+        version => Err(RpcError::new(
+            INVALID_REQUEST,
+            format!("unsupported JSON-RPC version: {}", version),
+        )),
+    };
+    let id = request.id?;
+    Some(RpcResponse::new(id, result))
+}
+
+// Whether `line` subscribes to events, which `handle_line` has answered already
+fn subscribes(line: &str) -> bool {
+    serde_json::from_str::<RpcRequest>(line)
+        .is_ok_and(|request| request.jsonrpc == "2.0" && request.method == SUBSCRIBE)
+}
+
+async fn next_event(subscription: &mut Option<mpsc::UnboundedReceiver<Event>>) -> Option<Event> {
+    match subscription {
+        Some(events_rx) => events_rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn handle_client(stream: UnixStream, request_tx: mpsc::Sender<Request>, events: Events) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut subscription = None;
+
+    loop {
+        let message = tokio::select! {
+            line = lines.next_line() => {
+                let Ok(Some(line)) = line else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let response = handle_line(&line, &request_tx).await;
+                if subscription.is_none() && subscribes(&line) {
+                    subscription = Some(events.subscribe());
+                }
+                let Some(response) = response else {
+                    continue;
+                };
+                serde_json::to_value(response)
+            }
+            Some(event) = next_event(&mut subscription) => serde_json::to_value(event).map(|event| {
+                serde_json::json!({"jsonrpc": "2.0", "method": EVENT, "params": event})
+            }),
+        };
+        let Ok(message) = message else {
+            break;
+        };
+        if writer
+            .write_all(format!("{}\n", message).as_bytes())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+// Calls a single method and returns its result, or the daemon's error message
+pub async fn call(
+    socket_path: &Path,
+    method: &str,
+    params: Value,
+) -> io::Result<Result<Value, RpcError>> {
+    let stream = UnixStream::connect(socket_path).await?;
+    let (reader, mut writer) = stream.into_split();
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+    writer
+        .write_all(format!("{}\n", request).as_bytes())
+        .await?;
+    writer.shutdown().await?;
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no response"))?;
+    let response: RpcResponse = serde_json::from_str(&line)?;
+    Ok(match response.error {
+        Some(error) => Err(error),
+        None => Ok(response.result.unwrap_or_default()),
+    })
+}
+
+// Events the daemon publishes, as JSON objects, for as long as the connection is kept open
+pub struct Subscription {
+    lines: tokio::io::Lines<BufReader<OwnedReadHalf>>,
+    // closing our side would end the subscription
+    _writer: OwnedWriteHalf,
+}
+
+impl Subscription {
+    pub async fn next(&mut self) -> io::Result<Option<Value>> {
+        while let Some(line) = self.lines.next_line().await? {
+            let mut message: Value = serde_json::from_str(&line)?;
+            if message["method"] == EVENT {
+                return Ok(Some(message["params"].take()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub async fn subscribe(socket_path: &Path) -> io::Result<Subscription> {
+    let stream = UnixStream::connect(socket_path).await?;
+    let (reader, mut writer) = stream.into_split();
+    let request = serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": SUBSCRIBE});
+    writer
+        .write_all(format!("{}\n", request).as_bytes())
+        .await?;
+    // events published from now on are on their way once the daemon answered
+    let mut lines = BufReader::new(reader).lines();
+    lines
+        .next_line()
+        .await?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no response"))?;
+    Ok(Subscription {
+        lines,
+        _writer: writer,
+    })
+}
+
+// asynchronous listener
+pub async fn listener(socket_path: PathBuf, request_tx: mpsc::Sender<Request>, events: Events) {
+    // a stale socket from a previous run would make bind fail
+    let _ = std::fs::remove_file(&socket_path);
+    let listener = match UnixListener::bind(&socket_path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(
+                "Cannot bind control socket {}: {}",
+                socket_path.display(),
+                e
+            );
+            return;
+        }
+    };
+    info!("Listening for commands on {}", socket_path.display());
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                debug!("Control client connected");
+                tokio::spawn(handle_client(stream, request_tx.clone(), events.clone()));
+            }
+            Err(e) => error!("Control socket accept failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn methods() {
+        assert_eq!(
+            Command::from_rpc("status", Value::Null),
+            Ok(Command::Status)
+        );
+        assert_eq!(
+            Command::from_rpc("dnd", json!({"state": "on"})),
+            Ok(Command::Dnd(Some(true)))
+        );
+        assert_eq!(
+            Command::from_rpc("dnd", json!({"state": "auto"})),
+            Ok(Command::Dnd(None))
+        );
+        assert_eq!(
+            Command::from_rpc("dnd", Value::Null),
+            Ok(Command::DndStatus)
+        );
+        assert_eq!(
+            Command::from_rpc("message", json!({"notification_id": 12})),
+            Ok(Command::FullMessage(12))
+        );
+        assert_eq!(
+            Command::from_rpc(
+                "action",
+                json!({"notification_id": 3, "action": "negative"})
+            ),
+            Ok(Command::Action(3, ActionID::Negative))
+        );
+        let error = Command::from_rpc("action", json!({"notification_id": 3, "action": "maybe"}))
+            .unwrap_err();
+        assert_eq!(error.code, INVALID_PARAMS);
+        let error = Command::from_rpc("message", json!({"notification_id": "twelve"})).unwrap_err();
+        assert_eq!(error.code, INVALID_PARAMS);
+        let error = Command::from_rpc("reboot", Value::Null).unwrap_err();
+        assert_eq!(error.code, METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn socket() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket_path = directory.path().join("control.sock");
+        let (request_tx, mut request_rx) = mpsc::channel(1);
+        let events = Events::default();
+        tokio::spawn(listener(socket_path.clone(), request_tx, events.clone()));
+        tokio::spawn(async move {
+            while let Some(request) = request_rx.recv().await {
+                let result = match request.command {
+                    Command::DndStatus => Ok(json!({"active": false})),
+                    _ => Err("not now".to_string()),
+                };
+                let _ = request.reply.send(result);
+            }
+        });
+        while !socket_path.exists() {
+            tokio::task::yield_now().await;
+        }
+
+        let result = call(&socket_path, "dnd", Value::Null).await.unwrap();
+        assert_eq!(result, Ok(json!({"active": false})));
+        let result = call(&socket_path, "reload", Value::Null).await.unwrap();
+        assert_eq!(
+            result,
+            Err(RpcError::new(SERVER_ERROR, "not now".to_string()))
+        );
+
+        // subscribers get every event published after they subscribed
+        let mut subscription = subscribe(&socket_path).await.unwrap();
+        events.publish(Event::Connection { connected: false });
+        assert_eq!(
+            subscription.next().await.unwrap(),
+            Some(json!({"event": "connection", "connected": false}))
+        );
+
+        // malformed lines are answered, notifications are not
+        let request_tx = mpsc::channel(1).0;
+        let response = handle_line("{", &request_tx).await.unwrap();
+        assert_eq!(response.error.unwrap().code, PARSE_ERROR);
+        let response = handle_line(
+            r#"{"jsonrpc": "1.0", "id": 7, "method": "list"}"#,
+            &request_tx,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.id, json!(7));
+        assert_eq!(response.error.unwrap().code, INVALID_REQUEST);
+        assert!(
+            handle_line(r#"{"jsonrpc": "2.0", "method": "reboot"}"#, &request_tx)
+                .await
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,1309 @@
+use config::Config;
+use futures::StreamExt;
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{str::FromStr, time::Duration, time::Instant};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::ancs::client::{AncsClient, ClientError};
+use crate::ancs::control_point::{
+    ActionID, AncsCommandError, AppAttributeCmd, AppAttributeID, EventFlag, EventID,
+    NotificationActionCmd, NotificationAttributeCmd, NotificationAttributeID,
+};
+use crate::ancs::data_source::{AppAttributes, NotificationAttributes};
+use crate::ancs::date::{self, PhoneClock};
+use crate::ancs::notification_source::NotificationEvent;
+use crate::ancs::replay::{Pace, ReplayTransport};
+use crate::ancs::socket::SocketTransport;
+use crate::ancs::trace::{RecordingTransport, Tap, Trace, TraceWriter};
+use crate::ancs::transport::{ConnectionEvent, Transport, TransportError};
+use crate::ancs::{self, bluez::BluezTransport, notification::ANCSNotification};
+use crate::attributes::{AttributeRequest, AttributesConfig};
+use crate::cli::RunArgs;
+use crate::coalesce::{CoalesceConfig, Coalesced, Coalescer};
+use crate::conformance::Checker;
+use crate::control::{self, Command};
+use crate::dnd::{self, DndConfig, DoNotDisturb, Suppressed};
+use crate::events::{Attributes, Event, Events};
+use crate::history::{History, HistoryConfig};
+use crate::notify::{self, ActionInvoked, Desktop, Notifier};
+use crate::service;
+
+pub async fn run(xdg_dirs: xdg::BaseDirectories, args: RunArgs) {
+    let config_path_exists = xdg_dirs.find_config_file("ancs.toml");
+    if config_path_exists.is_none() {
+        error!("Cannot find config file");
+        return;
+    }
+    let config_path = config_path_exists.unwrap();
+    // Load config
+    let config = match load_config(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Cannot load config {}: {}", config_path.display(), e);
+            return;
+        }
+    };
+
+    info!("Starting ANCS application ...");
+
+    let settings = match Settings::from_config(&config) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    // Notification history is kept unless disabled in the config
+    let history_config = config.get::<HistoryConfig>("history").unwrap_or_default();
+    let mut history = None;
+    if history_config.enabled {
+        match History::default_path(&xdg_dirs).and_then(|path| History::open(path, history_config))
+        {
+            Ok(store) => history = Some(store),
+            Err(e) => {
+                error!("Cannot open notification history: {}", e);
+                return;
+            }
+        }
+    }
+
+    // One JSON object per line on stdout for `jq` and other tools
+    let events = Events::default();
+    if args.json {
+        tokio::spawn(print_events(events.subscribe()));
+    }
+
+    // Spawn the control socket, e.g. `ancsctl dnd on`
+    let (control_tx, control_rx) = mpsc::channel(16);
+    match xdg_dirs.place_runtime_file("control.sock") {
+        Ok(socket_path) => {
+            tokio::spawn(control::listener(
+                socket_path,
+                control_tx.clone(),
+                events.clone(),
+            ));
+        }
+        Err(e) => warn!("Control socket disabled: {}", e),
+    }
+
+    // Other desktop components reach the phone's notifications on the session bus
+    if let Err(e) = service::start(control_tx, events.subscribe()).await {
+        warn!("D-Bus service {} disabled: {}", service::SERVICE_NAME, e);
+    }
+
+    // Desktop notifications and the actions clicked on them
+    let (action_tx, action_rx) = mpsc::channel(16);
+    let notifier = match Notifier::connect(action_tx).await {
+        Ok(notifier) => notifier,
+        Err(e) => {
+            error!("Cannot connect to the notification service: {}", e);
+            return;
+        }
+    };
+
+    let daemon = Daemon {
+        desktop: notifier,
+        attribute_request: settings.attribute_request,
+        coalescer: Coalescer::from_config(&settings.coalesce),
+        history,
+        // validated by `Settings::from_config`
+        dnd: DoNotDisturb::from_config(&settings.dnd).unwrap(),
+        events,
+        config_path: Some(config_path),
+    };
+
+    if let Some(trace_path) = args.replay {
+        let pace = match args.speed {
+            _ if args.fast => Pace::Fast,
+            Some(speed) if !speed.is_finite() || speed <= 0.0 => {
+                error!("Invalid replay speed {}", speed);
+                return;
+            }
+            speed => Pace::Scaled(speed.unwrap_or(1.0)),
+        };
+        let trace = match Trace::read(&trace_path) {
+            Ok(trace) => trace,
+            Err(e) => {
+                error!("Cannot read trace {}", e);
+                return;
+            }
+        };
+        info!("Replaying {} for ANCS", trace_path.display());
+        daemon
+            .start(
+                ReplayTransport::new(trace, pace),
+                args.record,
+                args.check,
+                control_rx,
+                action_rx,
+            )
+            .await;
+        return;
+    }
+
+    if let Some(socket_path) = args.simulator {
+        let transport = match SocketTransport::connect(&socket_path).await {
+            Ok(transport) => transport,
+            Err(e) => {
+                error!(
+                    "Cannot connect to simulator {}: {}",
+                    socket_path.display(),
+                    e
+                );
+                return;
+            }
+        };
+        info!("Using simulator {} for ANCS", socket_path.display());
+        daemon
+            .start(transport, args.record, args.check, control_rx, action_rx)
+            .await;
+        return;
+    }
+
+    // Get iphone device using MAC address from config
+    let address_exists = config.get_string("address");
+    if address_exists.is_err() {
+        error!("Config does not provide MAC of iPhone");
+        return;
+    }
+    let address = address_exists.unwrap();
+    let address = match bluer::Address::from_str(&address) {
+        Ok(address) => address,
+        Err(e) => {
+            error!("Invalid iphone address {}: {}", address, e);
+            return;
+        }
+    };
+    let transport = match BluezTransport::connect(address).await {
+        Ok(transport) => transport,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    info!("Using {} for ANCS", address);
+
+    daemon
+        .start(transport, args.record, args.check, control_rx, action_rx)
+        .await;
+}
+
+fn load_config(config_path: &Path) -> Result<Config, config::ConfigError> {
+    Config::builder()
+        .add_source(config::File::from(config_path))
+        .build()
+}
+
+fn reload(config_path: &Path) -> Result<Settings, String> {
+    let config = load_config(config_path).map_err(|e| e.to_string())?;
+    Settings::from_config(&config)
+}
+
+// The parts of ancs.toml that are read again on `reload`. The address and the history settings
+// only take effect on a restart.
+pub struct Settings {
+    // attributes fetched for every notification, e.g. to leave out messages or cap their length
+    pub attribute_request: AttributeRequest,
+    // bursts from the same app are grouped when a coalescing window is configured
+    pub coalesce: CoalesceConfig,
+    // quiet hours are optional
+    pub dnd: DndConfig,
+}
+
+impl Settings {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let attributes_config = config
+            .get::<AttributesConfig>("attributes")
+            .unwrap_or_default();
+        let attribute_request = AttributeRequest::from_config(&attributes_config)
+            .map_err(|e| format!("Invalid attributes config: {}", e))?;
+        let dnd = config.get::<DndConfig>("dnd").unwrap_or_default();
+        DoNotDisturb::from_config(&dnd).map_err(|e| format!("Invalid dnd config: {}", e))?;
+        Ok(Self {
+            attribute_request,
+            coalesce: config.get::<CoalesceConfig>("coalesce").unwrap_or_default(),
+            dnd,
+        })
+    }
+}
+
+// The protocol and display logic, set up by `run` from the config and the real phone and
+// desktop, or by tests with fakes
+pub struct Daemon<D> {
+    pub desktop: D,
+    pub attribute_request: AttributeRequest,
+    pub coalescer: Coalescer,
+    pub history: Option<History>,
+    pub dnd: DoNotDisturb,
+    // what front-ends such as the D-Bus service are told
+    pub events: Events,
+    // read again on `reload`, tests run without one
+    pub config_path: Option<PathBuf>,
+}
+
+impl<D: Desktop> Daemon<D> {
+    // Serves `transport`, recording its traffic to `record` and checking it against the
+    // specification first if asked to
+    async fn start<T: Transport>(
+        self,
+        transport: T,
+        record: Option<PathBuf>,
+        check: bool,
+        control_rx: mpsc::Receiver<control::Request>,
+        action_rx: mpsc::Receiver<ActionInvoked>,
+    ) {
+        let mut taps: Vec<Arc<dyn Tap>> = Vec::new();
+        if let Some(trace_path) = record {
+            match TraceWriter::create(&trace_path, transport.link_info().await) {
+                Ok(trace) => {
+                    info!("Recording ANCS traffic to {}", trace_path.display());
+                    taps.push(Arc::new(trace));
+                }
+                Err(e) => {
+                    error!("Cannot record to {}: {}", trace_path.display(), e);
+                    return;
+                }
+            }
+        }
+        if check {
+            info!("Checking ANCS traffic against the specification");
+            taps.push(Arc::new(Mutex::new(Checker::default())));
+        }
+        if taps.is_empty() {
+            return self.serve(Arc::new(transport), control_rx, action_rx).await;
+        }
+        let transport = RecordingTransport::new(transport, Arc::new(taps));
+        self.serve(Arc::new(transport), control_rx, action_rx).await;
+    }
+
+    pub async fn serve<T: Transport>(
+        self,
+        transport: Arc<T>,
+        mut control_rx: mpsc::Receiver<control::Request>,
+        mut action_rx: mpsc::Receiver<ActionInvoked>,
+    ) {
+        let Daemon {
+            desktop,
+            mut attribute_request,
+            mut coalescer,
+            mut history,
+            mut dnd,
+            events,
+            config_path,
+        } = self;
+
+        // The phone's time zone is taken from its Current Time Service when it offers one
+        let mut clock = PhoneClock::default();
+        match transport.read_current_time().await {
+            Ok(Some(buffer)) => match date::parse_current_time(&buffer) {
+                Some(phone_now) => {
+                    clock = PhoneClock::from_current_time(phone_now, chrono::Utc::now());
+                    info!("Phone clock offset: {:?}", clock.offset());
+                }
+                None => warn!("Malformed current time: {:?}", buffer),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("Cannot read current time: {}", e),
+        }
+
+        // Create message queues for application comms
+        let (notification_event_tx, mut notification_event_rx) = mpsc::channel(64);
+        let (notification_attributes_tx, mut notification_attributes_rx) = mpsc::channel(64);
+        let (full_message_tx, mut full_message_rx) =
+            mpsc::channel::<(u32, Result<NotificationAttributes, ClientError>)>(16);
+        let (app_attributes_tx, mut app_attributes_rx) = mpsc::channel(64);
+
+        // Spawn a listener that will handle the bluetooth message parsing for notification sources
+        if let Err(e) = listen(transport.as_ref(), &notification_event_tx).await {
+            error!("Cannot subscribe to notification source: {}", e);
+            return;
+        }
+        let mut connection_events = match transport.connection_events().await {
+            Ok(connection_events) => connection_events,
+            Err(e) => {
+                warn!("Cannot watch the connection: {}", e);
+                futures::stream::pending().boxed()
+            }
+        };
+
+        // Commands go through the client, which pairs them with their data source responses
+        let mut client = match AncsClient::connect(transport.clone()).await {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Cannot subscribe to data source: {}", e);
+                return;
+            }
+        };
+        events.publish(Event::Connection { connected: true });
+
+        let mut notifications: HashMap<u32, ANCSNotification> = HashMap::new();
+        let mut display_names: HashMap<String, String> = HashMap::new();
+        // apps whose display name was asked for, answered or not
+        let mut requested_apps: HashSet<String> = HashSet::new();
+        let mut groups = notify::Groups::default();
+        // notifications whose full message was requested, with the control clients waiting for it
+        let mut full_message_requests: HashMap<u32, Vec<oneshot::Sender<Result<Value, String>>>> =
+            HashMap::new();
+        let mut connected = true;
+
+        // Main thread code
+        debug!("Starting main loop ...");
+        let mut dnd_tick = tokio::time::interval(Duration::from_secs(30));
+        loop {
+            tokio::select! {
+                Some(event) = notification_event_rx.recv() => {
+                    // reserved ids are not modifications, refetching attributes would not help
+                    let Some(source_event) = Event::from_source(&event) else {
+                        warn!("Ignoring unknown event id {} for {}", event.event_id, event.notification_id);
+                        continue;
+                    };
+                    events.publish(source_event);
+                    if event.event_id == EventID::NotificationRemoved as u8 {
+                        record(&mut history, |h| h.removed(event.notification_id));
+                        // e.g. read or answered on the phone, the bubble goes as well
+                        if let Some(mut notification) = notifications.remove(&event.notification_id) {
+                            notification.close(&desktop).await;
+                        }
+                        dnd.remove(event.notification_id);
+                        if let Some(group) = coalescer.remove(event.notification_id) {
+                            groups.close(&desktop, group).await;
+                        }
+                        continue;
+                    }
+                    if event.event_id == EventID::NotificationAdded as u8 {
+                        notifications.insert(event.notification_id, ANCSNotification::new(event.clone()));
+                        record(&mut history, |h| h.arrived(&event));
+                    } else {
+                        if let Some(notification) = notifications.get_mut(&event.notification_id) {
+                            // e.g. an incoming call that can no longer be answered
+                            notification.event_flags = event.event_flags;
+                        }
+                    }
+                    request_attributes(
+                        &client,
+                        attribute_request.command(event.notification_id),
+                        &notification_attributes_tx,
+                    );
+                }
+                Some(attributes) = notification_attributes_rx.recv() => {
+                    let received = attributes.date.and_then(|date| clock.to_local(date));
+                    events.publish(Event::Attributes(Attributes::new(&attributes, received)));
+                    let Some(notification) = notifications.get_mut(&attributes.notification_id) else {
+                        continue;
+                    };
+                    let notification_id = attributes.notification_id;
+                    record(&mut history, |h| h.updated(&attributes, received));
+                    notification.update(attributes, received);
+                    if let Some(app_identifier) = &notification.app_identifier {
+                        if requested_apps.insert(app_identifier.clone()) {
+                            request_display_name(&client, app_identifier.clone(), &app_attributes_tx);
+                        }
+                    }
+                    if !notification.displayable() {
+                        continue;
+                    }
+                    let app_identifier = notification.app_identifier.clone().unwrap_or_default();
+                    let app_name = display_names.get(&app_identifier).cloned();
+                    events.publish(Event::Notification(notification.snapshot(notification_id, app_name.clone())));
+                    // quiet hours may have ended since the last tick
+                    show_dnd_summary(&desktop, dnd.update(chrono::Local::now().naive_local())).await;
+                    let app = app_name.unwrap_or(app_identifier.clone());
+                    let title = notification.title.clone().unwrap_or_default();
+                    if dnd.suppresses(notification.category_id, notification.app_identifier.as_deref()) {
+                        dnd.suppress(Suppressed { notification_id, app, title });
+                        record(&mut history, |h| h.action(notification_id, "suppressed"));
+                        continue;
+                    }
+                    let message = notification.message.clone().unwrap_or_default();
+                    match coalescer.push(notification_id, &app_identifier, &app, &title, &message, Instant::now()) {
+                        Coalesced::Single => notification.show(&desktop).await,
+                        Coalesced::Group { group, replaces, summary, body } => {
+                            record(&mut history, |h| h.action(notification_id, "grouped"));
+                            if let Some(first) = replaces.and_then(|id| notifications.get_mut(&id)) {
+                                first.close(&desktop).await;
+                            }
+                            groups.show(&desktop, group, &summary, &body).await;
+                        }
+                    }
+                }
+                Some((notification_id, result)) = full_message_rx.recv() => {
+                    let replies = full_message_requests.remove(&notification_id).unwrap_or_default();
+                    let attributes = match result {
+                        Ok(attributes) => attributes,
+                        Err(ClientError::Command(AncsCommandError::InvalidParameter)) => {
+                            for reply in replies {
+                                let _ = reply.send(Err(format!("notification {} is no longer on the phone", notification_id)));
+                            }
+                            continue;
+                        }
+                        Err(e) => {
+                            warn!("Cannot get full message of {}: {}", notification_id, e);
+                            for reply in replies {
+                                let _ = reply.send(Err(e.to_string()));
+                            }
+                            continue;
+                        }
+                    };
+                    let received = attributes.date.and_then(|date| clock.to_local(date));
+                    events.publish(Event::Attributes(Attributes::new(&attributes, received)));
+                    let Some(notification) = notifications.get_mut(&notification_id) else {
+                        for reply in replies {
+                            let _ = reply.send(Err(format!("notification {} was removed", notification_id)));
+                        }
+                        continue;
+                    };
+                    // a full message replaces the cut one in place, it is not a new notification
+                    record(&mut history, |h| h.updated(&attributes, received));
+                    notification.update(attributes, received);
+                    if notification.message_truncated() {
+                        warn!(
+                            "Message of {} is still truncated at {} of {} bytes",
+                            notification_id,
+                            notification.message.as_deref().map_or(0, str::len),
+                            notification.message_size.unwrap_or_default()
+                        );
+                    }
+                    record(&mut history, |h| h.action(notification_id, "full message"));
+                    let snapshot = notification.snapshot(notification_id, app_name(&display_names, notification));
+                    events.publish(Event::Notification(snapshot));
+                    if notification.desktop_id.is_some() {
+                        notification.show(&desktop).await;
+                    }
+                    for reply in replies {
+                        let _ = reply.send(Ok(json!(notification.message.clone().unwrap_or_default())));
+                    }
+                }
+                Some(attributes) = app_attributes_rx.recv() => {
+                    events.publish(Event::AppAttributes {
+                        app_identifier: attributes.app_identifier.clone(),
+                        display_name: attributes.display_name.clone(),
+                    });
+                    let Some(display_name) = attributes.display_name else {
+                        continue;
+                    };
+                    // notifications that arrived before the app's name did are announced again
+                    for (&notification_id, notification) in &notifications {
+                        if notification.displayable()
+                            && notification.app_identifier.as_ref() == Some(&attributes.app_identifier)
+                        {
+                            let snapshot = notification.snapshot(notification_id, Some(display_name.clone()));
+                            events.publish(Event::Notification(snapshot));
+                        }
+                    }
+                    display_names.insert(attributes.app_identifier, display_name);
+                }
+                Some(action) = action_rx.recv() => {
+                    // the signal is broadcast, skip actions on other applications' notifications
+                    let Some(notification_id) = notifications
+                        .iter()
+                        .find(|(_, notification)| notification.desktop_id == Some(action.desktop_id))
+                        .map(|(notification_id, _)| *notification_id)
+                    else {
+                        continue;
+                    };
+                    if action.action == notify::FULL_MESSAGE_ACTION
+                        && !full_message_requests.contains_key(&notification_id)
+                    {
+                        full_message_requests.insert(notification_id, Vec::new());
+                        request_full_message(&client, notification_id, &full_message_tx);
+                    }
+                }
+                Some(request) = control_rx.recv() => {
+                    let result = match request.command {
+                        Command::Status => Ok(json!({
+                            "connected": connected,
+                            "notifications": notifications.len(),
+                            "dnd": dnd.status(),
+                        })),
+                        Command::List => {
+                            let mut snapshots: Vec<_> = notifications
+                                .iter()
+                                .filter(|(_, notification)| notification.displayable())
+                                .map(|(&notification_id, notification)| {
+                                    notification.snapshot(notification_id, app_name(&display_names, notification))
+                                })
+                                .collect();
+                            snapshots.sort_by_key(|snapshot| snapshot.notification_id);
+                            Ok(json!(snapshots))
+                        }
+                        Command::FullMessage(notification_id) => {
+                            if !notifications.contains_key(&notification_id) {
+                                let _ = request.reply.send(Err(format!("unknown notification {}", notification_id)));
+                                continue;
+                            }
+                            // one request to the phone answers everyone waiting
+                            let replies = full_message_requests.entry(notification_id).or_default();
+                            if replies.is_empty() {
+                                request_full_message(&client, notification_id, &full_message_tx);
+                            }
+                            replies.push(request.reply);
+                            continue;
+                        }
+                        Command::Action(notification_id, action_id) => {
+                            let Some(notification) = notifications.get(&notification_id) else {
+                                let _ = request.reply.send(Err(format!("unknown notification {}", notification_id)));
+                                continue;
+                            };
+                            let (flag, action) = match action_id {
+                                ActionID::Positive => (EventFlag::PositiveAction, "positive action"),
+                                ActionID::Negative => (EventFlag::NegativeAction, "negative action"),
+                            };
+                            if !flag.is_set(notification.event_flags) {
+                                let _ = request.reply.send(Err(format!("notification {} has no {}", notification_id, action)));
+                                continue;
+                            }
+                            record(&mut history, |h| h.action(notification_id, action));
+                            perform_action(&client, NotificationActionCmd::new(notification_id, action_id), &events, request.reply);
+                            continue;
+                        }
+                        Command::Dismiss(notification_id) => match notifications.get_mut(&notification_id) {
+                            Some(notification) => {
+                                notification.close(&desktop).await;
+                                record(&mut history, |h| h.action(notification_id, "dismissed"));
+                                Ok(Value::Null)
+                            }
+                            None => Err(format!("unknown notification {}", notification_id)),
+                        },
+                        Command::Dnd(manual) => {
+                            dnd.set_manual(manual);
+                            show_dnd_summary(&desktop, dnd.update(chrono::Local::now().naive_local())).await;
+                            Ok(json!(dnd.status()))
+                        }
+                        Command::DndStatus => {
+                            show_dnd_summary(&desktop, dnd.update(chrono::Local::now().naive_local())).await;
+                            Ok(json!(dnd.status()))
+                        }
+                        Command::Reload => match config_path.as_deref().map(reload) {
+                            Some(Ok(settings)) => {
+                                info!("Reloaded config");
+                                attribute_request = settings.attribute_request;
+                                coalescer = Coalescer::from_config(&settings.coalesce);
+                                // the new coalescer starts without groups
+                                groups.close_all(&desktop).await;
+                                // validated by `Settings::from_config`
+                                dnd.reconfigure(&settings.dnd).unwrap();
+                                Ok(Value::Null)
+                            }
+                            Some(Err(e)) => {
+                                warn!("Cannot reload config: {}", e);
+                                Err(e)
+                            }
+                            None => Err("running without a config file".to_string()),
+                        },
+                        Command::Reconnect => {
+                            info!("Reconnecting to the phone");
+                            // the connection events announce the new link, subscriptions follow
+                            let transport = transport.clone();
+                            tokio::spawn(async move {
+                                let result = transport.reconnect().await.map(|()| Value::Null);
+                                let _ = request.reply.send(result.map_err(|e| e.to_string()));
+                            });
+                            continue;
+                        }
+                    };
+                    let _ = request.reply.send(result);
+                }
+                Some(event) = connection_events.next() => match event {
+                    ConnectionEvent::Connected => {
+                        info!("Phone connected");
+                        connected = true;
+                        // notification ids do not outlive the connection, the phone announces
+                        // what it holds again once subscribed
+                        for (&notification_id, notification) in notifications.iter_mut() {
+                            notification.close(&desktop).await;
+                            dnd.remove(notification_id);
+                            if let Some(group) = coalescer.remove(notification_id) {
+                                groups.close(&desktop, group).await;
+                            }
+                            events.publish(Event::Removed { notification_id });
+                        }
+                        notifications.clear();
+                        // answers to requests sent before would belong to the old ids
+                        for (notification_id, replies) in full_message_requests.drain() {
+                            for reply in replies {
+                                let _ = reply.send(Err(format!("notification {} was lost when the phone reconnected", notification_id)));
+                            }
+                        }
+                        events.publish(Event::Connection { connected: true });
+                        if let Err(e) = listen(transport.as_ref(), &notification_event_tx).await {
+                            error!("Cannot subscribe to notification source: {}", e);
+                        }
+                        match AncsClient::connect(transport.clone()).await {
+                            Ok(reconnected) => client = reconnected,
+                            Err(e) => error!("Cannot subscribe to data source: {}", e),
+                        }
+                    }
+                    ConnectionEvent::Disconnected => {
+                        warn!("Phone disconnected");
+                        connected = false;
+                        events.publish(Event::Connection { connected: false });
+                    }
+                },
+                _ = dnd_tick.tick() => {
+                    show_dnd_summary(&desktop, dnd.update(chrono::Local::now().naive_local())).await;
+                }
+            }
+        }
+    }
+}
+
+async fn print_events(mut events_rx: mpsc::UnboundedReceiver<Event>) {
+    while let Some(event) = events_rx.recv().await {
+        if let Ok(json) = serde_json::to_string(&event) {
+            println!("{}", json);
+        }
+    }
+}
+
+// Subscribes to the notification source, events arrive on `notification_event_tx`
+async fn listen<T: Transport>(
+    transport: &T,
+    notification_event_tx: &mpsc::Sender<NotificationEvent>,
+) -> Result<(), TransportError> {
+    let notification_source = transport.notification_source().await?;
+    tokio::spawn(ancs::notification_source::listener(
+        notification_source,
+        notification_event_tx.clone(),
+    ));
+    Ok(())
+}
+
+// The display name the phone gave for the notification's app, if it did yet
+fn app_name(
+    display_names: &HashMap<String, String>,
+    notification: &ANCSNotification,
+) -> Option<String> {
+    notification
+        .app_identifier
+        .as_ref()
+        .and_then(|app_identifier| display_names.get(app_identifier))
+        .cloned()
+}
+
+// History is best effort, a failed write should not take the daemon down
+fn record<F: FnOnce(&mut History) -> std::io::Result<()>>(history: &mut Option<History>, f: F) {
+    if let Some(history) = history.as_mut() {
+        if let Err(e) = f(history) {
+            error!("Cannot write notification history: {}", e);
+        }
+    }
+}
+
+// Lists what was held back once do-not-disturb ends
+async fn show_dnd_summary(desktop: &impl Desktop, suppressed: Option<Vec<Suppressed>>) {
+    if let Some(suppressed) = suppressed {
+        desktop
+            .show(
+                &format!("{} notifications while Do Not Disturb", suppressed.len()),
+                &dnd::summary(&suppressed),
+            )
+            .await;
+    }
+}
+
+// Requests attributes without holding up the main loop, the answer arrives on `attributes_tx`
+fn request_attributes(
+    client: &AncsClient,
+    cmd: NotificationAttributeCmd,
+    attributes_tx: &mpsc::Sender<NotificationAttributes>,
+) {
+    let client = client.clone();
+    let attributes_tx = attributes_tx.clone();
+    tokio::spawn(async move {
+        let notification_id = cmd.notification_id;
+        match client.get_notification_attributes(cmd).await {
+            Ok(attributes) => {
+                let _ = attributes_tx.send(attributes).await;
+            }
+            // removed before we asked, its removal event follows
+            Err(ClientError::Command(AncsCommandError::InvalidParameter)) => {}
+            Err(e) => warn!("Cannot get attributes of {}: {}", notification_id, e),
+        }
+    });
+}
+
+// Fetches the message again without the configured maximum length
+fn request_full_message(
+    client: &AncsClient,
+    notification_id: u32,
+    full_message_tx: &mpsc::Sender<(u32, Result<NotificationAttributes, ClientError>)>,
+) {
+    let cmd = NotificationAttributeCmd::new(
+        notification_id,
+        vec![
+            NotificationAttributeID::Message,
+            NotificationAttributeID::MessageSize,
+        ],
+    )
+    .with_max_length(NotificationAttributeID::Message, u16::MAX);
+    // a whole message can take many data source fragments
+    let client = client.with_timeout(Duration::from_secs(10));
+    let full_message_tx = full_message_tx.clone();
+    tokio::spawn(async move {
+        let result = client.get_notification_attributes(cmd).await;
+        let _ = full_message_tx.send((notification_id, result)).await;
+    });
+}
+
+// The phone removes the notification once the action is performed, the reply only says whether
+// it accepted the command
+fn perform_action(
+    client: &AncsClient,
+    cmd: NotificationActionCmd,
+    events: &Events,
+    reply: oneshot::Sender<Result<Value, String>>,
+) {
+    let client = client.clone();
+    let events = events.clone();
+    tokio::spawn(async move {
+        let notification_id = cmd.notification_id;
+        let action = match cmd.action_id {
+            ActionID::Positive => "positive",
+            ActionID::Negative => "negative",
+        };
+        let result = client.perform_notification_action(cmd).await;
+        if let Err(e) = &result {
+            warn!("Cannot perform action on {}: {}", notification_id, e);
+        }
+        events.publish(Event::Action {
+            notification_id,
+            action,
+            accepted: result.is_ok(),
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+        let _ = reply.send(result.map(|()| Value::Null).map_err(|e| e.to_string()));
+    });
+}
+
+fn request_display_name(
+    client: &AncsClient,
+    app_identifier: String,
+    app_attributes_tx: &mpsc::Sender<AppAttributes>,
+) {
+    let cmd = AppAttributeCmd::new(app_identifier, vec![AppAttributeID::Displayname]);
+    let client = client.clone();
+    let app_attributes_tx = app_attributes_tx.clone();
+    tokio::spawn(async move {
+        let app_identifier = cmd.app_identifier.clone();
+        match client.get_app_attributes(cmd).await {
+            Ok(attributes) => {
+                let _ = app_attributes_tx.send(attributes).await;
+            }
+            Err(e) => warn!("Cannot get display name of {}: {}", app_identifier, e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::control_point::CategoryID;
+    use crate::ancs::fake_phone::{FakeNotification, FakePhone};
+    use crate::notify::FULL_MESSAGE_ACTION;
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Shown {
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+    }
+
+    // Keeps what would be on screen
+    #[derive(Clone, Default)]
+    struct FakeDesktop {
+        shown: Rc<RefCell<BTreeMap<u32, Shown>>>,
+        next_id: Rc<Cell<u32>>,
+    }
+
+    impl FakeDesktop {
+        fn find(&self, summary: &str) -> Option<(u32, Shown)> {
+            self.shown
+                .borrow()
+                .iter()
+                .find(|(_, shown)| shown.summary == summary)
+                .map(|(id, shown)| (*id, shown.clone()))
+        }
+    }
+
+    impl Desktop for FakeDesktop {
+        type Error = String;
+
+        async fn notify(
+            &self,
+            replaces_id: u32,
+            summary: &str,
+            body: &str,
+            actions: &[(&str, &str)],
+        ) -> Result<u32, String> {
+            let id = match replaces_id {
+                0 => {
+                    self.next_id.set(self.next_id.get() + 1);
+                    self.next_id.get()
+                }
+                id => id,
+            };
+            let shown = Shown {
+                summary: summary.to_string(),
+                body: body.to_string(),
+                actions: actions.iter().map(|(key, _)| key.to_string()).collect(),
+            };
+            self.shown.borrow_mut().insert(id, shown);
+            Ok(id)
+        }
+
+        async fn close(&self, id: u32) -> Result<(), String> {
+            self.shown.borrow_mut().remove(&id);
+            Ok(())
+        }
+    }
+
+    async fn until<F: Fn() -> bool>(condition: F) {
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("condition not reached");
+    }
+
+    fn daemon(desktop: FakeDesktop) -> Daemon<FakeDesktop> {
+        Daemon {
+            desktop,
+            attribute_request: AttributeRequest::from_config(&AttributesConfig::default()).unwrap(),
+            coalescer: Coalescer::from_config(&CoalesceConfig::default()),
+            history: None,
+            dnd: DoNotDisturb::from_config(&DndConfig::default()).unwrap(),
+            events: Events::default(),
+            config_path: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn end_to_end() {
+        let phone = FakePhone::new();
+        phone.set_fragment_size(Some(20));
+        let now = chrono::Local::now().naive_local();
+        phone.set_current_time(Some(now));
+        phone.set_display_name("com.apple.MobileSMS", "Messages");
+        let mut earlier = FakeNotification::new("com.apple.MobileSMS", "Alice", "Are you there?");
+        earlier.date = Some(now - chrono::Duration::minutes(10));
+        phone.add(earlier);
+
+        let desktop = FakeDesktop::default();
+        let attributes_config = AttributesConfig {
+            max_length: HashMap::from([("message".to_string(), 8)]),
+            ..Default::default()
+        };
+        let daemon = Daemon {
+            desktop: desktop.clone(),
+            attribute_request: AttributeRequest::from_config(&attributes_config).unwrap(),
+            coalescer: Coalescer::from_config(&CoalesceConfig::default()),
+            history: None,
+            dnd: DoNotDisturb::from_config(&DndConfig::default()).unwrap(),
+            events: Events::default(),
+            config_path: None,
+        };
+        let (_control_tx, control_rx) = mpsc::channel(1);
+        let (action_tx, action_rx) = mpsc::channel(1);
+
+        let script = async {
+            // pre-existing notifications are announced on subscription
+            until(|| desktop.find("Alice").is_some()).await;
+            let (alice_id, alice) = desktop.find("Alice").unwrap();
+            assert_eq!(alice.body, "Are you …\nreceived 10 min ago");
+            assert_eq!(alice.actions, vec![FULL_MESSAGE_ACTION]);
+            let action = ActionInvoked {
+                desktop_id: alice_id,
+                action: FULL_MESSAGE_ACTION.to_string(),
+            };
+            action_tx.send(action).await.unwrap();
+            until(|| {
+                desktop
+                    .find("Alice")
+                    .unwrap()
+                    .1
+                    .body
+                    .starts_with("Are you there?")
+            })
+            .await;
+
+            // a rejected request is dropped, a write that never left is retried
+            phone.fail_next_write(TransportError::Att(
+                AncsCommandError::InvalidParameter as u8,
+            ));
+            phone.add(FakeNotification::new("org.example", "Gone", ""));
+            phone.fail_next_write(TransportError::Unsent("busy".to_string()));
+            let bob = phone.add(FakeNotification::new("org.example", "Bob", "Hi"));
+            until(|| desktop.find("Bob").is_some()).await;
+            assert!(desktop.find("Gone").is_none());
+
+            // modified notifications are updated in place
+            phone.modify(bob, |notification| notification.title = "Bob!".to_string());
+            until(|| desktop.find("Bob!").is_some()).await;
+            assert!(desktop.find("Bob").is_none());
+
+            // removed on the phone, closed on the desktop
+            phone.remove(bob);
+            until(|| desktop.find("Bob!").is_none()).await;
+        };
+        tokio::select! {
+            _ = daemon.serve(Arc::new(phone.clone()), control_rx, action_rx) => panic!("daemon stopped"),
+            _ = script => {}
+        }
+    }
+
+    // Actions go to the phone through the control channel, front-ends hear about the outcome
+    #[tokio::test]
+    async fn actions() {
+        let phone = FakePhone::new();
+        let mut call = FakeNotification::new("com.apple.mobilephone", "Alice", "");
+        call.category_id = CategoryID::IncomingCall;
+        call.event_flags = EventFlag::PositiveAction as u8 | EventFlag::NegativeAction as u8;
+        let call_id = phone.add(call);
+        let message_id = phone.add(FakeNotification::new("org.example", "Bob", "Hi"));
+
+        let desktop = FakeDesktop::default();
+        let daemon = daemon(desktop.clone());
+        let mut events_rx = daemon.events.subscribe();
+        let (control_tx, control_rx) = mpsc::channel(1);
+        let (_action_tx, action_rx) = mpsc::channel(1);
+        let send = |command| {
+            let control_tx = control_tx.clone();
+            async move {
+                let (reply, response) = oneshot::channel();
+                let request = control::Request { command, reply };
+                control_tx.send(request).await.unwrap();
+                response.await.unwrap()
+            }
+        };
+        let script = async {
+            until(|| desktop.find("Alice").is_some() && desktop.find("Bob").is_some()).await;
+            assert_eq!(
+                send(Command::Action(message_id, ActionID::Positive)).await,
+                Err(format!(
+                    "notification {} has no positive action",
+                    message_id
+                ))
+            );
+            assert_eq!(
+                send(Command::Action(call_id, ActionID::Negative)).await,
+                Ok(Value::Null)
+            );
+            assert_eq!(
+                phone.performed_actions(),
+                vec![(call_id, ActionID::Negative as u8)]
+            );
+            let mut seen = Vec::new();
+            let removed = Event::Removed {
+                notification_id: call_id,
+            };
+            while seen.last() != Some(&removed) {
+                seen.push(events_rx.recv().await.unwrap());
+            }
+            assert_eq!(seen[0], Event::Connection { connected: true });
+            assert!(seen.contains(&Event::Action {
+                notification_id: call_id,
+                action: "negative",
+                accepted: true,
+                error: None,
+            }));
+            assert!(seen.iter().any(|event| matches!(
+                event,
+                Event::Added(source) if source.notification_id == message_id
+            )));
+            assert!(seen.iter().any(|event| matches!(
+                event,
+                Event::Notification(notification) if notification.notification_id == call_id
+                    && notification.title.as_deref() == Some("Alice")
+            )));
+        };
+        tokio::select! {
+            _ = daemon.serve(Arc::new(phone.clone()), control_rx, action_rx) => panic!("daemon stopped"),
+            _ = script => {}
+        }
+    }
+
+    // The control socket's methods, as the main loop answers them
+    #[tokio::test]
+    async fn control() {
+        let phone = FakePhone::new();
+        phone.set_display_name("org.example", "Example");
+        let alice = phone.add(FakeNotification::new("org.example", "Alice", "Hi"));
+        let bob = phone.add(FakeNotification::new("org.example", "Bob", "Hey"));
+
+        let desktop = FakeDesktop::default();
+        let daemon = daemon(desktop.clone());
+        let (control_tx, control_rx) = mpsc::channel(1);
+        let (_action_tx, action_rx) = mpsc::channel(1);
+        let send = |command| {
+            let control_tx = control_tx.clone();
+            async move {
+                let (reply, response) = oneshot::channel();
+                let request = control::Request { command, reply };
+                control_tx.send(request).await.unwrap();
+                response.await.unwrap()
+            }
+        };
+        let script = async {
+            until(|| desktop.find("Alice").is_some() && desktop.find("Bob").is_some()).await;
+            let status = send(Command::Status).await.unwrap();
+            assert_eq!(status["connected"], json!(true));
+            assert_eq!(status["notifications"], json!(2));
+            assert_eq!(status["dnd"]["active"], json!(false));
+
+            let list = send(Command::List).await.unwrap();
+            let titles: Vec<_> = list
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|n| n["title"].clone())
+                .collect();
+            assert_eq!(titles, vec![json!("Alice"), json!("Bob")]);
+            assert_eq!(list[0]["notification_id"], json!(alice));
+
+            // dismissing only closes the desktop notification
+            assert_eq!(send(Command::Dismiss(bob)).await, Ok(Value::Null));
+            assert!(desktop.find("Bob").is_none());
+            assert_eq!(
+                send(Command::Status).await.unwrap()["notifications"],
+                json!(2)
+            );
+            assert!(send(Command::Dismiss(99)).await.is_err());
+
+            let dnd = send(Command::Dnd(Some(true))).await.unwrap();
+            assert_eq!(
+                dnd,
+                json!({"active": true, "mode": "manual", "suppressed": 0})
+            );
+            assert_eq!(
+                send(Command::Reload).await,
+                Err("running without a config file".to_string())
+            );
+
+            // the phone announces its notifications again on the new link
+            assert_eq!(
+                send(Command::Dnd(None)).await.unwrap()["active"],
+                json!(false)
+            );
+            let (shown_id, _) = desktop.find("Alice").unwrap();
+            assert_eq!(send(Command::Reconnect).await, Ok(Value::Null));
+            until(|| desktop.find("Alice").is_some_and(|(id, _)| id != shown_id)).await;
+            assert_eq!(
+                send(Command::Status).await.unwrap()["connected"],
+                json!(true)
+            );
+        };
+        tokio::select! {
+            _ = daemon.serve(Arc::new(phone.clone()), control_rx, action_rx) => panic!("daemon stopped"),
+            _ = script => {}
+        }
+    }
+
+    // An edit inside the coalescing window updates the bubble, it is not a second message
+    #[tokio::test]
+    async fn modified_in_window() {
+        let phone = FakePhone::new();
+        let alice = phone.add(FakeNotification::new("org.example", "Alice", "hi"));
+
+        let desktop = FakeDesktop::default();
+        let mut daemon = daemon(desktop.clone());
+        daemon.coalescer = Coalescer::from_config(&CoalesceConfig {
+            window_secs: 60,
+            by_title: false,
+        });
+        let (_control_tx, control_rx) = mpsc::channel(1);
+        let (_action_tx, action_rx) = mpsc::channel(1);
+        let grouped = || {
+            desktop
+                .shown
+                .borrow()
+                .values()
+                .any(|shown| shown.summary.contains("new messages"))
+        };
+        let script = async {
+            until(|| desktop.find("Alice").is_some()).await;
+            phone.modify(alice, |notification| {
+                notification.message = "hi!".to_string()
+            });
+            until(|| {
+                desktop
+                    .find("Alice")
+                    .is_some_and(|(_, shown)| shown.body == "hi!")
+            })
+            .await;
+            assert!(!grouped());
+
+            phone.add(FakeNotification::new("org.example", "Bob", "hey"));
+            until(grouped).await;
+        };
+        tokio::select! {
+            _ = daemon.serve(Arc::new(phone.clone()), control_rx, action_rx) => panic!("daemon stopped"),
+            _ = script => {}
+        }
+    }
+
+    // A group read on the phone takes its summary along, as does a new link
+    #[tokio::test]
+    async fn group_removed() {
+        let phone = FakePhone::new();
+        let desktop = FakeDesktop::default();
+        let mut daemon = daemon(desktop.clone());
+        daemon.coalescer = Coalescer::from_config(&CoalesceConfig {
+            window_secs: 60,
+            by_title: false,
+        });
+        let (control_tx, control_rx) = mpsc::channel(1);
+        let (_action_tx, action_rx) = mpsc::channel(1);
+        let summary = || {
+            desktop
+                .shown
+                .borrow()
+                .iter()
+                .find(|(_, shown)| shown.summary.contains("new messages"))
+                .map(|(id, _)| *id)
+        };
+        let script = async {
+            let alice = phone.add(FakeNotification::new("org.example", "Alice", "hi"));
+            let bob = phone.add(FakeNotification::new("org.example", "Bob", "hey"));
+            until(|| summary().is_some()).await;
+            phone.remove(alice);
+            phone.remove(bob);
+            until(|| summary().is_none()).await;
+
+            phone.add(FakeNotification::new("org.example", "Carol", "yo"));
+            phone.add(FakeNotification::new("org.example", "Dave", "hello"));
+            until(|| summary().is_some()).await;
+            let shown_id = summary().unwrap();
+            let (reply, response) = oneshot::channel();
+            let request = control::Request {
+                command: Command::Reconnect,
+                reply,
+            };
+            control_tx.send(request).await.unwrap();
+            assert_eq!(response.await.unwrap(), Ok(Value::Null));
+            // announced again, grouped under a fresh summary
+            until(|| summary().is_some_and(|id| id != shown_id)).await;
+            let summaries = desktop
+                .shown
+                .borrow()
+                .values()
+                .filter(|shown| shown.summary.contains("new messages"))
+                .count();
+            assert_eq!(summaries, 1);
+        };
+        tokio::select! {
+            _ = daemon.serve(Arc::new(phone.clone()), control_rx, action_rx) => panic!("daemon stopped"),
+            _ = script => {}
+        }
+    }
+
+    // Quiet hours that ended before the next tick still get their summary
+    #[tokio::test]
+    async fn dnd_ends() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join("ancs.toml");
+        let all_day = r#"
+[[dnd.schedule]]
+days = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]
+start = "00:00"
+end = "23:59"
+
+[[dnd.schedule]]
+days = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]
+start = "23:59"
+end = "00:00"
+"#;
+        std::fs::write(&config_path, all_day).unwrap();
+        let phone = FakePhone::new();
+
+        let desktop = FakeDesktop::default();
+        let mut daemon = daemon(desktop.clone());
+        daemon.dnd = DoNotDisturb::from_config(&reload(&config_path).unwrap().dnd).unwrap();
+        daemon.config_path = Some(config_path.clone());
+        let (control_tx, control_rx) = mpsc::channel(1);
+        let (_action_tx, action_rx) = mpsc::channel(1);
+        let send = |command| {
+            let control_tx = control_tx.clone();
+            async move {
+                let (reply, response) = oneshot::channel();
+                let request = control::Request { command, reply };
+                control_tx.send(request).await.unwrap();
+                response.await.unwrap()
+            }
+        };
+        let script = async {
+            phone.add(FakeNotification::new("org.example", "Alice", "Hi"));
+            let suppressed =
+                || async { send(Command::Status).await.unwrap()["dnd"]["suppressed"].clone() };
+            while suppressed().await != json!(1) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            assert!(desktop.find("Alice").is_none());
+
+            // the schedule no longer covers now, nothing re-evaluates it until Bob arrives
+            std::fs::write(&config_path, "").unwrap();
+            assert_eq!(send(Command::Reload).await, Ok(Value::Null));
+            phone.add(FakeNotification::new("org.example", "Bob", "Hey"));
+            until(|| desktop.find("Bob").is_some()).await;
+            let (_, summary) = desktop
+                .find("1 notifications while Do Not Disturb")
+                .unwrap();
+            assert_eq!(summary.body, "org.example: Alice");
+        };
+        tokio::select! {
+            _ = daemon.serve(Arc::new(phone.clone()), control_rx, action_rx) => panic!("daemon stopped"),
+            _ = script => {}
+        }
+    }
+
+    // A recorded session replays to the same desktop, however fast
+    #[tokio::test]
+    async fn replayed_trace() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("trace.jsonl");
+        let phone = FakePhone::new();
+        phone.set_fragment_size(Some(7));
+        phone.set_display_name("org.example", "Example");
+        phone.add(FakeNotification::new("org.example", "Alice", "Hi"));
+        let recording = RecordingTransport::create(phone.clone(), &path)
+            .await
+            .unwrap();
+        let recorded = FakeDesktop::default();
+        let (_control_tx, control_rx) = mpsc::channel(1);
+        let (_action_tx, action_rx) = mpsc::channel(1);
+        let script = async {
+            until(|| recorded.find("Alice").is_some()).await;
+            let bob = phone.add(FakeNotification::new("org.example", "Bob", "Hey"));
+            until(|| recorded.find("Bob").is_some()).await;
+            phone.modify(bob, |notification| {
+                notification.message = "Hey there".to_string()
+            });
+            until(|| recorded.find("Bob").unwrap().1.body == "Hey there").await;
+        };
+        tokio::select! {
+            _ = daemon(recorded.clone()).serve(Arc::new(recording), control_rx, action_rx) => panic!("daemon stopped"),
+            _ = script => {}
+        }
+
+        let replayed = FakeDesktop::default();
+        let replay = ReplayTransport::new(Trace::read(&path).unwrap(), Pace::Fast);
+        let (_control_tx, control_rx) = mpsc::channel(1);
+        let (_action_tx, action_rx) = mpsc::channel(1);
+        let shown =
+            |desktop: &FakeDesktop| desktop.shown.borrow().values().cloned().collect::<Vec<_>>();
+        tokio::select! {
+            _ = daemon(replayed.clone()).serve(Arc::new(replay), control_rx, action_rx) => panic!("daemon stopped"),
+            _ = until(|| replayed.find("Bob").is_some_and(|(_, bob)| bob.body == "Hey there")) => {}
+        }
+        assert_eq!(shown(&replayed), shown(&recorded));
+    }
+}
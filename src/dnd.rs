@@ -0,0 +1,303 @@
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ancs::control_point::CategoryID;
+
+// `[dnd]` section of ancs.toml
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DndConfig {
+    #[serde(default)]
+    pub allow_categories: Vec<String>,
+    #[serde(default)]
+    pub allow_apps: Vec<String>,
+    #[serde(default)]
+    pub schedule: Vec<ScheduleConfig>,
+}
+
+// `[[dnd.schedule]]` entry, e.g. days = ["mon", "tue"], start = "22:00", end = "07:00"
+#[derive(Clone, Debug, Deserialize)]
+pub struct ScheduleConfig {
+    pub days: Vec<String>,
+    pub start: String,
+    pub end: String,
+}
+
+// A quiet hours window. Windows where `end` is before `start` wrap past midnight and belong to
+// the day they start on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuietHours {
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn from_config(config: &ScheduleConfig) -> Result<Self, String> {
+        let days = config
+            .days
+            .iter()
+            .map(|day| Weekday::from_str(day).map_err(|_| format!("invalid weekday: {}", day)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let start = NaiveTime::parse_from_str(&config.start, "%H:%M")
+            .map_err(|_| format!("invalid start time: {}", config.start))?;
+        let end = NaiveTime::parse_from_str(&config.end, "%H:%M")
+            .map_err(|_| format!("invalid end time: {}", config.end))?;
+        Ok(Self { days, start, end })
+    }
+
+    pub fn contains(&self, now: NaiveDateTime) -> bool {
+        let time = now.time();
+        let today = now.weekday();
+        if self.start <= self.end {
+            self.days.contains(&today) && time >= self.start && time < self.end
+        } else {
+            (self.days.contains(&today) && time >= self.start)
+                || (self.days.contains(&today.pred()) && time < self.end)
+        }
+    }
+}
+
+// Notification held back while do-not-disturb was active
+#[derive(Clone, Debug)]
+pub struct Suppressed {
+    pub notification_id: u32,
+    pub app: String,
+    pub title: String,
+}
+
+// Do-not-disturb as reported over the control socket
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Status {
+    pub active: bool,
+    // "manual" while overridden, otherwise "schedule"
+    pub mode: &'static str,
+    pub suppressed: usize,
+}
+
+pub struct DoNotDisturb {
+    schedule: Vec<QuietHours>,
+    allow_categories: Vec<CategoryID>,
+    allow_apps: Vec<String>,
+    // manual override set over the control interface, `None` follows the schedule
+    manual: Option<bool>,
+    active: bool,
+    suppressed: Vec<Suppressed>,
+}
+
+impl DoNotDisturb {
+    pub fn from_config(config: &DndConfig) -> Result<Self, String> {
+        let schedule = config
+            .schedule
+            .iter()
+            .map(QuietHours::from_config)
+            .collect::<Result<Vec<_>, _>>()?;
+        let allow_categories = config
+            .allow_categories
+            .iter()
+            .map(|category| CategoryID::from_str(category))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            schedule,
+            allow_categories,
+            allow_apps: config.allow_apps.clone(),
+            manual: None,
+            active: false,
+            suppressed: Vec::new(),
+        })
+    }
+
+    // Applies a reloaded config, the manual override and what was held back are kept
+    pub fn reconfigure(&mut self, config: &DndConfig) -> Result<(), String> {
+        let reconfigured = Self::from_config(config)?;
+        self.schedule = reconfigured.schedule;
+        self.allow_categories = reconfigured.allow_categories;
+        self.allow_apps = reconfigured.allow_apps;
+        Ok(())
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            active: self.active,
+            mode: match self.manual {
+                Some(_) => "manual",
+                None => "schedule",
+            },
+            suppressed: self.suppressed.len(),
+        }
+    }
+
+    pub fn is_active(&self, now: NaiveDateTime) -> bool {
+        self.manual
+            .unwrap_or_else(|| self.schedule.iter().any(|window| window.contains(now)))
+    }
+
+    pub fn set_manual(&mut self, manual: Option<bool>) {
+        self.manual = manual;
+    }
+
+    // Re-evaluates the state and returns the suppressed notifications once do-not-disturb ends
+    pub fn update(&mut self, now: NaiveDateTime) -> Option<Vec<Suppressed>> {
+        let was_active = self.active;
+        self.active = self.is_active(now);
+        if was_active && !self.active && !self.suppressed.is_empty() {
+            return Some(self.suppressed.drain(..).collect());
+        }
+        None
+    }
+
+    pub fn suppresses(&self, category_id: u8, app_identifier: Option<&str>) -> bool {
+        if !self.active {
+            return false;
+        }
+        let allowed_category = self
+            .allow_categories
+            .iter()
+            .any(|category| *category as u8 == category_id);
+        let allowed_app = app_identifier
+            .map(|app| self.allow_apps.iter().any(|allowed| allowed == app))
+            .unwrap_or(false);
+        !(allowed_category || allowed_app)
+    }
+
+    pub fn suppress(&mut self, suppressed: Suppressed) {
+        self.suppressed
+            .retain(|s| s.notification_id != suppressed.notification_id);
+        self.suppressed.push(suppressed);
+    }
+
+    pub fn remove(&mut self, notification_id: u32) {
+        self.suppressed
+            .retain(|s| s.notification_id != notification_id);
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dnd: {} ({}), {} suppressed",
+            if self.active { "on" } else { "off" },
+            self.mode,
+            self.suppressed
+        )
+    }
+}
+
+// Body of the desktop notification shown when do-not-disturb ends
+pub fn summary(suppressed: &[Suppressed]) -> String {
+    suppressed
+        .iter()
+        .map(|s| format!("{}: {}", s.app, s.title))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // 2024-01-01 was a Monday
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn config() -> DndConfig {
+        DndConfig {
+            allow_categories: vec!["IncomingCall".to_string()],
+            allow_apps: vec!["com.apple.MobileSMS".to_string()],
+            schedule: vec![ScheduleConfig {
+                days: vec!["mon".to_string(), "fri".to_string()],
+                start: "22:00".to_string(),
+                end: "07:00".to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn overnight_window() {
+        let dnd = DoNotDisturb::from_config(&config()).unwrap();
+        assert!(!dnd.is_active(at(1, 21, 59)));
+        assert!(dnd.is_active(at(1, 22, 0)));
+        assert!(dnd.is_active(at(2, 6, 59)));
+        assert!(!dnd.is_active(at(2, 7, 0)));
+        assert!(!dnd.is_active(at(2, 23, 0)));
+        // friday night runs into saturday morning
+        assert!(dnd.is_active(at(6, 3, 0)));
+    }
+
+    #[test]
+    fn manual_override() {
+        let mut dnd = DoNotDisturb::from_config(&config()).unwrap();
+        dnd.set_manual(Some(true));
+        assert!(dnd.is_active(at(2, 12, 0)));
+        dnd.set_manual(Some(false));
+        assert!(!dnd.is_active(at(1, 23, 0)));
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut dnd = DoNotDisturb::from_config(&config()).unwrap();
+        dnd.update(at(1, 23, 0));
+        assert!(dnd.suppresses(CategoryID::Social as u8, Some("org.whispersystems.signal")));
+        assert!(!dnd.suppresses(CategoryID::IncomingCall as u8, None));
+        assert!(!dnd.suppresses(CategoryID::Social as u8, Some("com.apple.MobileSMS")));
+    }
+
+    #[test]
+    fn summary_on_end() {
+        let mut dnd = DoNotDisturb::from_config(&config()).unwrap();
+        assert!(dnd.update(at(1, 23, 0)).is_none());
+        dnd.suppress(Suppressed {
+            notification_id: 1,
+            app: "Signal".to_string(),
+            title: "Alice".to_string(),
+        });
+        assert!(dnd.update(at(2, 6, 0)).is_none());
+        let suppressed = dnd.update(at(2, 8, 0)).unwrap();
+        assert_eq!(summary(&suppressed), "Signal: Alice");
+        assert!(dnd.update(at(2, 9, 0)).is_none());
+    }
+
+    #[test]
+    fn reconfigure() {
+        let mut dnd = DoNotDisturb::from_config(&DndConfig::default()).unwrap();
+        dnd.set_manual(Some(true));
+        dnd.update(at(2, 12, 0));
+        dnd.suppress(Suppressed {
+            notification_id: 1,
+            app: "Signal".to_string(),
+            title: "Alice".to_string(),
+        });
+        dnd.reconfigure(&config()).unwrap();
+        assert!(!dnd.suppresses(CategoryID::IncomingCall as u8, None));
+        assert_eq!(
+            dnd.status(),
+            Status {
+                active: true,
+                mode: "manual",
+                suppressed: 1
+            }
+        );
+        assert_eq!(dnd.status().to_string(), "dnd: on (manual), 1 suppressed");
+        let mut invalid = config();
+        invalid.allow_categories.push("Nonsense".to_string());
+        assert!(dnd.reconfigure(&invalid).is_err());
+    }
+
+    #[test]
+    fn invalid_config() {
+        let mut config = config();
+        config.schedule[0].start = "25:00".to_string();
+        assert!(DoNotDisturb::from_config(&config).is_err());
+        let mut config = self::config();
+        config.allow_categories.push("Nonsense".to_string());
+        assert!(DoNotDisturb::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use chrono::{DateTime, Local};
+use log::{debug, log_enabled, Level};
+use serde::{Serialize, Serializer};
+use tokio::sync::mpsc;
+
+use std::sync::{Arc, Mutex};
+
+use crate::ancs::control_point::{category_name, event_flag_names, EventID};
+use crate::ancs::data_source::NotificationAttributes;
+use crate::ancs::notification_source::NotificationEvent;
+
+// A notification as front-ends see it once its attributes are in
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Notification {
+    pub notification_id: u32,
+    pub app_identifier: Option<String>,
+    // the app's display name, once the phone told us
+    pub app_name: Option<String>,
+    #[serde(rename = "category", serialize_with = "category")]
+    pub category_id: u8,
+    #[serde(rename = "flags", serialize_with = "flags")]
+    pub event_flags: u8,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub message: Option<String>,
+    pub message_truncated: bool,
+    pub date: Option<DateTime<Local>>,
+    pub positive_action_label: Option<String>,
+    pub negative_action_label: Option<String>,
+}
+
+// A Notification Source event as the phone sent it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Source {
+    pub notification_id: u32,
+    #[serde(rename = "category", serialize_with = "category")]
+    pub category_id: u8,
+    pub category_count: u8,
+    #[serde(rename = "flags", serialize_with = "flags")]
+    pub event_flags: u8,
+}
+
+impl From<&NotificationEvent> for Source {
+    fn from(event: &NotificationEvent) -> Self {
+        Self {
+            notification_id: event.notification_id,
+            category_id: event.category_id,
+            category_count: event.category_count,
+            event_flags: event.event_flags,
+        }
+    }
+}
+
+// Notification attributes as the phone answered them, with the date in local time
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Attributes {
+    pub notification_id: u32,
+    pub app_identifier: Option<String>,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub message: Option<String>,
+    pub message_size: Option<u16>,
+    pub message_truncated: bool,
+    pub date: Option<DateTime<Local>>,
+    pub positive_action_label: Option<String>,
+    pub negative_action_label: Option<String>,
+}
+
+impl Attributes {
+    pub fn new(attributes: &NotificationAttributes, date: Option<DateTime<Local>>) -> Self {
+        Self {
+            notification_id: attributes.notification_id,
+            app_identifier: attributes.app_identifier.clone(),
+            title: attributes.title.clone(),
+            subtitle: attributes.subtitle.clone(),
+            message: attributes.message.clone(),
+            message_size: attributes.message_size,
+            message_truncated: attributes.message_truncated(),
+            date,
+            positive_action_label: attributes.positive_action_label.clone(),
+            negative_action_label: attributes.negative_action_label.clone(),
+        }
+    }
+}
+
+fn category<S: Serializer>(category_id: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&category_name(*category_id))
+}
+
+fn flags<S: Serializer>(event_flags: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    event_flag_names(*event_flags).serialize(serializer)
+}
+
+// What the daemon tells front-ends such as the D-Bus service and `ancs run --json` about, one
+// JSON object per event tagged with `event`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    // the phone announced a notification
+    Added(Source),
+    // the phone changed a notification, its attributes are requested again
+    Modified(Source),
+    Removed {
+        notification_id: u32,
+    },
+    Attributes(Attributes),
+    AppAttributes {
+        app_identifier: String,
+        display_name: Option<String>,
+    },
+    // a notification whose attributes arrived, for the first time or again after a change
+    Notification(Notification),
+    // the phone's answer to a positive or negative action
+    Action {
+        notification_id: u32,
+        action: &'static str,
+        accepted: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Connection {
+        connected: bool,
+    },
+}
+
+impl Event {
+    // A Notification Source event, `None` for event ids newer than this implementation
+    pub fn from_source(event: &NotificationEvent) -> Option<Self> {
+        match event.event_id {
+            id if id == EventID::NotificationAdded as u8 => Some(Event::Added(Source::from(event))),
+            id if id == EventID::NotificationModified as u8 => {
+                Some(Event::Modified(Source::from(event)))
+            }
+            id if id == EventID::NotificationRemoved as u8 => Some(Event::Removed {
+                notification_id: event.notification_id,
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Every subscriber gets every event in order, subscribers that went away are dropped. Clones
+// share their subscribers, so e.g. control clients can subscribe while the daemon runs.
+#[derive(Clone, Default)]
+pub struct Events {
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<Event>>>>,
+}
+
+impl Events {
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<Event> {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().push(events_tx);
+        events_rx
+    }
+
+    pub fn publish(&self, event: Event) {
+        if log_enabled!(Level::Debug) {
+            if let Ok(json) = serde_json::to_string(&event) {
+                debug!("{}", json);
+            }
+        }
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::control_point::{CategoryID, EventFlag};
+    use serde_json::json;
+
+    #[test]
+    fn json() {
+        let event = NotificationEvent {
+            event_id: EventID::NotificationAdded as u8,
+            event_flags: EventFlag::Important as u8 | EventFlag::PositiveAction as u8,
+            category_id: CategoryID::IncomingCall as u8,
+            category_count: 1,
+            notification_id: 7,
+        };
+        assert!(Event::from_source(&NotificationEvent {
+            event_id: 3,
+            ..event.clone()
+        })
+        .is_none());
+        assert_eq!(
+            serde_json::to_value(Event::from_source(&event).unwrap()).unwrap(),
+            json!({
+                "event": "added",
+                "notification_id": 7,
+                "category": "IncomingCall",
+                "category_count": 1,
+                "flags": ["Important", "PositiveAction"],
+            })
+        );
+        let action = Event::Action {
+            notification_id: 7,
+            action: "negative",
+            accepted: true,
+            error: None,
+        };
+        assert_eq!(
+            serde_json::to_value(action).unwrap(),
+            json!({"event": "action", "notification_id": 7, "action": "negative", "accepted": true})
+        );
+        assert_eq!(
+            serde_json::to_value(Event::Connection { connected: false }).unwrap(),
+            json!({"event": "connection", "connected": false})
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use chrono::NaiveDate;
+use clap::ValueEnum;
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::ancs::control_point::category_name;
+use crate::history::Entry;
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    Json,
+    Csv,
+    Markdown,
+}
+
+// Replaces message bodies, titles are kept so the export stays useful for auditing
+pub fn redact(entries: &mut [Entry]) {
+    for entry in entries {
+        if entry.message.is_some() {
+            entry.message = Some(REDACTED.to_string());
+        }
+    }
+}
+
+pub fn write<W: Write>(out: &mut W, entries: &[Entry], format: Format) -> io::Result<()> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, entries)?;
+            writeln!(out)
+        }
+        Format::Csv => write_csv(out, entries),
+        Format::Markdown => write_markdown(out, entries),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_csv<W: Write>(out: &mut W, entries: &[Entry]) -> io::Result<()> {
+    writeln!(
+        out,
+        "id,notification_id,app_identifier,category,event_flags,title,subtitle,message,message_size,date,positive_action_label,negative_action_label,arrived_at,removed_at,actions"
+    )?;
+    for entry in entries {
+        let optional = |value: &Option<String>| csv_field(value.as_deref().unwrap_or_default());
+        let actions = entry
+            .actions
+            .iter()
+            .map(|action| action.action.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            entry.id,
+            entry.notification_id,
+            optional(&entry.app_identifier),
+            category_name(entry.category_id),
+            entry.event_flags,
+            optional(&entry.title),
+            optional(&entry.subtitle),
+            optional(&entry.message),
+            entry
+                .message_size
+                .map(|size| size.to_string())
+                .unwrap_or_default(),
+            entry.date.map(|date| date.to_rfc3339()).unwrap_or_default(),
+            optional(&entry.positive_action_label),
+            optional(&entry.negative_action_label),
+            entry.arrived_at.to_rfc3339(),
+            entry
+                .removed_at
+                .map(|removed_at| removed_at.to_rfc3339())
+                .unwrap_or_default(),
+            csv_field(&actions)
+        )?;
+    }
+    Ok(())
+}
+
+// Digest grouped by day, then by app
+fn write_markdown<W: Write>(out: &mut W, entries: &[Entry]) -> io::Result<()> {
+    let mut days: BTreeMap<NaiveDate, BTreeMap<&str, Vec<&Entry>>> = BTreeMap::new();
+    for entry in entries {
+        days.entry(entry.received_at().date_naive())
+            .or_default()
+            .entry(entry.app_identifier.as_deref().unwrap_or("unknown"))
+            .or_default()
+            .push(entry);
+    }
+
+    writeln!(out, "# Notifications")?;
+    for (day, apps) in days {
+        writeln!(out)?;
+        writeln!(out, "## {}", day.format("%A, %Y-%m-%d"))?;
+        for (app, entries) in apps {
+            writeln!(out)?;
+            writeln!(out, "### {}", app)?;
+            writeln!(out)?;
+            for entry in entries {
+                let mut line = format!("- {}", entry.received_at().format("%H:%M"));
+                if let Some(title) = &entry.title {
+                    line += &format!(" **{}**", title);
+                }
+                if let Some(subtitle) = &entry.subtitle {
+                    line += &format!(" _{}_", subtitle);
+                }
+                if let Some(message) = &entry.message {
+                    line += &format!(": {}", message.replace('\n', " "));
+                }
+                writeln!(out, "{}", line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    fn entries() -> Vec<Entry> {
+        let entry = |id: u64, app: &str, day: u32, title: &str, message: &str| Entry {
+            id,
+            notification_id: id as u32,
+            app_identifier: Some(app.to_string()),
+            category_id: 4,
+            event_flags: 0,
+            title: Some(title.to_string()),
+            subtitle: None,
+            message: Some(message.to_string()),
+            message_size: None,
+            date: None,
+            positive_action_label: None,
+            negative_action_label: None,
+            arrived_at: Local.with_ymd_and_hms(2024, 1, day, 9, 30, 0).unwrap(),
+            removed_at: None,
+            actions: Vec::new(),
+        };
+        vec![
+            entry(0, "org.signal", 1, "Alice", "hi, \"there\""),
+            entry(1, "com.mail", 1, "Bob", "meeting"),
+            entry(2, "org.signal", 2, "Carol", "line\nbreak"),
+        ]
+    }
+
+    #[test]
+    fn csv() {
+        let mut out = Vec::new();
+        write(&mut out, &entries(), Format::Csv).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("id,notification_id,app_identifier,category"));
+        assert!(lines[1].starts_with("0,0,org.signal,Social,0,Alice,,\"hi, \"\"there\"\"\","));
+        // quoted newline keeps the record together
+        assert_eq!(lines[3], "2,2,org.signal,Social,0,Carol,,\"line");
+    }
+
+    #[test]
+    fn markdown() {
+        let mut out = Vec::new();
+        write(&mut out, &entries(), Format::Markdown).unwrap();
+        let markdown = String::from_utf8(out).unwrap();
+        assert_eq!(
+            markdown,
+            "# Notifications\n\n\
+             ## Monday, 2024-01-01\n\n\
+             ### com.mail\n\n\
+             - 09:30 **Bob**: meeting\n\n\
+             ### org.signal\n\n\
+             - 09:30 **Alice**: hi, \"there\"\n\n\
+             ## Tuesday, 2024-01-02\n\n\
+             ### org.signal\n\n\
+             - 09:30 **Carol**: line break\n"
+        );
+    }
+
+    #[test]
+    fn redacted_json() {
+        let mut entries = entries();
+        redact(&mut entries);
+        let mut out = Vec::new();
+        write(&mut out, &entries, Format::Json).unwrap();
+        let json: Vec<Entry> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json.len(), 3);
+        assert!(json.iter().all(|e| e.message.as_deref() == Some(REDACTED)));
+        assert_eq!(json[0].title.as_deref(), Some("Alice"));
+    }
+}
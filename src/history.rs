@@ -0,0 +1,564 @@
+use chrono::{DateTime, Duration, Local};
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::ancs::control_point::EventFlag;
+use crate::ancs::data_source::{self, NotificationAttributes};
+use crate::ancs::date::{self, PhoneClock};
+use crate::ancs::notification_source::NotificationEvent;
+
+// `[history]` section of ancs.toml
+#[derive(Clone, Debug, Deserialize)]
+pub struct HistoryConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: i64,
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_max_age_days() -> i64 {
+    30
+}
+
+fn default_max_entries() -> usize {
+    5000
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            max_age_days: default_max_age_days(),
+            max_entries: default_max_entries(),
+        }
+    }
+}
+
+// Something done with a notification, e.g. held back by do-not-disturb
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Action {
+    pub action: String,
+    pub at: DateTime<Local>,
+}
+
+// One notification as seen by the daemon. ANCS notification ids are only unique for a
+// connection, `id` identifies the entry across restarts.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u64,
+    pub notification_id: u32,
+    pub app_identifier: Option<String>,
+    pub category_id: u8,
+    pub event_flags: u8,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub message: Option<String>,
+    #[serde(default)]
+    pub message_size: Option<u16>,
+    // when the phone received the notification
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub date: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub positive_action_label: Option<String>,
+    #[serde(default)]
+    pub negative_action_label: Option<String>,
+    pub arrived_at: DateTime<Local>,
+    pub removed_at: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+}
+
+impl Entry {
+    pub fn message_truncated(&self) -> bool {
+        data_source::message_truncated(self.message.as_deref(), self.message_size)
+    }
+
+    // Time the phone received the notification, or when we did if the phone did not say
+    pub fn received_at(&self) -> DateTime<Local> {
+        self.date.unwrap_or(self.arrived_at)
+    }
+}
+
+// Dates used to be stored as the raw ANCS string
+fn deserialize_date<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTime<Local>>, D::Error> {
+    let date: Option<String> = Option::deserialize(deserializer)?;
+    Ok(date.and_then(|date| {
+        DateTime::parse_from_rfc3339(&date)
+            .map(|date| date.with_timezone(&Local))
+            .ok()
+            .or_else(|| date::parse(&date).and_then(|date| PhoneClock::default().to_local(date)))
+    }))
+}
+
+// Filters for history queries, unset fields match everything
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    // case-insensitive search in title, subtitle and message
+    pub text: Option<String>,
+    pub app: Option<String>,
+    pub category_id: Option<u8>,
+    pub since: Option<DateTime<Local>>,
+    pub until: Option<DateTime<Local>>,
+}
+
+impl Query {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        if let Some(text) = &self.text {
+            let text = text.to_lowercase();
+            let found = [&entry.title, &entry.subtitle, &entry.message]
+                .iter()
+                .filter_map(|field| field.as_deref())
+                .any(|field| field.to_lowercase().contains(&text));
+            if !found {
+                return false;
+            }
+        }
+        if let Some(app) = &self.app {
+            let app = app.to_lowercase();
+            let found = entry
+                .app_identifier
+                .as_deref()
+                .is_some_and(|identifier| identifier.to_lowercase().contains(&app));
+            if !found {
+                return false;
+            }
+        }
+        if self
+            .category_id
+            .is_some_and(|category_id| category_id != entry.category_id)
+        {
+            return false;
+        }
+        if self.since.is_some_and(|since| entry.received_at() < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| entry.received_at() > until) {
+            return false;
+        }
+        true
+    }
+}
+
+// Append-only JSON lines store. Every change appends the full entry, the last line for an id
+// wins, and the file is rewritten with only the current entries when it is compacted.
+pub struct History {
+    path: PathBuf,
+    config: HistoryConfig,
+    file: File,
+    entries: BTreeMap<u64, Entry>,
+    // entries of notifications that are still on the phone
+    active: HashMap<u32, u64>,
+    next_id: u64,
+    lines: usize,
+}
+
+impl History {
+    pub fn default_path(xdg_dirs: &xdg::BaseDirectories) -> io::Result<PathBuf> {
+        xdg_dirs.place_data_file("history.jsonl")
+    }
+
+    // Reads the entries stored at `path`, in the order the phone received them
+    pub fn load(path: &Path) -> io::Result<Vec<Entry>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = BTreeMap::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Entry>(&line) {
+                Ok(entry) => {
+                    entries.insert(entry.id, entry);
+                }
+                // most likely a line cut short by a crash
+                Err(e) => warn!("Skipping history line {}: {}", number + 1, e),
+            }
+        }
+        let mut entries: Vec<Entry> = entries.into_values().collect();
+        entries.sort_by_key(|entry| (entry.received_at(), entry.id));
+        Ok(entries)
+    }
+
+    pub fn open(path: PathBuf, config: HistoryConfig) -> io::Result<Self> {
+        let entries: BTreeMap<u64, Entry> = Self::load(&path)?
+            .into_iter()
+            .map(|entry| (entry.id, entry))
+            .collect();
+        let next_id = entries.keys().next_back().map_or(0, |id| id + 1);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut history = Self {
+            path,
+            config,
+            file,
+            entries,
+            active: HashMap::new(),
+            next_id,
+            lines: 0,
+        };
+        history.compact(Local::now())?;
+        Ok(history)
+    }
+
+    pub fn arrived(&mut self, event: &NotificationEvent) -> io::Result<()> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let entry = Entry {
+            id,
+            notification_id: event.notification_id,
+            app_identifier: None,
+            category_id: event.category_id,
+            event_flags: event.event_flags,
+            title: None,
+            subtitle: None,
+            message: None,
+            message_size: None,
+            date: None,
+            positive_action_label: None,
+            negative_action_label: None,
+            arrived_at: Local::now(),
+            removed_at: None,
+            actions: Vec::new(),
+        };
+        self.active.insert(event.notification_id, id);
+        self.entries.insert(id, entry);
+        // announcements after a reconnect are only written once they turn out to be new
+        if EventFlag::PreExisting.is_set(event.event_flags) {
+            return Ok(());
+        }
+        self.write(id)
+    }
+
+    // `date` is the attributes' date converted with the phone's clock
+    pub fn updated(
+        &mut self,
+        attributes: &NotificationAttributes,
+        date: Option<DateTime<Local>>,
+    ) -> io::Result<()> {
+        let Some(&id) = self.active.get(&attributes.notification_id) else {
+            return Ok(());
+        };
+        let Some(entry) = self.entries.get_mut(&id) else {
+            return Ok(());
+        };
+        if attributes.app_identifier.is_some() {
+            entry.app_identifier = attributes.app_identifier.clone();
+        }
+        if attributes.title.is_some() {
+            entry.title = attributes.title.clone();
+        }
+        if attributes.subtitle.is_some() {
+            entry.subtitle = attributes.subtitle.clone();
+        }
+        if attributes.message.is_some() {
+            entry.message = attributes.message.clone();
+        }
+        if attributes.message_size.is_some() {
+            entry.message_size = attributes.message_size;
+        }
+        if date.is_some() {
+            entry.date = date;
+        }
+        if attributes.positive_action_label.is_some() {
+            entry.positive_action_label = attributes.positive_action_label.clone();
+        }
+        if attributes.negative_action_label.is_some() {
+            entry.negative_action_label = attributes.negative_action_label.clone();
+        }
+
+        // the phone announces everything again after a reconnect, keep the earlier entry
+        if EventFlag::PreExisting.is_set(entry.event_flags) {
+            let entry = entry.clone();
+            let earlier = self
+                .entries
+                .values()
+                .find(|earlier| {
+                    earlier.id != entry.id
+                        && earlier.removed_at.is_none()
+                        && earlier.notification_id == entry.notification_id
+                        && earlier.app_identifier == entry.app_identifier
+                        && earlier.title == entry.title
+                        && earlier.date == entry.date
+                })
+                .map(|earlier| earlier.id);
+            if let Some(earlier) = earlier {
+                self.entries.remove(&id);
+                self.active.insert(entry.notification_id, earlier);
+                return Ok(());
+            }
+        }
+        self.write(id)
+    }
+
+    pub fn action(&mut self, notification_id: u32, action: &str) -> io::Result<()> {
+        self.modify(notification_id, |entry| {
+            entry.actions.push(Action {
+                action: action.to_string(),
+                at: Local::now(),
+            })
+        })
+    }
+
+    pub fn removed(&mut self, notification_id: u32) -> io::Result<()> {
+        self.modify(notification_id, |entry| {
+            entry.removed_at = Some(Local::now())
+        })?;
+        self.active.remove(&notification_id);
+        Ok(())
+    }
+
+    fn modify<F: FnOnce(&mut Entry)>(&mut self, notification_id: u32, f: F) -> io::Result<()> {
+        let Some(&id) = self.active.get(&notification_id) else {
+            return Ok(());
+        };
+        if let Some(entry) = self.entries.get_mut(&id) {
+            f(entry);
+            self.write(id)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, id: u64) -> io::Result<()> {
+        let line = serde_json::to_string(&self.entries[&id])?;
+        writeln!(self.file, "{}", line)?;
+        self.lines += 1;
+        // superseded lines pile up, rewrite once they outnumber the entries
+        if self.lines > self.entries.len().max(1000) {
+            self.compact(Local::now())?;
+        }
+        Ok(())
+    }
+
+    // Applies the retention limits and rewrites the file with the remaining entries
+    pub fn compact(&mut self, now: DateTime<Local>) -> io::Result<()> {
+        let oldest = now - Duration::days(self.config.max_age_days);
+        self.entries.retain(|_, entry| entry.arrived_at >= oldest);
+        while self.entries.len() > self.config.max_entries {
+            self.entries.pop_first();
+        }
+
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        for entry in self.entries.values() {
+            writeln!(tmp, "{}", serde_json::to_string(entry)?)?;
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.lines = self.entries.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(notification_id: u32, event_flags: u8) -> NotificationEvent {
+        NotificationEvent {
+            event_id: 0,
+            event_flags,
+            category_id: 4,
+            category_count: 1,
+            notification_id,
+        }
+    }
+
+    fn attributes(notification_id: u32, title: &str) -> NotificationAttributes {
+        NotificationAttributes {
+            notification_id,
+            app_identifier: Some("org.whispersystems.signal".to_string()),
+            title: Some(title.to_string()),
+            subtitle: None,
+            message: Some("hello".to_string()),
+            message_size: None,
+            date: date::parse("20240101T120000"),
+            positive_action_label: None,
+            negative_action_label: None,
+        }
+    }
+
+    fn received() -> Option<DateTime<Local>> {
+        PhoneClock::default().to_local(date::parse("20240101T120000").unwrap())
+    }
+
+    #[test]
+    fn persists_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+
+        let mut history = History::open(path.clone(), HistoryConfig::default()).unwrap();
+        history.arrived(&event(1, 0)).unwrap();
+        history.updated(&attributes(1, "Alice"), None).unwrap();
+        history.action(1, "positive").unwrap();
+        history.removed(1).unwrap();
+        history.arrived(&event(1, 0)).unwrap();
+        drop(history);
+
+        let entries = History::load(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title.as_deref(), Some("Alice"));
+        assert_eq!(entries[0].actions[0].action, "positive");
+        assert!(entries[0].removed_at.is_some());
+        assert!(entries[1].removed_at.is_none());
+
+        // reopening compacts the superseded lines away
+        History::open(path.clone(), HistoryConfig::default()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn pre_existing_notifications_are_not_duplicated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+
+        let mut history = History::open(path.clone(), HistoryConfig::default()).unwrap();
+        history.arrived(&event(7, 0)).unwrap();
+        history
+            .updated(&attributes(7, "Alice"), received())
+            .unwrap();
+        drop(history);
+
+        let mut history = History::open(path.clone(), HistoryConfig::default()).unwrap();
+        history
+            .arrived(&event(7, EventFlag::PreExisting as u8))
+            .unwrap();
+        history
+            .updated(&attributes(7, "Alice"), received())
+            .unwrap();
+        history.removed(7).unwrap();
+        drop(history);
+
+        let entries = History::load(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].removed_at.is_some());
+    }
+
+    #[test]
+    fn query() {
+        let now = Local::now();
+        let entry = Entry {
+            id: 0,
+            notification_id: 1,
+            app_identifier: Some("org.whispersystems.signal".to_string()),
+            category_id: 4,
+            event_flags: 0,
+            title: Some("Bank".to_string()),
+            subtitle: None,
+            message: Some("Your code is 123456".to_string()),
+            message_size: None,
+            date: None,
+            positive_action_label: None,
+            negative_action_label: None,
+            arrived_at: now - Duration::minutes(30),
+            removed_at: None,
+            actions: Vec::new(),
+        };
+
+        assert!(Query::default().matches(&entry));
+        let query = Query {
+            text: Some("CODE".to_string()),
+            app: Some("signal".to_string()),
+            category_id: Some(4),
+            since: Some(now - Duration::hours(1)),
+            until: Some(now),
+        };
+        assert!(query.matches(&entry));
+        for query in [
+            Query {
+                text: Some("password".to_string()),
+                ..Default::default()
+            },
+            Query {
+                app: Some("whatsapp".to_string()),
+                ..Default::default()
+            },
+            Query {
+                category_id: Some(6),
+                ..Default::default()
+            },
+            Query {
+                since: Some(now - Duration::minutes(10)),
+                ..Default::default()
+            },
+            Query {
+                until: Some(now - Duration::hours(1)),
+                ..Default::default()
+            },
+        ] {
+            assert!(!query.matches(&entry), "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn ordered_by_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+
+        let mut history = History::open(path.clone(), HistoryConfig::default()).unwrap();
+        history.arrived(&event(1, 0)).unwrap();
+        history.updated(&attributes(1, "Today"), None).unwrap();
+        history.arrived(&event(2, 0)).unwrap();
+        history
+            .updated(&attributes(2, "Earlier"), received())
+            .unwrap();
+        drop(history);
+
+        let entries = History::load(&path).unwrap();
+        assert_eq!(entries[0].title.as_deref(), Some("Earlier"));
+        assert_eq!(entries[0].received_at(), received().unwrap());
+        assert_eq!(entries[1].received_at(), entries[1].arrived_at);
+    }
+
+    #[test]
+    fn legacy_raw_date() {
+        let line = r#"{"id":0,"notification_id":1,"app_identifier":null,"category_id":0,"event_flags":0,"title":null,"subtitle":null,"message":null,"date":"20240101T120000","arrived_at":"2024-01-01T12:00:05+00:00","removed_at":null,"actions":[]}"#;
+        let entry: Entry = serde_json::from_str(line).unwrap();
+        assert_eq!(entry.date, received());
+        let line = line.replace("20240101T120000", "garbage");
+        let entry: Entry = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry.date, None);
+    }
+
+    #[test]
+    fn retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let config = HistoryConfig {
+            enabled: true,
+            max_age_days: 7,
+            max_entries: 2,
+        };
+
+        let mut history = History::open(path.clone(), config).unwrap();
+        for id in 0..3 {
+            history.arrived(&event(id, 0)).unwrap();
+        }
+        history.compact(Local::now()).unwrap();
+        let ids: Vec<u32> = History::load(&path)
+            .unwrap()
+            .iter()
+            .map(|e| e.notification_id)
+            .collect();
+        assert_eq!(ids, vec![1, 2]);
+
+        history.compact(Local::now() + Duration::days(8)).unwrap();
+        assert!(History::load(&path).unwrap().is_empty());
+    }
+}
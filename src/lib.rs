@@ -0,0 +1,19 @@
+// Shared by the `ancs` daemon and the `ancs-sim` simulator
+pub mod ancs;
+pub mod anonymize;
+pub mod attributes;
+pub mod btsnoop;
+pub mod cli;
+pub mod coalesce;
+pub mod conformance;
+pub mod control;
+pub mod daemon;
+pub mod dnd;
+pub mod events;
+pub mod export;
+pub mod history;
+pub mod notify;
+pub mod scenario;
+pub mod service;
+pub mod timeline;
+pub mod utils;
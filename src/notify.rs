@@ -0,0 +1,162 @@
+use dbus::arg::PropMap;
+use dbus::message::MatchRule;
+use dbus::nonblock::{MsgMatch, Proxy, SyncConnection};
+use log::error;
+use tokio::sync::mpsc;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Desktop notifications go straight to the org.freedesktop.Notifications service, the libnotify
+// bindings cannot attach actions to a notification
+
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const APP_NAME: &str = "ancs";
+
+// Action offered on notifications whose message was cut at the requested maximum length
+pub const FULL_MESSAGE_ACTION: &str = "full-message";
+
+// An action the user clicked on one of our desktop notifications
+#[derive(Clone, Debug)]
+pub struct ActionInvoked {
+    pub desktop_id: u32,
+    pub action: String,
+}
+
+// Where notifications end up, the notification service or a fake in tests
+pub trait Desktop {
+    type Error: fmt::Display;
+
+    // Shows a notification, or updates the one with `replaces_id` if it is not 0, and returns
+    // its id. `actions` are pairs of action key and label.
+    fn notify(
+        &self,
+        replaces_id: u32,
+        summary: &str,
+        body: &str,
+        actions: &[(&str, &str)],
+    ) -> impl Future<Output = Result<u32, Self::Error>>;
+
+    fn close(&self, id: u32) -> impl Future<Output = Result<(), Self::Error>>;
+
+    // Shows a one-off desktop notification that is not backed by an ANCS notification
+    fn show(&self, summary: &str, body: &str) -> impl Future<Output = ()> {
+        async move {
+            if let Err(e) = self.notify(0, summary, body, &[]).await {
+                error!("Cannot show notification: {}", e);
+            }
+        }
+    }
+}
+
+pub struct Notifier {
+    proxy: Proxy<'static, Arc<SyncConnection>>,
+    _action_match: MsgMatch,
+}
+
+impl Notifier {
+    pub async fn connect(action_tx: mpsc::Sender<ActionInvoked>) -> Result<Self, dbus::Error> {
+        let (resource, connection) = dbus_tokio::connection::new_session_sync()?;
+        tokio::spawn(async {
+            let e = resource.await;
+            error!("Lost connection to the session bus: {}", e);
+        });
+
+        let rule = MatchRule::new_signal(NOTIFICATIONS_NAME, "ActionInvoked");
+        let action_match =
+            connection
+                .add_match(rule)
+                .await?
+                .cb(move |_, (desktop_id, action): (u32, String)| {
+                    let _ = action_tx.try_send(ActionInvoked { desktop_id, action });
+                    true
+                });
+
+        Ok(Self {
+            proxy: Proxy::new(
+                NOTIFICATIONS_NAME,
+                NOTIFICATIONS_PATH,
+                Duration::from_secs(5),
+                connection,
+            ),
+            _action_match: action_match,
+        })
+    }
+}
+
+impl Desktop for Notifier {
+    type Error = dbus::Error;
+
+    async fn notify(
+        &self,
+        replaces_id: u32,
+        summary: &str,
+        body: &str,
+        actions: &[(&str, &str)],
+    ) -> Result<u32, dbus::Error> {
+        let actions: Vec<&str> = actions
+            .iter()
+            .flat_map(|(key, label)| [*key, *label])
+            .collect();
+        let (id,): (u32,) = self
+            .proxy
+            .method_call(
+                NOTIFICATIONS_NAME,
+                "Notify",
+                (
+                    APP_NAME,
+                    replaces_id,
+                    "",
+                    summary,
+                    body,
+                    actions,
+                    PropMap::new(),
+                    -1i32,
+                ),
+            )
+            .await?;
+        Ok(id)
+    }
+
+    async fn close(&self, id: u32) -> Result<(), dbus::Error> {
+        self.proxy
+            .method_call(NOTIFICATIONS_NAME, "CloseNotification", (id,))
+            .await
+    }
+}
+
+// Summary notifications for coalesced bursts, updated in place while the group lasts
+#[derive(Default)]
+pub struct Groups {
+    ids: HashMap<u32, u32>,
+}
+
+impl Groups {
+    pub async fn show(&mut self, desktop: &impl Desktop, group: u32, summary: &str, body: &str) {
+        let replaces_id = self.ids.get(&group).copied().unwrap_or(0);
+        match desktop.notify(replaces_id, summary, body, &[]).await {
+            Ok(id) => {
+                self.ids.insert(group, id);
+            }
+            Err(e) => error!("Cannot show group notification: {}", e),
+        }
+    }
+
+    // The group's notifications are all gone from the phone
+    pub async fn close(&mut self, desktop: &impl Desktop, group: u32) {
+        if let Some(id) = self.ids.remove(&group) {
+            let _ = desktop.close(id).await;
+        }
+    }
+
+    // Closes every summary, e.g. once the coalescer that kept their groups is replaced
+    pub async fn close_all(&mut self, desktop: &impl Desktop) {
+        for (_, id) in self.ids.drain() {
+            let _ = desktop.close(id).await;
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use chrono::Local;
+use config::Config;
+use log::info;
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::ancs::control_point::{CategoryID, EventFlag};
+use crate::ancs::fake_phone::{FakeNotification, FakePhone};
+use crate::ancs::transport::TransportError;
+
+// A scripted session for `ancs-sim`, read from TOML or YAML, e.g.
+//
+//   [[apps]]
+//   app_identifier = "net.whatsapp.WhatsApp"
+//   display_name = "WhatsApp"
+//
+//   [[events]]
+//   at = 1.5
+//   action = "add"
+//   id = "lunch"
+//   app_identifier = "net.whatsapp.WhatsApp"
+//   category = "Social"
+//   title = "Alice"
+//   message = "Lunch?"
+#[derive(Debug, Deserialize)]
+pub struct Scenario {
+    // split data source responses like a small MTU would
+    #[serde(default)]
+    pub fragment_size: Option<usize>,
+    #[serde(default)]
+    pub apps: Vec<App>,
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct App {
+    pub app_identifier: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Event {
+    // seconds after the daemon connected
+    pub at: f64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    Add(Notification),
+    Modify(Change),
+    Remove { id: String },
+    Disconnect,
+    Reconnect,
+    // the next control point write fails with this ATT error, e.g. 0xA2
+    Fail { att_error: u8 },
+}
+
+// A notification as written in a scenario, `id` names it for later events
+#[derive(Debug, Deserialize)]
+pub struct Notification {
+    pub id: String,
+    pub app_identifier: String,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub subtitle: String,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub positive_action_label: String,
+    #[serde(default)]
+    pub negative_action_label: String,
+    #[serde(default)]
+    pub silent: bool,
+    #[serde(default)]
+    pub important: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Change {
+    pub id: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub subtitle: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl Notification {
+    fn to_fake(&self) -> FakeNotification {
+        let mut notification =
+            FakeNotification::new(&self.app_identifier, &self.title, &self.message);
+        notification.category_id = self
+            .category
+            .as_deref()
+            .and_then(|category| CategoryID::from_str(category).ok())
+            .unwrap_or(CategoryID::Other);
+        notification.subtitle = self.subtitle.clone();
+        notification.positive_action_label = self.positive_action_label.clone();
+        notification.negative_action_label = self.negative_action_label.clone();
+        if self.silent {
+            notification.event_flags |= EventFlag::Silent as u8;
+        }
+        if self.important {
+            notification.event_flags |= EventFlag::Important as u8;
+        }
+        if !self.positive_action_label.is_empty() {
+            notification.event_flags |= EventFlag::PositiveAction as u8;
+        }
+        if !self.negative_action_label.is_empty() {
+            notification.event_flags |= EventFlag::NegativeAction as u8;
+        }
+        // the phone stamps notifications when they arrive
+        notification.date = Some(Local::now().naive_local());
+        notification
+    }
+}
+
+impl Change {
+    fn apply(&self, notification: &mut FakeNotification) {
+        if let Some(title) = &self.title {
+            notification.title = title.clone();
+        }
+        if let Some(subtitle) = &self.subtitle {
+            notification.subtitle = subtitle.clone();
+        }
+        if let Some(message) = &self.message {
+            notification.message = message.clone();
+        }
+    }
+}
+
+impl Scenario {
+    // The format follows the file extension
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let scenario: Scenario = Config::builder()
+            .add_source(config::File::from(path))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        scenario.validated()
+    }
+
+    // Events are played in time order, ids must be added before they are used
+    fn validated(mut self) -> Result<Self, String> {
+        self.events.sort_by(|a, b| a.at.total_cmp(&b.at));
+        let mut ids = HashSet::new();
+        for event in &self.events {
+            if !event.at.is_finite() || event.at < 0.0 {
+                return Err(format!("invalid time {}", event.at));
+            }
+            match &event.action {
+                Action::Add(notification) => {
+                    if let Some(category) = &notification.category {
+                        CategoryID::from_str(category)?;
+                    }
+                    ids.insert(notification.id.as_str());
+                }
+                Action::Modify(Change { id, .. }) | Action::Remove { id }
+                    if !ids.contains(id.as_str()) =>
+                {
+                    return Err(format!(
+                        "{} at {}s is not added before or already removed",
+                        id, event.at
+                    ));
+                }
+                Action::Remove { id } => {
+                    ids.remove(id.as_str());
+                }
+                _ => {}
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn prepare(&self, phone: &FakePhone) {
+        phone.set_fragment_size(self.fragment_size);
+        phone.set_current_time(Some(Local::now().naive_local()));
+        for app in &self.apps {
+            phone.set_display_name(&app.app_identifier, &app.display_name);
+        }
+    }
+
+    // Plays the events into `phone`, `speed` 2 plays twice as fast
+    pub async fn play(&self, phone: &FakePhone, speed: f64) {
+        let start = Instant::now();
+        let mut ids = HashMap::new();
+        for event in &self.events {
+            tokio::time::sleep_until(start + Duration::from_secs_f64(event.at / speed)).await;
+            info!("{:>7.1}s {:?}", event.at, event.action);
+            match &event.action {
+                Action::Add(notification) => {
+                    ids.insert(notification.id.clone(), phone.add(notification.to_fake()));
+                }
+                Action::Modify(change) => {
+                    if let Some(&notification_id) = ids.get(&change.id) {
+                        phone.modify(notification_id, |notification| change.apply(notification));
+                    }
+                }
+                Action::Remove { id } => {
+                    if let Some(notification_id) = ids.remove(id) {
+                        phone.remove(notification_id);
+                    }
+                }
+                Action::Disconnect => phone.disconnect(),
+                Action::Reconnect => phone.reconnect(),
+                Action::Fail { att_error } => {
+                    phone.fail_next_write(TransportError::Att(*att_error))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::notification_source::NotificationEvent;
+    use crate::ancs::transport::Transport;
+    use futures::StreamExt;
+    use std::io::Write;
+
+    fn load(contents: &str, extension: &str) -> Result<Scenario, String> {
+        let mut file = tempfile::Builder::new()
+            .suffix(extension)
+            .tempfile()
+            .unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        Scenario::load(file.path())
+    }
+
+    #[tokio::test]
+    async fn toml() {
+        let scenario = load(
+            r#"
+            fragment_size = 20
+
+            [[events]]
+            at = 2
+            action = "remove"
+            id = "call"
+
+            [[events]]
+            at = 0.5
+            action = "add"
+            id = "call"
+            app_identifier = "com.apple.mobilephone"
+            category = "IncomingCall"
+            title = "Bob"
+            negative_action_label = "Decline"
+
+            [[events]]
+            at = 3
+            action = "disconnect"
+            "#,
+            ".toml",
+        )
+        .unwrap();
+        assert_eq!(scenario.fragment_size, Some(20));
+
+        let phone = FakePhone::new();
+        let mut notification_source = phone.notification_source().await.unwrap();
+        scenario.play(&phone, 1000.0).await;
+        let added =
+            NotificationEvent::from_buffer(notification_source.next().await.unwrap()).unwrap();
+        assert_eq!(added.category_id, CategoryID::IncomingCall as u8);
+        assert!(EventFlag::NegativeAction.is_set(added.event_flags));
+        let removed =
+            NotificationEvent::from_buffer(notification_source.next().await.unwrap()).unwrap();
+        assert_eq!(removed.notification_id, added.notification_id);
+        // disconnected
+        assert_eq!(notification_source.next().await, None);
+    }
+
+    #[test]
+    fn yaml() {
+        let scenario = load(
+            "apps:\n  - app_identifier: net.whatsapp.WhatsApp\n    display_name: WhatsApp\n\
+             events:\n  - at: 1\n    action: add\n    id: lunch\n    app_identifier: net.whatsapp.WhatsApp\n    title: Alice\n\
+             \x20 - at: 2\n    action: modify\n    id: lunch\n    message: Lunch at 12?\n\
+             \x20 - at: 3\n    action: fail\n    att_error: 162\n",
+            ".yaml",
+        )
+        .unwrap();
+        assert_eq!(scenario.apps[0].display_name, "WhatsApp");
+        assert!(matches!(
+            scenario.events[2].action,
+            Action::Fail { att_error: 0xA2 }
+        ));
+    }
+
+    #[test]
+    fn invalid() {
+        let unknown_id = "[[events]]\nat = 1\naction = \"remove\"\nid = \"nope\"\n";
+        assert!(load(unknown_id, ".toml").is_err());
+        let unknown_category = "[[events]]\nat = 1\naction = \"add\"\nid = \"a\"\napp_identifier = \"a\"\ncategory = \"Spam\"\n";
+        assert!(load(unknown_category, ".toml").is_err());
+        let removed = "[[events]]\nat = 1\naction = \"add\"\nid = \"a\"\napp_identifier = \"a\"\n\
+                       [[events]]\nat = 2\naction = \"remove\"\nid = \"a\"\n\
+                       [[events]]\nat = 3\naction = \"modify\"\nid = \"a\"\ntitle = \"b\"\n";
+        assert!(load(removed, ".toml")
+            .unwrap_err()
+            .contains("already removed"));
+    }
+}
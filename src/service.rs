@@ -0,0 +1,327 @@
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::MatchRule;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::RequestNameReply;
+use dbus::Message;
+use dbus_crossroads::{Crossroads, MethodErr};
+use log::error;
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::ancs::control_point::{category_name, EventFlag};
+use crate::control::{self, Command};
+use crate::events::{Event, Notification};
+
+// The phone's notifications on the session bus for status bars, widgets and scripts, e.g.
+// `busctl --user call io.github.ancs.Desktop /io/github/ancs/Desktop io.github.ancs.Desktop1
+// ListNotifications`
+
+pub const SERVICE_NAME: &str = "io.github.ancs.Desktop";
+const SERVICE_PATH: &str = "/io/github/ancs/Desktop";
+const INTERFACE: &str = "io.github.ancs.Desktop1";
+
+// What the service answers from, kept up to date from the daemon's events
+#[derive(Default)]
+struct State {
+    connected: bool,
+    notifications: BTreeMap<u32, Notification>,
+}
+
+impl State {
+    // Applies an event and returns the signal announcing it
+    fn apply(&mut self, event: Event) -> Option<Message> {
+        match event {
+            Event::Notification(notification) => {
+                let uid = notification.notification_id;
+                let properties = properties(&notification);
+                let member = match self.notifications.insert(uid, notification) {
+                    Some(_) => "NotificationModified",
+                    None => "NotificationAdded",
+                };
+                Some(signal(member).append2(uid, properties))
+            }
+            Event::Removed {
+                notification_id: uid,
+            } => self
+                .notifications
+                .remove(&uid)
+                .map(|_| signal("NotificationRemoved").append1(uid)),
+            Event::Connection { connected } => {
+                self.connected = connected;
+                Some(signal("ConnectionChanged").append1(connected))
+            }
+            // the bus only carries notifications once their attributes are in
+            _ => None,
+        }
+    }
+}
+
+fn signal(member: &'static str) -> Message {
+    Message::signal(&SERVICE_PATH.into(), &INTERFACE.into(), &member.into())
+}
+
+// A notification as a dictionary, attributes the phone did not send are left out
+fn properties(notification: &Notification) -> PropMap {
+    let mut properties = PropMap::new();
+    let mut insert = |key: &str, value: Box<dyn RefArg>| {
+        properties.insert(key.to_string(), Variant(value));
+    };
+    insert("uid", Box::new(notification.notification_id));
+    insert(
+        "category",
+        Box::new(category_name(notification.category_id)),
+    );
+    insert(
+        "important",
+        Box::new(EventFlag::Important.is_set(notification.event_flags)),
+    );
+    insert(
+        "silent",
+        Box::new(EventFlag::Silent.is_set(notification.event_flags)),
+    );
+    insert(
+        "message_truncated",
+        Box::new(notification.message_truncated),
+    );
+    let strings = [
+        ("app_identifier", &notification.app_identifier),
+        ("app_name", &notification.app_name),
+        ("title", &notification.title),
+        ("subtitle", &notification.subtitle),
+        ("message", &notification.message),
+    ];
+    for (key, value) in strings {
+        if let Some(value) = value {
+            insert(key, Box::new(value.clone()));
+        }
+    }
+    if let Some(date) = notification.date {
+        insert("date", Box::new(date.timestamp()));
+    }
+    // actions are offered by the flags, the labels may not have been requested
+    let actions = [
+        (
+            "positive_action",
+            EventFlag::PositiveAction,
+            &notification.positive_action_label,
+        ),
+        (
+            "negative_action",
+            EventFlag::NegativeAction,
+            &notification.negative_action_label,
+        ),
+    ];
+    for (key, flag, label) in actions {
+        if flag.is_set(notification.event_flags) {
+            insert(key, Box::new(label.clone().unwrap_or_default()));
+        }
+    }
+    properties
+}
+
+struct Service {
+    state: Arc<Mutex<State>>,
+    control_tx: mpsc::Sender<control::Request>,
+}
+
+// Hands a command to the main loop like the control socket does, its errors become D-Bus errors
+async fn forward(
+    control_tx: Option<mpsc::Sender<control::Request>>,
+    command: Command,
+) -> Result<Value, MethodErr> {
+    let control_tx = control_tx.ok_or_else(|| MethodErr::failed("the service is gone"))?;
+    let (reply, response) = oneshot::channel();
+    control_tx
+        .send(control::Request { command, reply })
+        .await
+        .map_err(|_| MethodErr::failed("the daemon stopped"))?;
+    response
+        .await
+        .map_err(|_| MethodErr::failed("the daemon stopped"))?
+        .map_err(|e| MethodErr::failed(&e))
+}
+
+// Owns `SERVICE_NAME` on the session bus and serves it until the daemon exits
+pub async fn start(
+    control_tx: mpsc::Sender<control::Request>,
+    mut events_rx: mpsc::UnboundedReceiver<Event>,
+) -> Result<(), dbus::Error> {
+    let (resource, connection) = dbus_tokio::connection::new_session_sync()?;
+    tokio::spawn(async {
+        let e = resource.await;
+        error!("D-Bus service lost the session bus: {}", e);
+    });
+    // another daemon already serving the phone keeps the name
+    let reply = connection
+        .request_name(SERVICE_NAME, false, false, true)
+        .await?;
+    if reply != RequestNameReply::PrimaryOwner {
+        return Err(dbus::Error::new_failed(&format!(
+            "{} is already taken",
+            SERVICE_NAME
+        )));
+    }
+
+    let state = Arc::new(Mutex::new(State::default()));
+    let mut cr = Crossroads::new();
+    cr.set_async_support(Some((
+        connection.clone(),
+        Box::new(|future| {
+            tokio::spawn(future);
+        }),
+    )));
+    let interface = cr.register(INTERFACE, |b| {
+        b.signal::<(u32, PropMap), _>("NotificationAdded", ("uid", "notification"));
+        b.signal::<(u32, PropMap), _>("NotificationModified", ("uid", "notification"));
+        b.signal::<(u32,), _>("NotificationRemoved", ("uid",));
+        b.signal::<(bool,), _>("ConnectionChanged", ("connected",));
+        b.property::<bool, _>("Connected")
+            .get(|_, service: &mut Service| Ok(service.state.lock().unwrap().connected))
+            .emits_changed_false();
+        b.method(
+            "ListNotifications",
+            (),
+            ("notifications",),
+            |_, service: &mut Service, ()| {
+                let state = service.state.lock().unwrap();
+                Ok((state
+                    .notifications
+                    .values()
+                    .map(properties)
+                    .collect::<Vec<_>>(),))
+            },
+        );
+        b.method(
+            "GetNotification",
+            ("uid",),
+            ("notification",),
+            |_, service: &mut Service, (uid,): (u32,)| {
+                let state = service.state.lock().unwrap();
+                match state.notifications.get(&uid) {
+                    Some(notification) => Ok((properties(notification),)),
+                    None => Err(MethodErr::invalid_arg(&uid)),
+                }
+            },
+        );
+        b.method_with_cr_async(
+            "PerformAction",
+            ("uid", "action"),
+            (),
+            |mut ctx, cr, (uid, action): (u32, String)| {
+                let control_tx = cr
+                    .data_mut::<Service>(ctx.path())
+                    .map(|service| service.control_tx.clone());
+                async move {
+                    let result = match control::parse_action(&action) {
+                        Ok(action) => forward(control_tx, Command::Action(uid, action))
+                            .await
+                            .map(|_| ()),
+                        Err(e) => Err(MethodErr::invalid_arg(&e)),
+                    };
+                    ctx.reply(result)
+                }
+            },
+        );
+        b.method_with_cr_async(
+            "GetFullMessage",
+            ("uid",),
+            ("message",),
+            |mut ctx, cr, (uid,): (u32,)| {
+                let control_tx = cr
+                    .data_mut::<Service>(ctx.path())
+                    .map(|service| service.control_tx.clone());
+                async move {
+                    let result = forward(control_tx, Command::FullMessage(uid)).await;
+                    ctx.reply(
+                        result.map(|message| (message.as_str().unwrap_or_default().to_string(),)),
+                    )
+                }
+            },
+        );
+    });
+    cr.insert(
+        SERVICE_PATH,
+        &[interface],
+        Service {
+            state: state.clone(),
+            control_tx,
+        },
+    );
+    connection.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |message, connection| {
+            let _ = cr.handle_message(message, connection);
+            true
+        }),
+    );
+
+    tokio::spawn(async move {
+        while let Some(event) = events_rx.recv().await {
+            let signal = state.lock().unwrap().apply(event);
+            if let Some(signal) = signal {
+                let _ = connection.send(signal);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::control_point::CategoryID;
+
+    fn notification(title: &str) -> Notification {
+        Notification {
+            notification_id: 4,
+            app_identifier: Some("com.apple.mobilephone".to_string()),
+            app_name: None,
+            category_id: CategoryID::IncomingCall as u8,
+            event_flags: EventFlag::PositiveAction as u8 | EventFlag::NegativeAction as u8,
+            title: Some(title.to_string()),
+            subtitle: None,
+            message: None,
+            message_truncated: false,
+            date: None,
+            positive_action_label: Some("Answer".to_string()),
+            negative_action_label: None,
+        }
+    }
+
+    #[test]
+    fn signals() {
+        let mut state = State::default();
+        let added = state
+            .apply(Event::Notification(notification("Alice")))
+            .unwrap();
+        assert_eq!(added.member().unwrap().to_string(), "NotificationAdded");
+        let (uid, properties): (u32, PropMap) = added.read2().unwrap();
+        assert_eq!(uid, 4);
+        assert_eq!(properties["title"].0.as_str(), Some("Alice"));
+        assert_eq!(properties["category"].0.as_str(), Some("IncomingCall"));
+        assert_eq!(properties["positive_action"].0.as_str(), Some("Answer"));
+        // offered without a label
+        assert_eq!(properties["negative_action"].0.as_str(), Some(""));
+        assert!(!properties.contains_key("message"));
+
+        let modified = state
+            .apply(Event::Notification(notification("Alice (2)")))
+            .unwrap();
+        assert_eq!(
+            modified.member().unwrap().to_string(),
+            "NotificationModified"
+        );
+        let removed = state.apply(Event::Removed { notification_id: 4 }).unwrap();
+        assert_eq!(removed.read1::<u32>().unwrap(), 4);
+        // a notification the service never announced
+        assert!(state.apply(Event::Removed { notification_id: 5 }).is_none());
+        assert!(state.notifications.is_empty());
+
+        let connection = state.apply(Event::Connection { connected: true }).unwrap();
+        assert!(connection.read1::<bool>().unwrap());
+        assert!(state.connected);
+    }
+}
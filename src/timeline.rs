@@ -0,0 +1,165 @@
+use serde::Serialize;
+
+use std::io::{self, Write};
+
+use crate::ancs::control_point::{AncsCommandError, CommandID};
+use crate::ancs::data_source::{AppAttributes, NotificationAttributes};
+use crate::ancs::data_source::{Expected, Progress};
+use crate::ancs::date;
+use crate::ancs::notification_source::NotificationEvent;
+use crate::ancs::trace::{hex, Packet, Trace};
+use crate::events::Event;
+
+// Decodes a trace for reading, one line per packet. Data Source fragments are put together and
+// shown once the response to the last command is complete.
+pub fn write<W: Write>(out: &mut W, trace: &Trace) -> io::Result<()> {
+    writeln!(out, "trace started {}", trace.header.started)?;
+    let mut expected = None;
+    let mut response = Vec::new();
+    let mut fragments = 0;
+    for record in &trace.records {
+        let seconds = record.t as f64 / 1_000_000.0;
+        let line = match &record.packet {
+            Packet::Ns { data } => match NotificationEvent::from_buffer(data.clone()) {
+                Some(event) => match Event::from_source(&event) {
+                    Some(event) => format!("NS {}", json(&event)),
+                    None => format!(
+                        "NS unknown event id {} {}",
+                        event.event_id,
+                        hex::encode(data)
+                    ),
+                },
+                None => format!("NS malformed {}", hex::encode(data)),
+            },
+            Packet::Cp {
+                data,
+                att_error,
+                failed,
+                unsent,
+            } => {
+                expected = Expected::from_command(data);
+                response.clear();
+                fragments = 0;
+                let mut line = format!("CP {}", command(data));
+                if let Some(code) = att_error {
+                    match AncsCommandError::from_att_code(*code) {
+                        Some(error) => line += &format!(", rejected: {}", error),
+                        None => line += &format!(", ATT error 0x{:02X}", code),
+                    }
+                }
+                if let Some(failed) = failed {
+                    let what = if *unsent { "not sent" } else { "failed" };
+                    line += &format!(", {}: {}", what, failed);
+                }
+                line
+            }
+            Packet::Ds { data } => {
+                response.extend(data);
+                fragments += 1;
+                match expected
+                    .as_ref()
+                    .map(|expected| expected.progress(&response))
+                {
+                    Some(Progress::Complete) => {
+                        let buffer = std::mem::take(&mut response);
+                        let decoded = match expected.take() {
+                            Some(Expected::Notification { .. }) => {
+                                json(&NotificationAttributes::from_buffer(buffer))
+                            }
+                            _ => json(&AppAttributes::from_buffer(buffer)),
+                        };
+                        format!("DS {} ({} fragments)", decoded, fragments)
+                    }
+                    Some(Progress::Incomplete) => continue,
+                    _ => {
+                        let buffer = std::mem::take(&mut response);
+                        format!("DS unexpected {}", hex::encode(&buffer))
+                    }
+                }
+            }
+            Packet::Time { data } => match date::parse_current_time(data) {
+                Some(now) => format!("current time {}", now),
+                None => format!("current time malformed {}", hex::encode(data)),
+            },
+            Packet::Connected => "connected".to_string(),
+            Packet::Disconnected => "disconnected".to_string(),
+        };
+        writeln!(out, "{:>12.6} {}", seconds, line)?;
+    }
+    Ok(())
+}
+
+fn json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+fn command(data: &[u8]) -> String {
+    let name = match data.first() {
+        Some(&id) if id == CommandID::GetNotificationAttributes as u8 => {
+            "GetNotificationAttributes"
+        }
+        Some(&id) if id == CommandID::GetAppAttributes as u8 => "GetAppAttributes",
+        Some(&id) if id == CommandID::PerformNotificationAction as u8 => {
+            "PerformNotificationAction"
+        }
+        _ => "unknown command",
+    };
+    format!("{} {}", name, hex::encode(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancs::trace::{Header, Record, VERSION};
+    use crate::ancs::transport::TransportError;
+    use chrono::Local;
+
+    #[test]
+    fn fragmented_response() {
+        let packets = vec![
+            Packet::Ns {
+                data: vec![0, 0, 4, 1, 7, 0, 0, 0],
+            },
+            Packet::cp(vec![0, 7, 0, 0, 0, 1, 0xFF, 0xFF], &Ok(())),
+            Packet::Ds {
+                data: vec![0, 7, 0, 0, 0, 1, 5],
+            },
+            Packet::Ds {
+                data: b"\0Alice".to_vec(),
+            },
+            Packet::cp(
+                vec![0, 8, 0, 0, 0, 1, 0xFF, 0xFF],
+                &Err(TransportError::Att(0xA2)),
+            ),
+        ];
+        let trace = Trace {
+            header: Header {
+                version: VERSION,
+                started: Local::now(),
+                link: Default::default(),
+            },
+            records: packets
+                .into_iter()
+                .enumerate()
+                .map(|(t, packet)| Record {
+                    t: t as u64 * 1000,
+                    packet,
+                })
+                .collect(),
+        };
+        let mut out = Vec::new();
+        write(&mut out, &trace).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().skip(1).map(str::trim).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].ends_with(
+            r#"NS {"event":"added","notification_id":7,"category":"Social","category_count":1,"flags":[]}"#
+        ));
+        assert!(lines[1].starts_with("0.001000 CP GetNotificationAttributes"));
+        assert!(lines[2].starts_with(
+            r#"0.003000 DS {"notification_id":7,"app_identifier":null,"title":"Alice""#
+        ));
+        assert!(lines[2].ends_with("(2 fragments)"));
+        assert!(lines[3].ends_with("rejected: invalid parameter (0xA2)"));
+    }
+}